@@ -0,0 +1,80 @@
+use std::convert::TryInto;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::Error;
+
+/// A single cached response, along with the time at which it was fetched
+#[derive(Debug, Eq, PartialEq)]
+pub struct CacheEntry {
+    pub fetched: SystemTime,
+    pub data: Vec<u8>,
+}
+
+impl CacheEntry {
+    pub fn age(&self) -> Duration {
+        SystemTime::now().duration_since(self.fetched)
+            .unwrap_or_else(|_| Duration::from_secs(0))
+    }
+}
+
+/// On-disk cache of raw responses, keyed by URL.  Each value is the fetch
+/// time (as big-endian seconds since the epoch) followed by the raw bytes.
+pub struct Cache {
+    db: sled::Tree,
+    max_age: Duration,
+}
+
+impl Cache {
+    /// Opens the cache, treating entries older than `max_age` as stale
+    pub fn new(root: &sled::Db, max_age: Duration) -> Result<Cache, Error> {
+        let db = root.open_tree("cache")?;
+        Ok(Self { db, max_age })
+    }
+
+    pub fn get(&self, url: &url::Url) -> Result<Option<CacheEntry>, Error> {
+        Ok(self.db.get(url.as_str())?.and_then(|v| {
+            if v.len() < 8 {
+                return None;
+            }
+            let secs = u64::from_be_bytes(v[..8].try_into().unwrap());
+            Some(CacheEntry {
+                fetched: UNIX_EPOCH + Duration::from_secs(secs),
+                data: v[8..].to_vec(),
+            })
+        }))
+    }
+
+    /// Returns the cached entry only if it's younger than the max age
+    pub fn get_fresh(&self, url: &url::Url)
+        -> Result<Option<CacheEntry>, Error>
+    {
+        Ok(self.get(url)?.filter(|e| e.age() < self.max_age))
+    }
+
+    pub fn insert(&self, url: &url::Url, data: &[u8]) -> Result<(), Error> {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut v = secs.to_be_bytes().to_vec();
+        v.extend_from_slice(data);
+        self.db.insert(url.as_str(), v)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_cache() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let url = url::Url::parse("gemini://example.com/").unwrap();
+
+    let cache = Cache::new(&db, Duration::from_secs(3600)).unwrap();
+    assert_eq!(cache.get(&url).unwrap(), None);
+    cache.insert(&url, b"20 text/gemini\r\n# hi").unwrap();
+    assert_eq!(cache.get_fresh(&url).unwrap().unwrap().data,
+               b"20 text/gemini\r\n# hi");
+
+    // With a max age of zero, the entry is still present but never fresh
+    let cache = Cache::new(&db, Duration::from_secs(0)).unwrap();
+    assert!(cache.get(&url).unwrap().is_some());
+    assert_eq!(cache.get_fresh(&url).unwrap(), None);
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::Error;
+
+/// Something which turns a hostname and port into socket addresses
+pub trait Resolve: Send + Sync {
+    fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, Error>;
+}
+
+/// How long to wait for the system's resolver before giving up
+pub const RESOLVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Resolves names with the system's resolver, which can block for a long
+/// time on a broken network, so it's given a time limit
+pub struct SystemResolver {
+    pub timeout: Duration,
+}
+
+impl Default for SystemResolver {
+    fn default() -> Self {
+        SystemResolver { timeout: RESOLVE_TIMEOUT }
+    }
+}
+
+/// Runs a function on another thread, returning None if it doesn't finish
+/// within the timeout.  The thread is left to finish in the background.
+fn with_timeout<T, F>(timeout: Duration, f: F) -> Option<T>
+    where T: Send + 'static, F: FnOnce() -> T + Send + 'static
+{
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(f());
+    });
+    rx.recv_timeout(timeout).ok()
+}
+
+impl Resolve for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, Error> {
+        let target = (host.to_owned(), port);
+        let addrs: Vec<_> = with_timeout(self.timeout,
+                                         move || target.to_socket_addrs())
+            .ok_or_else(|| Error::DnsTimeout(host.to_owned()))?
+            .map_err(|_| Error::DnsFailure(host.to_owned()))?
+            .collect();
+        if addrs.is_empty() {
+            return Err(Error::DnsFailure(host.to_owned()));
+        }
+        Ok(addrs)
+    }
+}
+
+/// Resolved addresses and when they were resolved, keyed by host and port
+type Entries = HashMap<(String, u16), (Instant, Vec<SocketAddr>)>;
+
+/// In-memory cache of resolved addresses, so that repeated requests to the
+/// same host don't each wait on a lookup.  Entries expire after a TTL, and
+/// the oldest entry is evicted when the cache is full.
+pub struct DnsCache {
+    resolver: Box<dyn Resolve>,
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+impl DnsCache {
+    /// Builds a cache in front of the system's resolver
+    pub fn new(ttl: Duration, capacity: usize) -> DnsCache {
+        Self::with_resolver(Box::new(SystemResolver::default()), ttl,
+                            capacity)
+    }
+
+    pub fn with_resolver(resolver: Box<dyn Resolve>, ttl: Duration,
+                         capacity: usize) -> DnsCache
+    {
+        let entries = Mutex::new(HashMap::new());
+        DnsCache { resolver, ttl, capacity, entries }
+    }
+
+    /// Returns the addresses for a host, resolving it if there's no fresh
+    /// entry in the cache
+    pub fn lookup(&self, host: &str, port: u16)
+        -> Result<Vec<SocketAddr>, Error>
+    {
+        let key = (host.to_owned(), port);
+        let mut entries = self.entries.lock().unwrap();
+        if let Some((t, addrs)) = entries.get(&key) {
+            if t.elapsed() < self.ttl {
+                return Ok(addrs.clone());
+            }
+        }
+
+        let addrs = self.resolver.resolve(host, port)?;
+        if self.capacity == 0 {
+            return Ok(addrs);
+        }
+        entries.retain(|_, (t, _)| t.elapsed() < self.ttl);
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            let oldest = entries.iter()
+                .min_by_key(|(_, (t, _))| *t)
+                .map(|(k, _)| k.clone());
+            if let Some(k) = oldest {
+                entries.remove(&k);
+            }
+        }
+        entries.insert(key, (Instant::now(), addrs.clone()));
+        Ok(addrs)
+    }
+
+    /// Drops the cached entry for a host, e.g. after failing to connect to
+    /// its addresses, so that the next lookup resolves it again
+    pub fn invalidate(&self, host: &str, port: u16) {
+        self.entries.lock().unwrap().remove(&(host.to_owned(), port));
+    }
+}
+
+#[test]
+fn test_dns_cache() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Counter(Arc<AtomicUsize>);
+    impl Resolve for Counter {
+        fn resolve(&self, _host: &str, port: u16)
+            -> Result<Vec<SocketAddr>, Error>
+        {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(vec![SocketAddr::from(([127, 0, 0, 1], port))])
+        }
+    }
+    let count = Arc::new(AtomicUsize::new(0));
+    let calls = || count.load(Ordering::SeqCst);
+
+    let dns = DnsCache::with_resolver(Box::new(Counter(count.clone())),
+                                      Duration::from_secs(60), 2);
+    let addrs = dns.lookup("example.com", 1965).unwrap();
+    assert_eq!(addrs, vec![SocketAddr::from(([127, 0, 0, 1], 1965))]);
+    assert_eq!(dns.lookup("example.com", 1965).unwrap(), addrs);
+    assert_eq!(calls(), 1);
+
+    // Invalidated entries are resolved again
+    dns.invalidate("example.com", 1965);
+    dns.lookup("example.com", 1965).unwrap();
+    assert_eq!(calls(), 2);
+
+    // When the cache is full, the oldest entry is evicted
+    dns.lookup("a.com", 1965).unwrap();
+    dns.lookup("b.com", 1965).unwrap();
+    assert_eq!(calls(), 4);
+    dns.lookup("b.com", 1965).unwrap();
+    assert_eq!(calls(), 4);
+    dns.lookup("example.com", 1965).unwrap();
+    assert_eq!(calls(), 5);
+
+    // With a zero TTL, nothing is reused
+    let dns = DnsCache::with_resolver(Box::new(Counter(count.clone())),
+                                      Duration::from_secs(0), 2);
+    dns.lookup("example.com", 1965).unwrap();
+    dns.lookup("example.com", 1965).unwrap();
+    assert_eq!(calls(), 7);
+}
+
+#[test]
+fn test_with_timeout() {
+    let short = Duration::from_millis(50);
+    assert_eq!(with_timeout(short, || 1), Some(1));
+    let slow = move || std::thread::sleep(short * 4);
+    assert_eq!(with_timeout(short, slow), None);
+}
@@ -1,4 +1,284 @@
+use std::fmt;
+
 use crate::protocol::Line;
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct Document<'a>(pub Vec<Line<'a>>);
+
+/// Counts of each kind of line in a document
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct DocumentStats {
+    pub headings: usize,
+    pub links: usize,
+    pub list_items: usize,
+    pub quotes: usize,
+    pub pre_blocks: usize,
+    pub text_lines: usize,
+}
+
+impl<'a> Document<'a> {
+    pub fn new(lines: Vec<Line<'a>>) -> Self {
+        Document(lines)
+    }
+
+    /// Iterates over the links in the document, in order, as `(url, name)`
+    /// pairs (where bare links have no name)
+    pub fn links(&self) -> impl Iterator<Item=(&'a str, Option<&'a str>)> + '_
+    {
+        self.0.iter().filter_map(|line| match line {
+            Line::BareLink(url) => Some((*url, None)),
+            Line::NamedLink { url, name } => Some((*url, Some(*name))),
+            _ => None,
+        })
+    }
+
+    /// Counts lines by type, in a single pass over the document
+    pub fn stats(&self) -> DocumentStats {
+        use Line::*;
+        let mut s = DocumentStats::default();
+        for line in self.0.iter() {
+            match line {
+                H1(_) | H2(_) | H3(_) => s.headings += 1,
+                BareLink(_) | NamedLink { .. } => s.links += 1,
+                List(_) => s.list_items += 1,
+                Quote(_) => s.quotes += 1,
+                Pre { .. } => s.pre_blocks += 1,
+                Text(_) => s.text_lines += 1,
+            }
+        }
+        s
+    }
+}
+
+/// Writes the document as text/gemini, with each line terminated by `\n`
+impl fmt::Display for Document<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Line::*;
+        for line in self.0.iter() {
+            match line {
+                Text(t) => writeln!(f, "{}", t)?,
+                BareLink(url) => writeln!(f, "=> {}", url)?,
+                NamedLink { url, name } => writeln!(f, "=> {} {}", url, name)?,
+                Pre { alt, text } => writeln!(f, "```{}\n{}\n```",
+                                              alt.unwrap_or(""), text)?,
+                H1(t) => writeln!(f, "# {}", t)?,
+                H2(t) => writeln!(f, "## {}", t)?,
+                H3(t) => writeln!(f, "### {}", t)?,
+                List(t) => writeln!(f, "* {}", t)?,
+                Quote(t) => writeln!(f, "> {}", t)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Owned equivalent of a [`Line`], which can outlive the text that it was
+/// parsed from.  This is what [`DocumentBuilder`] stores.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum OwnedLine {
+    Text(String),
+    BareLink(String),
+    NamedLink { url: String, name: String },
+    Pre { alt: Option<String>, text: String },
+    H1(String),
+    H2(String),
+    H3(String),
+    List(String),
+    Quote(String),
+}
+
+impl OwnedLine {
+    /// Borrows the line as a [`Line`]
+    pub fn as_line(&self) -> Line<'_> {
+        use OwnedLine::*;
+        match self {
+            Text(t) => Line::Text(t),
+            BareLink(url) => Line::BareLink(url),
+            NamedLink { url, name } => Line::NamedLink { url, name },
+            Pre { alt, text } => Line::Pre { alt: alt.as_deref(), text },
+            H1(t) => Line::H1(t),
+            H2(t) => Line::H2(t),
+            H3(t) => Line::H3(t),
+            List(t) => Line::List(t),
+            Quote(t) => Line::Quote(t),
+        }
+    }
+}
+
+impl From<Line<'_>> for OwnedLine {
+    fn from(line: Line<'_>) -> Self {
+        use Line::*;
+        match line {
+            Text(t) => OwnedLine::Text(t.to_owned()),
+            BareLink(url) => OwnedLine::BareLink(url.to_owned()),
+            NamedLink { url, name } => OwnedLine::NamedLink {
+                url: url.to_owned(), name: name.to_owned() },
+            Pre { alt, text } => OwnedLine::Pre {
+                alt: alt.map(str::to_owned), text: text.to_owned() },
+            H1(t) => OwnedLine::H1(t.to_owned()),
+            H2(t) => OwnedLine::H2(t.to_owned()),
+            H3(t) => OwnedLine::H3(t.to_owned()),
+            List(t) => OwnedLine::List(t.to_owned()),
+            Quote(t) => OwnedLine::Quote(t.to_owned()),
+        }
+    }
+}
+
+/// Builds a document from owned strings, for generating gemtext without
+/// needing some other buffer for its lines to borrow from.  The builder
+/// owns the text; call [`DocumentBuilder::document`] (as many times as
+/// needed) to borrow it as a [`Document`].
+///
+/// A finished builder is the owned form of a document: it holds no
+/// borrows, so it can be stored, cloned, and compared, and its lines are
+/// available as [`OwnedLine`]s.  Rendering and serializing work on the
+/// borrowed [`Document`], so that they're shared with parsed pages, and
+/// borrowing one from the builder only copies pointers.
+///
+/// A parsed document can also be copied into a builder (with `From`), to
+/// keep it after the text it borrows from is gone.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DocumentBuilder(Vec<OwnedLine>);
+
+impl DocumentBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(mut self, line: OwnedLine) -> Self {
+        self.0.push(line);
+        self
+    }
+
+    pub fn text<S: Into<String>>(self, s: S) -> Self {
+        self.push(OwnedLine::Text(s.into()))
+    }
+    pub fn h1<S: Into<String>>(self, s: S) -> Self {
+        self.push(OwnedLine::H1(s.into()))
+    }
+    pub fn h2<S: Into<String>>(self, s: S) -> Self {
+        self.push(OwnedLine::H2(s.into()))
+    }
+    pub fn h3<S: Into<String>>(self, s: S) -> Self {
+        self.push(OwnedLine::H3(s.into()))
+    }
+    pub fn link<S: Into<String>, T: Into<String>>(self, url: S, name: T)
+        -> Self
+    {
+        self.push(OwnedLine::NamedLink { url: url.into(), name: name.into() })
+    }
+    pub fn bare_link<S: Into<String>>(self, url: S) -> Self {
+        self.push(OwnedLine::BareLink(url.into()))
+    }
+    pub fn quote<S: Into<String>>(self, s: S) -> Self {
+        self.push(OwnedLine::Quote(s.into()))
+    }
+    pub fn list<S: Into<String>>(self, s: S) -> Self {
+        self.push(OwnedLine::List(s.into()))
+    }
+    pub fn pre<S: Into<String>>(self, alt: Option<&str>, text: S) -> Self {
+        self.push(OwnedLine::Pre { alt: alt.map(str::to_owned),
+                                   text: text.into() })
+    }
+
+    /// Borrows the lines built so far as a document
+    pub fn document(&self) -> Document<'_> {
+        Document(self.0.iter().map(OwnedLine::as_line).collect())
+    }
+
+    pub fn lines(&self) -> &[OwnedLine] {
+        &self.0
+    }
+}
+
+/// Copies a parsed document into an owned builder
+impl From<&Document<'_>> for DocumentBuilder {
+    fn from(doc: &Document<'_>) -> Self {
+        DocumentBuilder(doc.0.iter().map(|line| OwnedLine::from(*line))
+                                    .collect())
+    }
+}
+
+#[test]
+fn test_builder() {
+    let b = DocumentBuilder::new()
+        .h1("Title")
+        .text(format!("{} items", 2))
+        .list("one")
+        .list("two")
+        .link("gemini://example.com", "Example")
+        .bare_link("/foo")
+        .quote("quoted")
+        .pre(Some("py"), "print(1)\nprint(2)");
+    let doc = b.document();
+    assert_eq!(doc.0[1], Line::Text("2 items"));
+    assert_eq!(b.document(), doc);
+
+    let text = doc.to_string();
+    assert_eq!(text, "# Title\n2 items\n* one\n* two\n\
+                      => gemini://example.com Example\n=> /foo\n\
+                      > quoted\n```py\nprint(1)\nprint(2)\n```\n");
+    let (_, parsed) = crate::parser::parse_text_gemini(&text).unwrap();
+    assert_eq!(parsed, doc);
+}
+
+#[test]
+fn test_owned() {
+    let text = String::from("# Title\n=> a.gmi A link\n```py\ncode\n```\n");
+    let owned = DocumentBuilder::from(
+        &crate::parser::parse_document(&text).unwrap());
+    drop(text);
+
+    assert_eq!(owned.document(), Document::new(vec![
+        Line::H1("Title"),
+        Line::NamedLink { url: "a.gmi", name: "A link" },
+        Line::Pre { alt: Some("py"), text: "code" },
+    ]));
+    assert_eq!(owned.lines()[1], OwnedLine::NamedLink {
+        url: "a.gmi".to_owned(), name: "A link".to_owned() });
+    assert_eq!(OwnedLine::from(Line::Quote("q")).as_line(), Line::Quote("q"));
+}
+
+#[test]
+fn test_stats() {
+    let doc = Document::new(vec![
+        Line::H1("title"),
+        Line::Text("hello"),
+        Line::Text(""),
+        Line::BareLink("gemini://example.com"),
+        Line::NamedLink { url: "/foo", name: "foo" },
+        Line::H2("section"),
+        Line::List("one"),
+        Line::List("two"),
+        Line::List("three"),
+        Line::Quote("quoted"),
+        Line::Pre { alt: None, text: "code" },
+        Line::H3("subsection"),
+    ]);
+    assert_eq!(doc.stats(), DocumentStats {
+        headings: 3,
+        links: 2,
+        list_items: 3,
+        quotes: 1,
+        pre_blocks: 1,
+        text_lines: 2,
+    });
+}
+
+#[test]
+fn test_links() {
+    let doc = Document::new(vec![
+        Line::H1("title"),
+        Line::BareLink("gemini://example.com"),
+        Line::Text("=> not a link"),
+        Line::NamedLink { url: "/foo", name: "foo" },
+        Line::Pre { alt: None, text: "=> /pre" },
+        Line::List("item"),
+        Line::BareLink("bar.gmi"),
+    ]);
+    let links: Vec<_> = doc.links().collect();
+    assert_eq!(links, vec![("gemini://example.com", None),
+                           ("/foo", Some("foo")),
+                           ("bar.gmi", None)]);
+    assert_eq!(Document::new(vec![]).links().count(), 0);
+}
@@ -5,21 +5,54 @@ pub enum Error {
     #[error("invalid status code `{0}`")]
     InvalidStatusCode(u32),
 
-    #[error("parsing failed")]
-    ParseError,
+    #[error("parsing failed at line {line}: {detail}")]
+    ParseError { line: usize, detail: String },
+
+    #[error("malformed response header `{0}`")]
+    MalformedHeader(String),
+
+    #[error("no header terminator within the first {0} bytes")]
+    MissingHeaderTerminator(usize),
 
     #[error("too many redirects")]
     TooManyRedirects,
 
+    #[error("redirect loop at `{0}`")]
+    RedirectLoop(String),
+
     #[error("failed to write to db `{0}`")]
     DBWriteError(String),
 
     #[error("invalid URL scheme `{0}`")]
     InvalidURLScheme(String),
 
+    #[error("control character in request URL `{0}`")]
+    InvalidRequestURL(String),
+
     #[error("no hostname in `{0}`")]
     NoHostname(String),
 
+    #[error("invalid hostname `{0}`")]
+    InvalidHostname(String),
+
+    #[error("could not resolve `{0}`")]
+    DnsFailure(String),
+
+    #[error("timed out resolving `{0}`")]
+    DnsTimeout(String),
+
+    #[error("connection refused by `{0}`")]
+    ConnectionRefused(String),
+
+    #[error("network unreachable for `{0}`")]
+    NetworkUnreachable(String),
+
+    #[error("invalid identity: {0}")]
+    InvalidIdentity(String),
+
+    #[error("no identity named `{0}`")]
+    UnknownIdentity(String),
+
     #[error("unknown metatype `{0}`")]
     UnknownMeta(String),
 
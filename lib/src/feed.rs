@@ -0,0 +1,77 @@
+use crate::document::Document;
+use crate::protocol::Line;
+
+/// An entry in a Gemini subscription feed, i.e. a link whose name begins
+/// with an ISO 8601 date (`=> url 2023-01-02 - Title`)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FeedEntry<'a> {
+    pub date: &'a str, // YYYY-MM-DD
+    pub url: &'a str,
+    pub title: &'a str,
+}
+
+/// Extracts feed entries from a document, following the Gemini subscription
+/// convention.  Links whose names don't start with a valid date are skipped.
+pub fn parse_feed<'a>(doc: &Document<'a>) -> Vec<FeedEntry<'a>> {
+    doc.0.iter()
+        .filter_map(|line| match *line {
+            Line::NamedLink { url, name } => parse_entry(url, name),
+            _ => None,
+        })
+        .collect()
+}
+
+fn parse_entry<'a>(url: &'a str, name: &'a str) -> Option<FeedEntry<'a>> {
+    let date = name.get(..10).filter(|d| is_date(d))?;
+    let rest = &name[10..];
+
+    // The date must be a whole word, optionally followed by a separator
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let rest = rest.trim_start();
+    let title = rest.strip_prefix('-')
+        .or_else(|| rest.strip_prefix(':'))
+        .unwrap_or(rest)
+        .trim();
+    Some(FeedEntry { date, url, title })
+}
+
+/// Checks that a string is a plausible YYYY-MM-DD date
+fn is_date(s: &str) -> bool {
+    let b = s.as_bytes();
+    let num = |r: std::ops::Range<usize>| {
+        b[r.clone()].iter().all(u8::is_ascii_digit)
+            .then(|| s[r].parse::<u32>().unwrap())
+    };
+    if b.len() != 10 || b[4] != b'-' || b[7] != b'-' {
+        return false;
+    }
+    match (num(0..4), num(5..7), num(8..10)) {
+        (Some(_), Some(m), Some(d)) => (1..=12).contains(&m) &&
+                                       (1..=31).contains(&d),
+        _ => false,
+    }
+}
+
+#[test]
+fn test_parse_feed() {
+    let doc = Document::new(vec![
+        Line::H1("My gemlog"),
+        Line::NamedLink { url: "post1.gmi", name: "2023-01-02 - First post" },
+        Line::NamedLink { url: "post2.gmi", name: "2023-02-10 Second post" },
+        Line::NamedLink { url: "post3.gmi", name: "2023-03-01" },
+        Line::NamedLink { url: "about.gmi", name: "About me" },
+        Line::NamedLink { url: "bad.gmi", name: "2023-13-01 Bad month" },
+        Line::NamedLink { url: "bad.gmi", name: "2023-01-0100 Too long" },
+        Line::BareLink("2023-01-01"),
+        Line::Text("2023-01-01 not a link"),
+    ]);
+    assert_eq!(parse_feed(&doc), vec![
+        FeedEntry { date: "2023-01-02", url: "post1.gmi",
+                    title: "First post" },
+        FeedEntry { date: "2023-02-10", url: "post2.gmi",
+                    title: "Second post" },
+        FeedEntry { date: "2023-03-01", url: "post3.gmi", title: "" },
+    ]);
+}
@@ -1,29 +1,171 @@
+// ouroboros generates drop() calls on types without Drop impls
+#![allow(clippy::drop_non_drop)]
+
+use std::borrow::Cow;
 use std::io::{Read, Write};
 use std::sync::{Arc};
+use std::time::{Duration, Instant};
 use std::net::TcpStream;
 
+use rustls::Session;
+
 use crate::Error;
-use crate::parser::{parse_response, parse_text_gemini};
-use crate::protocol::{Line, Status, Response};
+use crate::cache::Cache;
+use crate::dns::{DnsCache, Resolve, SystemResolver};
+use crate::markdown::parse_markdown;
+use crate::parser::{parse_document, parse_response, MAX_HEADER_LEN};
+use crate::protocol::{mime_type, Line, Status, Response};
 use crate::document::Document;
 
+/// Resolves a link target against the URL of the document containing it.
+/// Absolute targets are returned as-is; anything else is joined to the base
+/// following RFC 3986.
+pub fn resolve(base: &url::Url, target: &str) -> Result<url::Url, Error> {
+    match url::Url::parse(target) {
+        Err(url::ParseError::RelativeUrlWithoutBase) => Ok(base.join(target)?),
+        r => Ok(r?),
+    }
+}
+
+/// Characters which are percent-encoded in a query: everything but the
+/// unreserved characters from RFC 3986
+const QUERY_ENCODE: &percent_encoding::AsciiSet = &percent_encoding::CONTROLS
+    .add(b' ').add(b'!').add(b'"').add(b'#').add(b'$').add(b'%').add(b'&')
+    .add(b'\'').add(b'(').add(b')').add(b'*').add(b'+').add(b',').add(b'/')
+    .add(b':').add(b';').add(b'<').add(b'=').add(b'>').add(b'?').add(b'@')
+    .add(b'[').add(b'\\').add(b']').add(b'^').add(b'`').add(b'{').add(b'|')
+    .add(b'}');
+
+/// Builds the URL which answers an input prompt from `url`: the same path,
+/// with the percent-encoded input as its query.  As the Gemini spec asks,
+/// the input replaces any query which the URL already had, rather than
+/// being added to it.  This is equivalent to following a relative link of
+/// the form `?input`.
+pub fn with_query(url: &url::Url, input: &str) -> url::Url {
+    let query = percent_encoding::utf8_percent_encode(input, QUERY_ENCODE)
+        .to_string();
+    let mut url = url.clone();
+    url.set_query(Some(&query));
+    url.set_fragment(None);
+    url
+}
+
+/// Resolves the target of a redirect from `url`.  A redirect which only
+/// changes the fragment (or nothing at all) would request the same resource
+/// again, so it's reported as a loop rather than followed.
+pub fn redirect(url: &url::Url, meta: &str) -> Result<url::Url, Error> {
+    let next = resolve(url, meta)?;
+    let mut a = url.clone();
+    let mut b = next.clone();
+    a.set_fragment(None);
+    b.set_fragment(None);
+    if a == b {
+        Err(Error::RedirectLoop(next.into_string()))
+    } else {
+        Ok(next)
+    }
+}
+
+/// Whether a redirect is permanent, so that anything storing the old URL
+/// should be updated to the new one, or only temporary
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RedirectKind {
+    Temporary,
+    Permanent,
+}
+
+/// A redirect response, with its target resolved against the requested URL
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Redirect {
+    pub kind: RedirectKind,
+    pub target: url::Url,
+}
+
+/// Returns the redirect asked for by a response to `url`, or None if the
+/// response isn't a redirect.  The target is checked as in [`redirect`].
+pub fn as_redirect(url: &url::Url, response: &Response)
+    -> Result<Option<Redirect>, Error>
+{
+    let kind = match response.status {
+        Status::RedirectTemporary => RedirectKind::Temporary,
+        Status::RedirectPermanent => RedirectKind::Permanent,
+        _ => return Ok(None),
+    };
+    Ok(Some(Redirect { kind, target: redirect(url, response.meta)? }))
+}
+
+/// Builds the request line for a URL.  The url crate strips or
+/// percent-encodes control characters when parsing, but we check again
+/// here, since an embedded CR-LF would let a link smuggle a second request.
+fn request_line(url: &url::Url) -> Result<String, Error> {
+    check_request_line(url.as_str())
+}
+
+/// Checks the text of a URL for control characters, then adds the CR-LF
+/// which ends the request line
+fn check_request_line(s: &str) -> Result<String, Error> {
+    if s.chars().any(|c| c.is_control()) {
+        Err(Error::InvalidRequestURL(s.escape_default().to_string()))
+    } else {
+        Ok(format!("{}\r\n", s))
+    }
+}
+
+/// Converts an internationalized hostname to its ASCII (punycode) form, as
+/// needed for DNS and TLS.  The `url` crate only does this for special
+/// schemes like `https`, and percent-encodes the hostname otherwise, so
+/// that's undone first.
+pub fn ascii_url(url: &url::Url) -> Result<url::Url, Error> {
+    let host = match url.host_str() {
+        Some(h) if !h.is_ascii() || h.contains('%') => h,
+        _ => return Ok(url.clone()),
+    };
+    let invalid = || Error::InvalidHostname(host.to_owned());
+    let decoded = percent_encoding::percent_decode_str(host).decode_utf8()
+        .map_err(|_| invalid())?;
+    let ascii = idna::domain_to_ascii(&decoded).map_err(|_| invalid())?;
+    let mut out = url.clone();
+    out.set_host(Some(&ascii))?;
+    Ok(out)
+}
+
 pub fn read(config: &Arc<rustls::ClientConfig>, url: &url::Url)
     -> Result<Vec<u8>, Error>
 {
-    if url.scheme() != "gemini" {
-        return Err(Error::InvalidURLScheme(url.scheme().to_owned()));
+    read_via(config, url, None)
+}
+
+/// Equivalent to [`read`], but looks up the host through the given
+/// [`DnsCache`].  If we can't connect to the cached addresses, they're
+/// dropped from the cache, so that the next request resolves them again.
+pub fn read_via(config: &Arc<rustls::ClientConfig>, url: &url::Url,
+                dns: Option<&DnsCache>) -> Result<Vec<u8>, Error>
+{
+    Ok(read_session(config, url, dns)?.0)
+}
+
+/// Protocol version and cipher suite negotiated for a TLS session
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TlsInfo {
+    pub version: rustls::ProtocolVersion,
+    pub suite: rustls::CipherSuite,
+}
+
+impl std::fmt::Display for TlsInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} with {:?}", self.version, self.suite)
     }
-    let hostname = url.host_str()
-        .ok_or_else(|| Error::NoHostname(url.as_str().to_owned()))?;
-    let dns_name = webpki::DNSNameRef::try_from_ascii_str(hostname)?;
-    let mut sess = rustls::ClientSession::new(config, dns_name);
+}
 
-    let port = url.port().unwrap_or(1965);
-    let mut sock = TcpStream::connect(format!("{}:{}", hostname, port))?;
+/// Equivalent to [`read_via`], also returning what was negotiated for the
+/// session (if the handshake got that far)
+pub fn read_session(config: &Arc<rustls::ClientConfig>, url: &url::Url,
+                    dns: Option<&DnsCache>)
+    -> Result<(Vec<u8>, Option<TlsInfo>), Error>
+{
+    let (mut sess, mut sock) = open(config, url, dns)?;
     let mut tls = rustls::Stream::new(&mut sess, &mut sock);
 
-    tls.write_all(format!("{}\r\n", url.as_str()).as_bytes())?;
-
     let mut plaintext = Vec::new();
     let rc = tls.read_to_end(&mut plaintext);
 
@@ -34,7 +176,152 @@ pub fn read(config: &Arc<rustls::ClientConfig>, url: &url::Url)
             return Err(err.into());
         }
     }
-    Ok(plaintext)
+    let info = sess.get_protocol_version()
+        .zip(sess.get_negotiated_ciphersuite())
+        .map(|(version, suite)| TlsInfo { version, suite: suite.suite });
+    Ok((plaintext, info))
+}
+
+/// Equivalent to [`read_via`], but only for a successful `text/*` response
+/// with a body of at most `limit` bytes, which is read by `deadline`.  The
+/// header is read first, so that other responses are dropped without
+/// reading their bodies.  Responses which don't qualify return `None`.
+pub fn read_text(config: &Arc<rustls::ClientConfig>, url: &url::Url,
+                 dns: Option<&DnsCache>, limit: usize, deadline: Instant)
+    -> Result<Option<Vec<u8>>, Error>
+{
+    let (mut sess, mut sock) = open(config, url, dns)?;
+    // The timeout is set through a second handle, since the TLS stream
+    // borrows the socket
+    let timer = sock.try_clone()?;
+    let mut tls = rustls::Stream::new(&mut sess, &mut sock);
+
+    let mut plaintext = Vec::new();
+    let mut buf = [0; 4096];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(std::io::Error::from(std::io::ErrorKind::TimedOut)
+                .into());
+        }
+        timer.set_read_timeout(Some(remaining))?;
+        let n = match tls.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            // See read_session for why this is a clean close
+            Err(err) if err.kind() ==
+                std::io::ErrorKind::ConnectionAborted => break,
+            Err(err) => return Err(err.into()),
+        };
+        plaintext.extend_from_slice(&buf[..n]);
+        if !text_so_far(&plaintext, limit) {
+            return Ok(None);
+        }
+    }
+    Ok(Some(plaintext).filter(|p| header_end(p).is_some()))
+}
+
+/// Returns the length of a response's header (including the `\r\n`), if
+/// it's all been read
+fn header_end(plaintext: &[u8]) -> Option<usize> {
+    let head = &plaintext[..plaintext.len().min(MAX_HEADER_LEN + 2)];
+    head.windows(2).position(|w| w == b"\r\n").map(|i| i + 2)
+}
+
+/// Checks whether a partly-read response could still be returned by
+/// [`read_text`]
+fn text_so_far(plaintext: &[u8], limit: usize) -> bool {
+    match header_end(plaintext) {
+        Some(end) => plaintext.len() - end <= limit &&
+            parse_response(&plaintext[..end])
+                .is_ok_and(|r| r.status == Status::Success &&
+                               r.mime_type().starts_with("text/")),
+        None => plaintext.len() < MAX_HEADER_LEN + 2,
+    }
+}
+
+/// Connects to a gemini URL's host and sends the request, returning the
+/// TLS session and socket for reading the response
+fn open(config: &Arc<rustls::ClientConfig>, url: &url::Url,
+        dns: Option<&DnsCache>)
+    -> Result<(rustls::ClientSession, TcpStream), Error>
+{
+    if url.scheme() != "gemini" {
+        return Err(Error::InvalidURLScheme(url.scheme().to_owned()));
+    }
+    // The server is sent the ASCII form of the URL, too
+    let url = &ascii_url(url)?;
+    let hostname = url.host_str()
+        .ok_or_else(|| Error::NoHostname(url.as_str().to_owned()))?;
+    let dns_name = webpki::DNSNameRef::try_from_ascii_str(hostname)?;
+    let mut sess = rustls::ClientSession::new(config, dns_name);
+
+    let request = request_line(url)?;
+    let port = url.port().unwrap_or(1965);
+    let mut sock = match dns {
+        Some(dns) => connect(&dns.lookup(hostname, port)?, hostname, port)
+            .inspect_err(|_| dns.invalidate(hostname, port))?,
+        None => connect(&SystemResolver::default().resolve(hostname, port)?,
+                        hostname, port)?,
+    };
+    rustls::Stream::new(&mut sess, &mut sock)
+        .write_all(request.as_bytes())?;
+    Ok((sess, sock))
+}
+
+/// How long to wait for each of a host's addresses to accept a connection
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connects to the first of a host's addresses which accepts, turning the
+/// common failures into errors which name the host (rather than a bare OS
+/// error)
+fn connect(addrs: &[std::net::SocketAddr], hostname: &str, port: u16)
+    -> Result<TcpStream, Error>
+{
+    use std::io::ErrorKind;
+    let mut result = Err(ErrorKind::NotFound.into());
+    for addr in addrs {
+        result = TcpStream::connect_timeout(addr, CONNECT_TIMEOUT);
+        if result.is_ok() {
+            break;
+        }
+    }
+    result.map_err(|err: std::io::Error| {
+        let host = format!("{}:{}", hostname, port);
+        match err.kind() {
+            ErrorKind::ConnectionRefused => Error::ConnectionRefused(host),
+            ErrorKind::NetworkUnreachable |
+            ErrorKind::HostUnreachable => Error::NetworkUnreachable(host),
+            _ => err.into(),
+        }
+    })
+}
+
+/// Reads a URL through the cache.  Fresh cache entries are returned without
+/// touching the network; otherwise, the URL is read (optionally resolving
+/// its host through a [`DnsCache`]) and successful `text/gemini` responses
+/// are stored.  If the network read fails, a stale cache entry is used as a
+/// fallback.  Session details are only returned for network reads.
+pub fn read_cached(config: &Arc<rustls::ClientConfig>, url: &url::Url,
+                   cache: &Cache, dns: Option<&DnsCache>)
+    -> Result<(Vec<u8>, Option<TlsInfo>), Error>
+{
+    if let Some(entry) = cache.get_fresh(url)? {
+        return Ok((entry.data, None));
+    }
+    match read_session(config, url, dns) {
+        Ok((plaintext, info)) => {
+            if let Ok(r) = parse_response(&plaintext) {
+                if r.status == Status::Success &&
+                   r.mime_type() == "text/gemini"
+                {
+                    cache.insert(url, &plaintext)?;
+                }
+            }
+            Ok((plaintext, info))
+        },
+        Err(err) => cache.get(url)?.map(|e| (e.data, None)).ok_or(err),
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -66,6 +353,11 @@ impl OwnedResponse {
     }
 }
 
+/// A complete response, which owns its raw bytes.  The header (status and
+/// meta) and raw body are available for every response, so that callers can
+/// handle input prompts, redirects, and failures themselves.  The parsed
+/// document is only present for successful `text/` responses; other MIME
+/// types and statuses leave it as `None`.
 #[self_referencing]
 pub struct OwnedDocument {
     response: OwnedResponse,
@@ -76,53 +368,340 @@ pub struct OwnedDocument {
 }
 
 impl OwnedDocument {
+    /// Parses a raw response (header and body)
+    pub fn parse(plaintext: Vec<u8>) -> Result<OwnedDocument, Error> {
+        to_document(plaintext)
+    }
     pub fn status(&self) -> Status {
         self.borrow_response().status()
     }
+    /// Returns the response's meta, whose meaning depends on the status
+    /// (e.g. the MIME type, the prompt for input, or a redirect target)
     pub fn meta(&self) -> &str {
         self.borrow_response().meta()
     }
+    /// Returns the raw response body, e.g. to save non-text content.
+    /// Servers only send a body with successful responses.
+    pub fn body(&self) -> &[u8] {
+        self.borrow_response()
+    }
+    /// Returns the parsed document, if this was a successful text response
+    pub fn doc(&self) -> Option<&Document<'_>> {
+        self.borrow_doc().as_ref()
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Fetches a URL without following redirects, returning whatever response
+/// the server sent
+pub fn fetch_once(config: &Arc<rustls::ClientConfig>, url: &url::Url)
+    -> Result<OwnedDocument, Error>
+{
+    to_document(read(config, url)?)
+}
+
+/// Fetches a URL, following redirects
 pub fn fetch(config: &Arc<rustls::ClientConfig>, url: url::Url)
     -> Result<OwnedDocument, Error>
 {
-    fetch_(config, url, 0)
+    fetch_(config, url, None, 0)
 }
 
-fn fetch_(config: &Arc<rustls::ClientConfig>, url: url::Url, depth: u8)
+/// Equivalent to [`fetch`], but reads through the given [`Cache`]
+pub fn fetch_cached(config: &Arc<rustls::ClientConfig>, url: url::Url,
+                    cache: &Cache) -> Result<OwnedDocument, Error>
+{
+    fetch_(config, url, Some(cache), 0)
+}
+
+fn fetch_(config: &Arc<rustls::ClientConfig>, url: url::Url,
+          cache: Option<&Cache>, depth: u8)
     -> Result<OwnedDocument, Error>
 {
     if depth >= 5 {
         return Err(Error::TooManyRedirects);
     }
 
-    let plaintext = read(config, &url)?;
-    let response = OwnedResponse::try_new(plaintext, |p| parse_response(p))?;
+    let plaintext = match cache {
+        Some(cache) => read_cached(config, &url, cache, None)?.0,
+        None => read(config, &url)?,
+    };
+    let doc = to_document(plaintext)?;
+    match doc.status() {
+        Status::RedirectTemporary | Status::RedirectPermanent => {
+            let next = redirect(&url, doc.meta())?;
+            fetch_(config, next, cache, depth + 1)
+        },
+        _ => Ok(doc),
+    }
+}
+
+/// Decodes a response body as UTF-8.  If the connection dropped partway
+/// through a multibyte character, the body is decoded up to the last
+/// complete character, and the returned flag is set.  Invalid bytes anywhere
+/// else are still an error.
+pub fn decode_body(body: &[u8]) -> Result<(&str, bool), Error> {
+    match std::str::from_utf8(body) {
+        Ok(s) => Ok((s, false)),
+        Err(e) if e.error_len().is_none() => {
+            let s = std::str::from_utf8(&body[..e.valid_up_to()]).unwrap();
+            Ok((s, true))
+        },
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Equivalent to [`decode_body`], but invalid bytes are replaced with
+/// U+FFFD rather than being an error.  The body is only copied if there
+/// were invalid bytes to replace.
+pub fn decode_body_lossy(body: &[u8]) -> (Cow<'_, str>, bool) {
+    match decode_body(body) {
+        Ok((s, truncated)) => (Cow::Borrowed(s), truncated),
+        Err(_) => (String::from_utf8_lossy(body), false),
+    }
+}
+
+fn to_document(plaintext: Vec<u8>) -> Result<OwnedDocument, Error> {
+    let response = OwnedResponse::try_new(plaintext, parse_response)?;
 
     if response.status() == Status::Success {
-        if response.meta().starts_with("text/gemini") {
+        let mime = mime_type(response.meta());
+        if mime == "text/gemini" {
+            OwnedDocument::try_new(response,
+                |body| {
+                    let (body, _) = decode_body(body)?;
+                    Ok(Some(parse_document(body)?))
+                })
+        } else if mime == "text/markdown" {
             OwnedDocument::try_new(response,
                 |body| {
-                    let body = std::str::from_utf8(body)?;
-                    let (_, doc) = parse_text_gemini(body)
-                        .map_err(|_| Error::ParseError)?;
-                    Ok(Some(doc))
+                    let (body, _) = decode_body(body)?;
+                    Ok(Some(parse_markdown(body)))
                 })
-        } else if response.meta().starts_with("text/") {
+        } else if mime.starts_with("text/") {
             OwnedDocument::try_new(response,
                 |body| {
                     // Read other text/ MIME types as a single preformatted line
-                    let body = std::str::from_utf8(body)?;
+                    let (body, _) = decode_body(body)?;
                     let text = Line::Pre { alt: None, text: body };
                     Ok(Some(Document(vec![text])))
                 })
         } else {
-            return Err(Error::UnknownMeta(response.meta().to_owned()));
+            // Non-text content isn't parsed, but the caller can still
+            // retrieve the raw bytes with body()
+            Ok(OwnedDocument::new(response, |_| None))
         }
     } else {
         Ok(OwnedDocument::new(response, |_| None))
     }
 }
+
+#[test]
+fn test_resolve() {
+    let base = url::Url::parse("gemini://example.com/a/b.gmi?x#y").unwrap();
+    let r = |t| resolve(&base, t).unwrap().into_string();
+
+    assert_eq!(r("gemini://other.com/c"), "gemini://other.com/c");
+    assert_eq!(r("https://other.com/c"), "https://other.com/c");
+    assert_eq!(r("c.gmi"), "gemini://example.com/a/c.gmi");
+    assert_eq!(r("../c.gmi"), "gemini://example.com/c.gmi");
+    assert_eq!(r("/c/d.gmi"), "gemini://example.com/c/d.gmi");
+    assert_eq!(r("//other.com/path"), "gemini://other.com/path");
+    assert_eq!(r("?foo"), "gemini://example.com/a/b.gmi?foo");
+    assert_eq!(r("#frag"), "gemini://example.com/a/b.gmi?x#frag");
+    assert_eq!(r(""), "gemini://example.com/a/b.gmi?x");
+
+    assert!(resolve(&base, "gemini://[invalid").is_err());
+}
+
+#[test]
+fn test_with_query() {
+    let url = url::Url::parse("gemini://example.com/a/search#x").unwrap();
+    let q = with_query(&url, "cats & dogs");
+    assert_eq!(q.as_str(), "gemini://example.com/a/search?cats%20%26%20dogs");
+
+    // The result matches following a query-only relative link
+    assert_eq!(resolve(&url, "?cats%20%26%20dogs").unwrap(), q);
+
+    // Any existing query is replaced entirely
+    let url = url::Url::parse("gemini://example.com/a?page=2&sort=new")
+        .unwrap();
+    assert_eq!(with_query(&url, "1+1=2?").as_str(),
+               "gemini://example.com/a?1%2B1%3D2%3F");
+    assert_eq!(with_query(&url, "").as_str(), "gemini://example.com/a?");
+    assert_eq!(with_query(&url, "café~_.-").as_str(),
+               "gemini://example.com/a?caf%C3%A9~_.-");
+}
+
+#[test]
+fn test_redirect() {
+    let url = url::Url::parse("gemini://example.com/a/b.gmi").unwrap();
+    assert_eq!(redirect(&url, "c.gmi").unwrap().as_str(),
+               "gemini://example.com/a/c.gmi");
+    assert_eq!(redirect(&url, "gemini://other.com/").unwrap().as_str(),
+               "gemini://other.com/");
+
+    // Redirects which don't change anything but the fragment are loops
+    for r in &["#section", "b.gmi#section", "gemini://example.com/a/b.gmi",
+               ""] {
+        match redirect(&url, r) {
+            Err(Error::RedirectLoop(_)) => (),
+            e => panic!("Expected loop for {}, got {:?}", r, e),
+        }
+    }
+    let url = url::Url::parse("gemini://example.com/a/b.gmi#x").unwrap();
+    assert!(redirect(&url, "#y").is_err());
+}
+
+#[test]
+fn test_as_redirect() {
+    let url = url::Url::parse("gemini://example.com/a/b.gmi").unwrap();
+    let r = |s| as_redirect(&url, &parse_response(s).unwrap());
+    assert_eq!(r(b"31 c.gmi\r\n").unwrap(), Some(Redirect {
+        kind: RedirectKind::Permanent,
+        target: url::Url::parse("gemini://example.com/a/c.gmi").unwrap(),
+    }));
+    assert_eq!(r(b"30 /x\r\n").unwrap().unwrap().kind,
+               RedirectKind::Temporary);
+    assert_eq!(r(b"20 text/gemini\r\n").unwrap(), None);
+    assert!(r(b"30 #frag\r\n").is_err());
+}
+
+#[test]
+fn test_request_line() {
+    let url = url::Url::parse("gemini://example.com/a\r\nb\x07c").unwrap();
+    assert_eq!(request_line(&url).unwrap(),
+               "gemini://example.com/ab%07c\r\n");
+
+    let url = url::Url::parse("gemini://example.com/").unwrap();
+    let url = url.join("x\r\ngemini://evil.com/\u{200b}").unwrap();
+    let r = request_line(&url).unwrap();
+    assert_eq!(r.matches("\r\n").count(), 1);
+    assert!(r.ends_with("%E2%80%8B\r\n"));
+
+    // Parsing never leaves control characters in a URL, but if it did, the
+    // request would be refused rather than written
+    for s in &["gemini://example.com/a\r\ngemini://evil.com/",
+               "gemini://example.com/\x1b[2J"] {
+        match check_request_line(s) {
+            Err(Error::InvalidRequestURL(u)) => assert!(!u.contains('\n')),
+            r => panic!("Expected {:?} to be refused, got {:?}", s, r),
+        }
+    }
+}
+
+#[test]
+fn test_connect_errors() {
+    let config = Arc::new(rustls::ClientConfig::new());
+
+    // Bind to a port then drop the listener, so that the port is closed
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap()
+        .local_addr().unwrap().port();
+    let url = url::Url::parse(&format!("gemini://localhost:{}/", port))
+        .unwrap();
+    match read(&config, &url) {
+        Err(Error::ConnectionRefused(h)) =>
+            assert_eq!(h, format!("localhost:{}", port)),
+        r => panic!("Expected refused connection, got {:?}", r),
+    }
+
+    // The .invalid TLD is reserved, so it never resolves
+    let url = url::Url::parse("gemini://titan.invalid/").unwrap();
+    match read(&config, &url) {
+        Err(Error::DnsFailure(h)) => assert_eq!(h, "titan.invalid"),
+        r => panic!("Expected DNS failure, got {:?}", r),
+    }
+    let dns = DnsCache::new(std::time::Duration::from_secs(60), 1);
+    assert!(matches!(read_via(&config, &url, Some(&dns)),
+                     Err(Error::DnsFailure(_))));
+}
+
+#[test]
+fn test_text_so_far() {
+    assert!(text_so_far(b"", 4));
+    assert!(text_so_far(b"20 text/gem", 4));
+    assert!(text_so_far(b"20 text/gemini\r\n", 4));
+    assert!(text_so_far(b"20 text/plain\r\nabcd", 4));
+    assert!(!text_so_far(b"20 text/plain\r\nabcde", 4));
+    assert!(!text_so_far(b"20 image/png\r\n", 4));
+    assert!(!text_so_far(b"30 gemini://example.com/\r\n", 4));
+    assert!(!text_so_far(b"10 Name?\r\n", 4));
+    assert!(!text_so_far(&[b'2'; MAX_HEADER_LEN + 2], 4));
+}
+
+#[test]
+fn test_ascii_url() {
+    let a = |s| ascii_url(&url::Url::parse(s).unwrap()).unwrap().into_string();
+    assert_eq!(a("gemini://bücher.example/a?ü"),
+               "gemini://xn--bcher-kva.example/a?%C3%BC");
+    assert_eq!(a("gemini://BÜCHER.example:1966/"),
+               "gemini://xn--bcher-kva.example:1966/");
+    assert_eq!(a("gemini://example.com/ü"), "gemini://example.com/%C3%BC");
+
+    let url = url::Url::parse("gemini://a%FFb.example/").unwrap();
+    assert!(ascii_url(&url).is_err());
+}
+
+#[test]
+fn test_tls_info() {
+    let info = TlsInfo {
+        version: rustls::ProtocolVersion::TLSv1_3,
+        suite: rustls::CipherSuite::TLS13_AES_128_GCM_SHA256,
+    };
+    assert_eq!(info.to_string(), "TLSv1_3 with TLS13_AES_128_GCM_SHA256");
+}
+
+#[test]
+fn test_decode_body() {
+    assert_eq!(decode_body("héllo".as_bytes()).unwrap(), ("héllo", false));
+
+    // "→" is three bytes, and the connection dropped after two of them
+    let body = "next →".as_bytes();
+    assert_eq!(decode_body(&body[..body.len() - 1]).unwrap(), ("next ", true));
+
+    // Invalid bytes which aren't at the end are still rejected
+    assert!(decode_body(b"a\xffb").is_err());
+
+    // ...unless they're replaced
+    assert_eq!(decode_body_lossy(b"a\xffb"), ("a\u{FFFD}b".into(), false));
+    assert!(matches!(decode_body_lossy(b"ok"), (Cow::Borrowed("ok"), false)));
+    let (s, truncated) = decode_body_lossy(&body[..body.len() - 1]);
+    assert_eq!((s.as_ref(), truncated), ("next ", true));
+}
+
+#[test]
+fn test_owned_document() {
+    let r = OwnedDocument::parse(b"30 gemini://example.com/\r\n".to_vec())
+        .unwrap();
+    assert_eq!(r.status(), Status::RedirectTemporary);
+    assert_eq!(r.meta(), "gemini://example.com/");
+    assert!(r.doc().is_none());
+    assert!(r.body().is_empty());
+
+    let r = OwnedDocument::parse(b"10 Your name?\r\n".to_vec()).unwrap();
+    assert_eq!(r.status(), Status::Input);
+    assert_eq!(r.meta(), "Your name?");
+
+    let r = OwnedDocument::parse(b"20 text/gemini\r\n# hi\n".to_vec())
+        .unwrap();
+    assert_eq!(r.doc(), Some(&Document::new(vec![Line::H1("hi")])));
+    assert_eq!(r.body(), b"# hi\n");
+
+    // MIME types are case-insensitive
+    let r = OwnedDocument::parse(b"20 Text/Gemini\r\n# hi\n".to_vec())
+        .unwrap();
+    assert_eq!(r.doc(), Some(&Document::new(vec![Line::H1("hi")])));
+    let r = OwnedDocument::parse(b"20 TEXT/Plain; charset=utf-8\r\n# hi"
+                                 .to_vec()).unwrap();
+    assert_eq!(r.doc(), Some(&Document::new(vec![
+        Line::Pre { alt: None, text: "# hi" }])));
+    let r = OwnedDocument::parse(b"20 text/markdown\r\n# hi\n".to_vec())
+        .unwrap();
+    assert_eq!(r.doc(), Some(&Document::new(vec![Line::H1("hi")])));
+
+    let r = OwnedDocument::parse(b"20 image/png\r\n\x89PNG".to_vec())
+        .unwrap();
+    assert!(r.doc().is_none());
+    assert_eq!(r.body(), b"\x89PNG");
+}
@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use rustls::internal::pemfile;
+use rustls::{Certificate, PrivateKey};
+
+use crate::Error;
+
+/// Named client certificates, and which one (if any) is offered to each
+/// host.  Each identity is stored as PEM text holding its certificate chain
+/// and private key; hosts map to identity names.
+pub struct Identities {
+    names: sled::Tree,
+    hosts: sled::Tree,
+}
+
+impl Identities {
+    pub fn new(root: &sled::Db) -> Result<Identities, Error> {
+        Ok(Identities { names: root.open_tree("identities")?,
+                        hosts: root.open_tree("identity_hosts")? })
+    }
+
+    /// Adds (or replaces) an identity, given PEM text containing a
+    /// certificate and its private key
+    pub fn add(&self, name: &str, pem: &[u8]) -> Result<(), Error> {
+        parse(pem)?;
+        self.names.insert(name, pem)?;
+        Ok(())
+    }
+
+    /// Returns the name of every identity, along with its certificate's
+    /// SHA-256 fingerprint
+    pub fn list(&self) -> Result<Vec<(String, String)>, Error> {
+        self.names.iter()
+            .map(|r| {
+                let (k, v) = r?;
+                let fingerprint = parse(&v).ok()
+                    .and_then(|(certs, _)| certs.into_iter().next())
+                    .map(|c| crate::tofu::fingerprint(&c.0))
+                    .unwrap_or_default();
+                Ok((String::from_utf8_lossy(&k).into_owned(), fingerprint))
+            })
+            .collect()
+    }
+
+    /// Returns every host which has an active identity, and its name
+    pub fn hosts(&self) -> Result<Vec<(String, String)>, Error> {
+        self.hosts.iter()
+            .map(|r| {
+                let (k, v) = r?;
+                Ok((String::from_utf8_lossy(&k).into_owned(),
+                    String::from_utf8_lossy(&v).into_owned()))
+            })
+            .collect()
+    }
+
+    /// Sets the identity offered to a host, or stops offering one if `name`
+    /// is `None`
+    pub fn activate(&self, host: &str, name: Option<&str>)
+        -> Result<(), Error>
+    {
+        match name {
+            Some(name) => {
+                if !self.names.contains_key(name)? {
+                    return Err(Error::UnknownIdentity(name.to_owned()));
+                }
+                self.hosts.insert(host, name)?;
+            },
+            None => {
+                self.hosts.remove(host)?;
+            },
+        }
+        Ok(())
+    }
+
+    /// Returns the name of the identity offered to a host
+    pub fn active(&self, host: &str) -> Result<Option<String>, Error> {
+        Ok(self.hosts.get(host)?
+            .map(|v| String::from_utf8_lossy(&v).into_owned()))
+    }
+
+    /// Returns the TLS configuration for connecting to a host, which is
+    /// `base` with the host's identity (if it has one) as its client
+    /// certificate.  Sessions using an identity aren't resumed, so that
+    /// switching identities takes effect on the next request.
+    pub fn config_for(&self, base: &Arc<rustls::ClientConfig>, host: &str)
+        -> Result<Arc<rustls::ClientConfig>, Error>
+    {
+        let pem = match self.active(host)?
+            .and_then(|name| self.names.get(name).transpose())
+            .transpose()?
+        {
+            Some(pem) => pem,
+            None => return Ok(base.clone()),
+        };
+        let (certs, key) = parse(&pem)?;
+        let mut config = (**base).clone();
+        config.set_single_client_cert(certs, key)?;
+        config.set_persistence(Arc::new(rustls::NoClientSessionStorage {}));
+        Ok(Arc::new(config))
+    }
+}
+
+/// Reads the certificate chain and private key (PKCS#8 or RSA) from PEM text
+fn parse(pem: &[u8]) -> Result<(Vec<Certificate>, PrivateKey), Error> {
+    let invalid = |s: &str| Error::InvalidIdentity(s.to_owned());
+    let certs = pemfile::certs(&mut &pem[..])
+        .ok().filter(|c| !c.is_empty())
+        .ok_or_else(|| invalid("no certificate found"))?;
+    let key = pemfile::pkcs8_private_keys(&mut &pem[..]).ok()
+        .and_then(|k| k.into_iter().next())
+        .or_else(|| pemfile::rsa_private_keys(&mut &pem[..]).ok()
+                     .and_then(|k| k.into_iter().next()))
+        .ok_or_else(|| invalid("no private key found"))?;
+    rustls::sign::any_supported_type(&key)
+        .map_err(|_| invalid("unsupported private key"))?;
+    Ok((certs, key))
+}
+
+#[test]
+fn test_identities() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let ids = Identities::new(&db).unwrap();
+    let pem = include_bytes!("../testdata/identity.pem");
+
+    assert!(ids.add("work", b"not a cert").is_err());
+    ids.add("work", pem).unwrap();
+    let list = ids.list().unwrap();
+    assert_eq!(list.len(), 1);
+    assert_eq!((list[0].0.as_str(), list[0].1.len()), ("work", 64));
+
+    assert!(ids.activate("example.com", Some("home")).is_err());
+    ids.activate("example.com", Some("work")).unwrap();
+    assert_eq!(ids.active("example.com").unwrap().as_deref(), Some("work"));
+    assert_eq!(ids.active("other.com").unwrap(), None);
+    assert_eq!(ids.hosts().unwrap(),
+               vec![("example.com".to_owned(), "work".to_owned())]);
+
+    // Only hosts with an identity get a different configuration
+    let base = Arc::new(rustls::ClientConfig::new());
+    assert!(Arc::ptr_eq(&ids.config_for(&base, "other.com").unwrap(), &base));
+    assert!(!Arc::ptr_eq(&ids.config_for(&base, "example.com").unwrap(),
+                         &base));
+
+    // Active identities persist
+    drop(ids);
+    let ids = Identities::new(&db).unwrap();
+    assert_eq!(ids.active("example.com").unwrap().as_deref(), Some("work"));
+    ids.activate("example.com", None).unwrap();
+    assert_eq!(ids.active("example.com").unwrap(), None);
+}
@@ -1,7 +1,14 @@
+pub mod cache;
+pub mod dns;
 pub mod document;
 pub mod error;
+pub mod feed;
+pub mod identity;
+pub mod markdown;
 pub mod protocol;
+pub mod render;
 pub mod parser;
+pub mod theme;
 pub mod tofu;
 pub mod fetch;
 
@@ -0,0 +1,221 @@
+use crate::document::Document;
+use crate::protocol::Line;
+
+/// Converts a `text/markdown` body into a [`Document`], mapping the common
+/// constructs (headings, lists, quotes, links, and code fences) onto the
+/// text/gemini line types.  Anything else is kept as plain text, so this
+/// never fails.
+///
+/// Links in the middle of a line are left in its text, then repeated as
+/// link lines after it so that they can be followed.
+pub fn parse_markdown(mut input: &str) -> Document<'_> {
+    let mut out = Vec::new();
+    while !input.is_empty() {
+        let (line, rest) = split_line(input);
+        input = rest;
+
+        let trimmed = line.trim_start();
+        if let Some(fence) = ["```", "~~~"].iter()
+            .find(|f| trimmed.starts_with(**f))
+        {
+            let alt = trimmed[3..].trim();
+            let alt = if alt.is_empty() { None } else { Some(alt) };
+            let (text, rest) = read_fence(input, fence);
+            input = rest;
+            out.push(Line::Pre { alt, text });
+            continue;
+        }
+
+        if let Some(link) = whole_link(trimmed) {
+            out.push(link);
+            continue;
+        }
+
+        out.push(if let Some((level, text)) = heading(trimmed) {
+            match level {
+                1 => Line::H1(text),
+                2 => Line::H2(text),
+                _ => Line::H3(text),
+            }
+        } else if is_rule(trimmed) {
+            Line::Text("")
+        } else if let Some(item) = list_item(trimmed) {
+            Line::List(item)
+        } else if let Some(quote) = trimmed.strip_prefix('>') {
+            Line::Quote(quote.trim_start())
+        } else {
+            Line::Text(line)
+        });
+        out.extend(inline_links(line));
+    }
+    Document(out)
+}
+
+/// Splits off the first line, without its line ending
+fn split_line(input: &str) -> (&str, &str) {
+    let (line, rest) = match input.find('\n') {
+        Some(i) => (&input[..i], &input[i + 1..]),
+        None => (input, ""),
+    };
+    (line.strip_suffix('\r').unwrap_or(line), rest)
+}
+
+/// Reads the body of a code block up to its closing fence (or the end of the
+/// input, if it's never closed), returning the text and the remaining input
+fn read_fence<'a>(input: &'a str, fence: &str) -> (&'a str, &'a str) {
+    let mut pos = 0;
+    while pos < input.len() {
+        let (line, rest) = split_line(&input[pos..]);
+        if line.trim_start().starts_with(fence) {
+            let text = &input[..pos];
+            let text = text.strip_suffix('\n').unwrap_or(text);
+            let text = text.strip_suffix('\r').unwrap_or(text);
+            return (text, rest);
+        }
+        pos = input.len() - rest.len();
+    }
+    (input.trim_end_matches(['\r', '\n']), "")
+}
+
+/// Parses an ATX heading (`# Title`), returning its level and text
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.bytes().take_while(|c| *c == b'#').count();
+    let text = &line[level..];
+    if !(1..=6).contains(&level) ||
+        !(text.is_empty() || text.starts_with([' ', '\t']))
+    {
+        return None;
+    }
+    // Closing hashes are optional decoration
+    Some((level, text.trim().trim_end_matches('#').trim_end()))
+}
+
+/// Checks for a thematic break, e.g. `---` or `* * *`
+fn is_rule(line: &str) -> bool {
+    let chars: Vec<char> = line.chars().filter(|c| !c.is_whitespace())
+        .collect();
+    chars.len() >= 3 && ['-', '*', '_'].iter()
+        .any(|r| chars.iter().all(|c| c == r))
+}
+
+/// Parses a bulleted (`- `, `* `, `+ `) or numbered (`1. `) list item
+fn list_item(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix(['-', '*', '+']).or_else(|| {
+        let digits = line.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return None;
+        }
+        line[digits..].strip_prefix(['.', ')'])
+    })?;
+    rest.starts_with([' ', '\t']).then(|| rest.trim())
+}
+
+/// Parses a link or image at the start of the input, returning its text,
+/// target, and the input after it
+fn link(input: &str) -> Option<(&str, &str, &str)> {
+    let input = input.strip_prefix('!').unwrap_or(input);
+    let input = input.strip_prefix('[')?;
+    let end = input.find("](")?;
+    let name = &input[..end];
+    let rest = &input[end + 2..];
+    let close = rest.find(')')?;
+    // Drop the optional title, as in [name](url "title")
+    let url = rest[..close].split_whitespace().next()?;
+    let url = url.strip_prefix('<')
+        .and_then(|u| u.strip_suffix('>'))
+        .unwrap_or(url);
+    Some((name.trim(), url, &rest[close + 1..]))
+}
+
+fn link_line<'a>(name: &'a str, url: &'a str) -> Line<'a> {
+    if name.is_empty() {
+        Line::BareLink(url)
+    } else {
+        Line::NamedLink { url, name }
+    }
+}
+
+/// Returns a link line if the entire line is a single link or image
+fn whole_link(line: &str) -> Option<Line<'_>> {
+    match link(line.trim_end()) {
+        Some((name, url, "")) => Some(link_line(name, url)),
+        _ => None,
+    }
+}
+
+/// Finds every link or image within a line
+fn inline_links(mut line: &str) -> Vec<Line<'_>> {
+    let mut out = Vec::new();
+    while let Some(i) = line.find('[') {
+        // Include a leading '!' so images are parsed the same way
+        let start = if line[..i].ends_with('!') { i - 1 } else { i };
+        match link(&line[start..]) {
+            Some((name, url, rest)) => {
+                out.push(link_line(name, url));
+                line = rest;
+            },
+            None => line = &line[i + 1..],
+        }
+    }
+    out
+}
+
+#[test]
+fn test_parse_markdown() {
+    let doc = parse_markdown("# Title\n\
+                              Some text\n\
+                              ## Sub ##\n\
+                              #### Deep\n\
+                              #hashtag\n\
+                              - item\n\
+                              * other\n\
+                              2. numbered\n\
+                              > quoted\n\
+                              ---\n");
+    assert_eq!(doc.0, vec![
+        Line::H1("Title"),
+        Line::Text("Some text"),
+        Line::H2("Sub"),
+        Line::H3("Deep"),
+        Line::Text("#hashtag"),
+        Line::List("item"),
+        Line::List("other"),
+        Line::List("numbered"),
+        Line::Quote("quoted"),
+        Line::Text(""),
+    ]);
+}
+
+#[test]
+fn test_markdown_links() {
+    let doc = parse_markdown("[name](gemini://example.com)\r\n\
+                              ![a picture](pic.png \"Title\")\n\
+                              [](bare.gmi)\n\
+                              See [here](a.gmi) and [there](<b.gmi>).\n\
+                              - [listed](c.gmi)\n\
+                              [not a link] (d.gmi)\n");
+    assert_eq!(doc.0, vec![
+        Line::NamedLink { url: "gemini://example.com", name: "name" },
+        Line::NamedLink { url: "pic.png", name: "a picture" },
+        Line::BareLink("bare.gmi"),
+        Line::Text("See [here](a.gmi) and [there](<b.gmi>)."),
+        Line::NamedLink { url: "a.gmi", name: "here" },
+        Line::NamedLink { url: "b.gmi", name: "there" },
+        Line::List("[listed](c.gmi)"),
+        Line::NamedLink { url: "c.gmi", name: "listed" },
+        Line::Text("[not a link] (d.gmi)"),
+    ]);
+}
+
+#[test]
+fn test_markdown_code() {
+    let doc = parse_markdown("```rust\nfn main() {}\n\n# not a heading\n```\n\
+                              after\n\
+                              ~~~\nunclosed\n");
+    assert_eq!(doc.0, vec![
+        Line::Pre { alt: Some("rust"),
+                    text: "fn main() {}\n\n# not a heading" },
+        Line::Text("after"),
+        Line::Pre { alt: None, text: "unclosed" },
+    ]);
+}
@@ -6,45 +6,132 @@ use crate::Error;
 use nom::{
     IResult,
     branch::alt,
-    bytes::complete::{is_not, tag, take_while_m_n, take_until, take_till},
+    bytes::complete::{is_not, tag, take_while_m_n, take_till},
     character::{is_digit},
     character::complete::space0,
-    combinator::map_res,
-    sequence::{terminated, tuple},
+    combinator::{map, map_res},
+    error::{Error as NomError, ErrorKind},
+    sequence::{delimited, terminated, tuple},
 };
 
 use crate::protocol::{Status, Response, Line};
 
+/// Default cap on the number of lines parsed from a document
+pub const MAX_LINES: usize = 100_000;
+
+/// Options which relax the text/gemini parser, for documents which don't
+/// quite follow the spec, or limit how much of a document is parsed.  The
+/// default is strict, with a generous limit.
+#[derive(Copy, Clone, Debug)]
+pub struct ParseOptions {
+    /// Accept `- ` as a list bullet, as well as `* `
+    pub dash_lists: bool,
+
+    /// Stop parsing after this many lines (where a preformatted block counts
+    /// as one line), so that a huge document can't exhaust memory
+    pub max_lines: Option<usize>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions { dash_lists: false, max_lines: Some(MAX_LINES) }
+    }
+}
+
 // Temporary tuple type, to make nom's type-inference happy
 type ResponseHeader<'a> = (Status, &'a str);
 
-pub fn parse_response_header(input: &[u8]) -> IResult<&[u8], ResponseHeader> {
-    let (input, (status, _, meta)) = tuple((
+pub fn parse_response_header(input: &[u8])
+    -> IResult<&[u8], ResponseHeader<'_>>
+{
+    let (input, (status, meta)) = tuple((
         map_res(
             take_while_m_n(2, 2, is_digit),
             |i| {
                 let s = std::str::from_utf8(i)
                     .expect("Could not convert to utf8");
-                let n = u32::from_str_radix(s, 10)
+                let n = s.parse::<u32>()
                     .expect("Could not get u32");
                 Status::try_from(n)
             }),
-        tag(" "),
-        map_res(
-            terminated(take_while_m_n(0, 1024, |c: u8| c != b'\r'),
-                       tag("\r\n")),
-            std::str::from_utf8)
+        // Some servers leave out the space when the meta is empty
+        alt((
+            map(tag("\r\n"), |_| ""),
+            map_res(
+                delimited(tag(" "),
+                          take_while_m_n(0, 1024, |c: u8| c != b'\r'),
+                          tag("\r\n")),
+                std::str::from_utf8),
+        )),
     ))(input)?;
 
     Ok((input, (status, meta)))
 }
 
-pub fn parse_response(input: &[u8]) -> Result<Response, Error> {
+/// Longest header before the `\r\n`: a two-digit status, a space, and up
+/// to 1024 bytes of meta
+pub(crate) const MAX_HEADER_LEN: usize = 1024 + 3;
+
+/// Splits a raw response into its header (including the trailing `\r\n`)
+/// and body, without parsing either
+pub fn split_response(input: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    let limit = (MAX_HEADER_LEN + 2).min(input.len());
+    input[..limit].windows(2)
+        .position(|w| w == b"\r\n")
+        .map(|i| input.split_at(i + 2))
+        .ok_or(Error::MissingHeaderTerminator(MAX_HEADER_LEN + 2))
+}
+
+pub fn parse_response(input: &[u8]) -> Result<Response<'_>, Error> {
+    // Check the header's structure (exactly two digits, then a space, or
+    // the line ending if the meta is empty) and status code up front, since
+    // errors from the parser itself are less helpful for debugging
+    // misbehaving servers
+    match input {
+        [a, b, b' ', ..] | [a, b, b'\r', b'\n', ..]
+            if a.is_ascii_digit() && b.is_ascii_digit() =>
+        {
+            Status::try_from(u32::from((a - b'0') * 10 + (b - b'0')))?;
+        },
+        _ => return Err(malformed_header(input)),
+    }
+
     let (body, (status, meta)) = parse_response_header(input)
-        .map_err(|_| Error::ParseError)?;
+        .map_err(|e| parse_error(input, e.map(|e| (e.input.len(), e.code))))?;
     Ok(Response { status, meta, body })
 }
 
+/// Builds a [`Error::MalformedHeader`] from the first line of a response
+fn malformed_header(input: &[u8]) -> Error {
+    let end = input.iter()
+        .position(|c| *c == b'\n')
+        .unwrap_or(input.len())
+        .min(MAX_HEADER_LEN + 2);
+    let header = String::from_utf8_lossy(&input[..end]);
+    Error::MalformedHeader(header.trim_end().to_owned())
+}
+
+/// Converts a nom error into an [`Error::ParseError`], locating it in the
+/// original input.  The error holds the length of the input which remained
+/// when the failing parser ran, which gives us its byte offset.
+fn parse_error(input: &[u8], e: nom::Err<(usize, ErrorKind)>) -> Error {
+    let (remaining, kind) = match e {
+        nom::Err::Error(e) | nom::Err::Failure(e) => e,
+        nom::Err::Incomplete(_) => (0, ErrorKind::Complete),
+    };
+    let offset = input.len() - remaining;
+    let before = &input[..offset];
+    let line = before.iter().filter(|c| **c == b'\n').count() + 1;
+    let column = offset - before.iter().rposition(|c| *c == b'\n')
+        .map(|i| i + 1)
+        .unwrap_or(0) + 1;
+    Error::ParseError {
+        line,
+        detail: format!("expected {} at column {} (byte {})",
+                        kind.description(), column, offset),
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 /// Reads a single line up until the newline, consuming the terminator
@@ -62,33 +149,48 @@ fn read_prefixed<'a, F>(input: &'a str, t: &'static str, f: F)
     Ok((input, f(o)))
 }
 
-fn parse_line_h1(input: &str) -> IResult<&str, Line> {
+fn parse_line_h1(input: &str) -> IResult<&str, Line<'_>> {
     read_prefixed(input, "#", |s| Line::H1(s))
 }
 
-fn parse_line_h2(input: &str) -> IResult<&str, Line> {
+fn parse_line_h2(input: &str) -> IResult<&str, Line<'_>> {
     read_prefixed(input, "##", |s| Line::H2(s))
 }
 
-fn parse_line_h3(input: &str) -> IResult<&str, Line> {
+fn parse_line_h3(input: &str) -> IResult<&str, Line<'_>> {
     read_prefixed(input, "###", |s| Line::H3(s))
 }
 
-fn parse_line_list(input: &str) -> IResult<&str, Line> {
-    read_prefixed(input, "* ", |s| Line::List(s))
+fn parse_line_list(input: &str, opts: ParseOptions)
+    -> IResult<&str, Line<'_>>
+{
+    // Only the single space after the bullet is consumed, so that extra
+    // indentation (e.g. for nested items) is preserved in the text
+    let (input, _) = tag("* ")(input).or_else(|e| if opts.dash_lists {
+        tag("- ")(input)
+    } else {
+        Err(e)
+    })?;
+    let (input, o) = read_line(input)?;
+    Ok((input, Line::List(o)))
 }
 
-fn parse_line_quote(input: &str) -> IResult<&str, Line> {
+fn parse_line_quote(input: &str) -> IResult<&str, Line<'_>> {
     read_prefixed(input, ">", |s| Line::Quote(s))
 }
 
-fn parse_line_link(input: &str) -> IResult<&str, Line> {
-    let (input, (_, url, name)) = tuple((
+/// Parses a link line.  A `=>` without a URL isn't a link, so it's left for
+/// [`parse_line_text`] to keep as plain text, where the author can see it.
+fn parse_line_link(input: &str) -> IResult<&str, Line<'_>> {
+    let (rest, (_, url, name)) = tuple((
             terminated(tag("=>"), space0),
             terminated(take_till(char::is_whitespace), space0),
             read_line))(input)?;
+    if url.is_empty() {
+        return Err(nom::Err::Error(NomError::new(input, ErrorKind::Verify)));
+    }
 
-    Ok((input,
+    Ok((rest,
         if name.is_empty() {
             Line::BareLink(url)
         } else {
@@ -96,37 +198,103 @@ fn parse_line_link(input: &str) -> IResult<&str, Line> {
         }))
 }
 
-fn parse_pre(input: &str) -> IResult<&str, Line> {
+fn parse_pre(input: &str) -> IResult<&str, Line<'_>> {
     let (input, (_, alt)) = tuple((tag("```"), read_line))(input)?;
     let alt = if alt.is_empty() {
         None
     } else {
         Some(alt)
     };
-    let (input, text) = take_until("\n```\n")(input)?;
-    let (input, _) = tag("\n```\n")(input)?;
-
-    Ok((input, Line::Pre { alt, text }))
+    // The block ends at the first line which starts with a fence (anything
+    // after the fence is ignored), or at the end of the input.  The line
+    // break before the fence isn't part of the preformatted text.
+    let mut pos = 0;
+    loop {
+        let (line, next) = match input[pos..].find('\n') {
+            Some(i) => (&input[pos..pos + i], pos + i + 1),
+            None => (&input[pos..], input.len()),
+        };
+        if line.starts_with("```") || pos == input.len() {
+            let text = &input[..pos];
+            let text = text.strip_suffix('\n').unwrap_or(text);
+            let text = text.strip_suffix('\r').unwrap_or(text);
+            return Ok((&input[next..], Line::Pre { alt, text }));
+        }
+        pos = next;
+    }
 }
 
-fn parse_line_text(input: &str) -> IResult<&str, Line> {
+fn parse_line_text(input: &str) -> IResult<&str, Line<'_>> {
     let (input, text) = read_line(input)?;
     Ok((input, Line::Text(text)))
 }
 
-/// Parse a single line or preformatted block of text/gemini
-fn parse_line(input: &str) -> IResult<&str, Line> {
-    alt((parse_line_h3, parse_line_h2, parse_line_h1, parse_line_list,
+/// Parse a single line or preformatted block of text/gemini, returning it
+/// and the rest of the input
+pub fn parse_line(input: &str, opts: ParseOptions)
+    -> IResult<&str, Line<'_>>
+{
+    alt((parse_line_h3, parse_line_h2, parse_line_h1,
+         |i| parse_line_list(i, opts),
          parse_line_quote, parse_line_link, parse_pre, parse_line_text))
         (input)
 }
 
+/// Schemes of URLs which are detected within plain text
+const INLINE_SCHEMES: &[&str] = &["gemini://", "gopher://", "https://",
+                                  "http://"];
+
+/// Finds URLs embedded in a line of plain text, in order.  A URL starts
+/// with one of a few well-known schemes and runs until whitespace, with
+/// trailing punctuation (e.g. the period ending a sentence) left off.
+pub fn find_urls(text: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut rest = text;
+    while let Some(start) = INLINE_SCHEMES.iter()
+        .filter_map(|s| rest.find(s))
+        .min()
+    {
+        let s = &rest[start..];
+        let end = s.find(|c: char| c.is_whitespace() || "<>\"".contains(c))
+            .unwrap_or(s.len());
+        let mut url = &s[..end];
+        loop {
+            let trimmed = url.trim_end_matches(&['.', ',', ';', ':', '!', '?',
+                                                 '\''][..]);
+            // A closing bracket is kept if it matches one in the URL
+            let unbalanced = trimmed.matches(')').count() >
+                             trimmed.matches('(').count();
+            let trimmed = match trimmed.strip_suffix(')') {
+                Some(t) if unbalanced => t,
+                _ => trimmed,
+            };
+            if trimmed == url {
+                break;
+            }
+            url = trimmed;
+        }
+        if !INLINE_SCHEMES.contains(&url) {
+            out.push(url);
+        }
+        rest = &s[end..];
+    }
+    out
+}
+
 /// Parse a full text/gemini document
-pub fn parse_text_gemini(mut input: &str) -> IResult<&str, Document> {
+pub fn parse_text_gemini(input: &str) -> IResult<&str, Document<'_>> {
+    parse_text_gemini_with(input, ParseOptions::default())
+}
+
+/// Equivalent to [`parse_text_gemini`], with the given [`ParseOptions`].  If
+/// parsing stops at `max_lines`, the unparsed input is returned.
+pub fn parse_text_gemini_with(mut input: &str, opts: ParseOptions)
+    -> IResult<&str, Document<'_>>
+{
     let mut out = Vec::new();
 
-    while !input.is_empty() {
-        let (input_, parsed) = parse_line(input)?;
+    while !input.is_empty() && opts.max_lines.is_none_or(|m| out.len() < m) {
+        let (input_, parsed) = parse_next(input, opts)?;
         input = input_;
         out.push(parsed);
     }
@@ -134,6 +302,58 @@ pub fn parse_text_gemini(mut input: &str) -> IResult<&str, Document> {
     Ok((input, Document(out)))
 }
 
+/// Equivalent to [`parse_text_gemini_with`], but never fails: a line which
+/// can't be parsed is kept as plain text, and parsing carries on with the
+/// next line.  Also returns how many lines were recovered this way.
+pub fn parse_text_gemini_lossy(mut input: &str, opts: ParseOptions)
+    -> (&str, Document<'_>, usize)
+{
+    let mut out = Vec::new();
+    let mut errors = 0;
+
+    while !input.is_empty() && opts.max_lines.is_none_or(|m| out.len() < m) {
+        let (input_, parsed) = parse_next(input, opts).unwrap_or_else(|_| {
+            // Lines only fail at a stray '\r' (once any text before it has
+            // been parsed), so it's skipped and the text after it is kept.
+            // Reading a line after the '\r' always makes progress.
+            errors += 1;
+            let rest = input.trim_start_matches('\r');
+            read_line(rest).map(|(rest, text)| (rest, Line::Text(text)))
+                .unwrap_or(("", Line::Text(rest)))
+        });
+        input = input_;
+        out.push(parsed);
+    }
+
+    (input, Document(out), errors)
+}
+
+/// Parses the next line, failing if it consumes nothing (e.g. a lone '\r'),
+/// which would otherwise loop forever
+fn parse_next(input: &str, opts: ParseOptions) -> IResult<&str, Line<'_>> {
+    let (rest, parsed) = parse_line(input, opts)?;
+    if rest.len() == input.len() {
+        return Err(nom::Err::Error(NomError::new(input, ErrorKind::CrLf)));
+    }
+    Ok((rest, parsed))
+}
+
+/// Parses a full text/gemini document, reporting the line on failure
+pub fn parse_document(input: &str) -> Result<Document<'_>, Error> {
+    parse_document_with(input, ParseOptions::default()).map(|(doc, _)| doc)
+}
+
+/// Equivalent to [`parse_document`], with the given [`ParseOptions`].  Also
+/// returns whether the document was cut short at `max_lines`.
+pub fn parse_document_with(input: &str, opts: ParseOptions)
+    -> Result<(Document<'_>, bool), Error>
+{
+    parse_text_gemini_with(input, opts)
+        .map(|(rest, doc)| (doc, !rest.is_empty()))
+        .map_err(|e| parse_error(input.as_bytes(),
+                                 e.map(|e| (e.input.len(), e.code))))
+}
+
 #[test]
 pub fn test_parse_text_gemini() {
     let r = parse_text_gemini("# h1
@@ -154,10 +374,44 @@ hi there").unwrap();
             "for i in range(10):\n    print(i)"},
         Line::Text("hi there"),
     ]));
+
+    // The same document with CRLF line endings
+    let r = parse_text_gemini("# h1\r\n> quote\r\n```py\r\n\
+                               for i in range(10):\r\n    print(i)\r\n\
+                               ```\r\nhi there").unwrap();
+    assert_eq!(r.1, Document::new(vec![
+        Line::H1("h1"),
+        Line::Quote("quote"),
+        Line::Pre { alt: Some("py"), text:
+            "for i in range(10):\r\n    print(i)"},
+        Line::Text("hi there"),
+    ]));
+
+    // Any line starting with a fence closes the block, and an empty block
+    // is closed by the very next line
+    let r = parse_text_gemini("```\nhi\n```py\n```\r\n```\n").unwrap();
+    assert_eq!(r.1, Document::new(vec![
+        Line::Pre { alt: None, text: "hi" },
+        Line::Pre { alt: None, text: "" },
+    ]));
+
+    // An unclosed block runs to the end of the document, with either line
+    // ending (or none) on its last line
+    for input in &["```\nhi\nthere", "```\nhi\nthere\n",
+                   "```\r\nhi\r\nthere\r\n", "```\nhi\nthere\n```"]
+    {
+        let r = parse_text_gemini(input).unwrap();
+        let text = if input.contains('\r') { "hi\r\nthere" }
+                   else { "hi\nthere" };
+        assert_eq!(r.1, Document::new(vec![
+            Line::Pre { alt: None, text },
+        ]));
+    }
 }
 
 #[test]
 pub fn test_parse_line() {
+    let parse_line = |s| parse_line(s, ParseOptions::default());
     let r = parse_line("=> hello.com world").unwrap();
     assert_eq!(r.1, Line::NamedLink {
         url: "hello.com",
@@ -166,6 +420,15 @@ pub fn test_parse_line() {
     let r = parse_line("=> hello.com ").unwrap();
     assert_eq!(r.1, Line::BareLink("hello.com"));
 
+    // Link lines without a URL are kept as text
+    assert_eq!(parse_line("=>").unwrap().1, Line::Text("=>"));
+    assert_eq!(parse_line("=> ").unwrap().1, Line::Text("=> "));
+    assert_eq!(parse_line("=> \r\nnext").unwrap(), ("next", Line::Text("=> ")));
+
+    // A single word is a (relative) URL, not a name
+    let r = parse_line("=>    name-only").unwrap();
+    assert_eq!(r.1, Line::BareLink("name-only"));
+
     let r = parse_line("#header").unwrap();
     assert_eq!(r.1, Line::H1("header"));
 
@@ -174,4 +437,169 @@ pub fn test_parse_line() {
 
     let r = parse_line("> quote").unwrap();
     assert_eq!(r.1, Line::Quote("quote"));
+
+    let r = parse_line("* item").unwrap();
+    assert_eq!(r.1, Line::List("item"));
+
+    let r = parse_line("*   indented").unwrap();
+    assert_eq!(r.1, Line::List("  indented"));
+}
+
+#[test]
+pub fn test_dash_lists() {
+    let lenient = ParseOptions { dash_lists: true, ..Default::default() };
+    let strict = ParseOptions::default();
+
+    let r = parse_line("- item", lenient).unwrap();
+    assert_eq!(r.1, Line::List("item"));
+    let r = parse_line("* item", lenient).unwrap();
+    assert_eq!(r.1, Line::List("item"));
+    let r = parse_line("- item", strict).unwrap();
+    assert_eq!(r.1, Line::Text("- item"));
+
+    // A dash which isn't followed by a space isn't a bullet
+    for opts in &[strict, lenient] {
+        assert_eq!(parse_line("-", *opts).unwrap().1, Line::Text("-"));
+        assert_eq!(parse_line("-item", *opts).unwrap().1,
+                   Line::Text("-item"));
+    }
+}
+
+#[test]
+pub fn test_split_response() {
+    let (header, body) = split_response(b"20 text/gemini\r\n# hi\r\n")
+        .unwrap();
+    assert_eq!(header, b"20 text/gemini\r\n");
+    assert_eq!(body, b"# hi\r\n");
+
+    // A header with a maximum-length meta is accepted
+    let mut r = b"20 ".to_vec();
+    r.extend(vec![b'x'; 1024]);
+    r.extend(b"\r\nbody");
+    let (header, body) = split_response(&r).unwrap();
+    assert_eq!(header.len(), 1029);
+    assert_eq!(body, b"body");
+
+    // ...but one byte more is not
+    let mut r = b"20 ".to_vec();
+    r.extend(vec![b'x'; 1025]);
+    r.extend(b"\r\nbody");
+    assert!(split_response(&r).is_err());
+
+    assert!(split_response(b"20 text/gemini\n# hi").is_err());
+    assert!(split_response(b"").is_err());
+}
+
+#[test]
+pub fn test_parse_lossy() {
+    let opts = ParseOptions::default();
+    let (rest, doc, errors) = parse_text_gemini_lossy(
+        "# title\nbad\rline\n=> a.gmi\r\n# two\r\rstray\rs\n", opts);
+    assert_eq!(rest, "");
+    assert_eq!(errors, 3);
+    assert_eq!(doc.0, vec![
+        Line::H1("title"),
+        Line::Text("bad"),
+        Line::Text("line"),
+        Line::BareLink("a.gmi"),
+        Line::H1("two"),
+        Line::Text("stray"),
+        Line::Text("s"),
+    ]);
+
+    // Documents which parse cleanly come out the same either way
+    let input = "# title\n```\npre\n```\n* item\n";
+    let (_, doc, errors) = parse_text_gemini_lossy(input, opts);
+    assert_eq!((doc, errors), (parse_document(input).unwrap(), 0));
+}
+
+#[test]
+pub fn test_parse_error() {
+    match parse_document("# title\nfine\nbad\rline\n") {
+        Err(Error::ParseError { line, detail }) => {
+            assert_eq!(line, 3);
+            assert!(detail.contains("column 4"), "{}", detail);
+        },
+        r => panic!("Unexpected result {:?}", r),
+    }
+    assert!(parse_document("# title\r\nfine\n").is_ok());
+
+    match parse_response(b"20 text/gemini") {
+        Err(Error::ParseError { line, detail }) => {
+            assert_eq!(line, 1);
+            assert!(detail.contains("column 15"), "{}", detail);
+        },
+        r => panic!("Unexpected result {:?}", r.map(|r| r.status)),
+    }
+}
+
+#[test]
+pub fn test_malformed_header() {
+    let header = |input: &[u8]| match parse_response(input) {
+        Err(Error::MalformedHeader(h)) => h,
+        r => panic!("Unexpected result {:?}", r.map(|r| r.status)),
+    };
+    assert_eq!(header(b"2 text/gemini\r\nbody"), "2 text/gemini");
+    assert_eq!(header(b"200 text/gemini\r\nbody"), "200 text/gemini");
+    assert_eq!(header(b"2x text/gemini\r\n"), "2x text/gemini");
+    assert_eq!(header(b"20\ttext/gemini\r\n"), "20\ttext/gemini");
+    assert_eq!(header(b"\xff\xfe\r\n"), "\u{fffd}\u{fffd}");
+    assert_eq!(header(b""), "");
+
+    // Well-formed headers with unknown status codes are reported as such
+    match parse_response(b"25 text/gemini\r\n") {
+        Err(Error::InvalidStatusCode(25)) => (),
+        r => panic!("Unexpected result {:?}", r.map(|r| r.status)),
+    }
+    assert!(parse_response(b"20 text/gemini\r\n").is_ok());
+}
+
+#[test]
+pub fn test_empty_meta() {
+    let r = parse_response(b"20\r\nbody").unwrap();
+    assert_eq!((r.status, r.meta, r.body), (Status::Success, "", &b"body"[..]));
+    let r = parse_response(b"20 \r\n").unwrap();
+    assert_eq!((r.status, r.meta), (Status::Success, ""));
+    let r = parse_response(b"20 text/gemini\r\n").unwrap();
+    assert_eq!((r.status, r.meta), (Status::Success, "text/gemini"));
+
+    // The space is still needed before a meta
+    assert!(parse_response(b"20text/gemini\r\n").is_err());
+    assert!(parse_response(b"20\n").is_err());
+}
+
+#[test]
+pub fn test_find_urls() {
+    assert_eq!(find_urls("see gemini://example.com/a.gmi."),
+               vec!["gemini://example.com/a.gmi"]);
+    assert_eq!(find_urls("(at https://example.com/wiki/A_(b)) and \
+                          http://x.org/, or gemini://y.org?q!"),
+               vec!["https://example.com/wiki/A_(b)", "http://x.org/",
+                    "gemini://y.org?q"]);
+    assert_eq!(find_urls("<gopher://example.com:70/1>"),
+               vec!["gopher://example.com:70/1"]);
+    assert!(find_urls("no urls, just gemini:// and ftp://example.com")
+            .is_empty());
+}
+
+#[test]
+pub fn test_max_lines() {
+    let huge = "line\n".repeat(MAX_LINES + 10);
+    let (doc, truncated) = parse_document_with(&huge, Default::default())
+        .unwrap();
+    assert_eq!(doc.0.len(), MAX_LINES);
+    assert!(truncated);
+
+    let opts = ParseOptions { max_lines: Some(2), ..Default::default() };
+    let (rest, doc) = parse_text_gemini_with("a\n```\nb\n```\nc\n", opts)
+        .unwrap();
+    assert_eq!(doc.0.len(), 2);
+    assert_eq!(rest, "c\n");
+
+    // Documents which fit (or aren't limited) aren't marked as truncated
+    let (doc, truncated) = parse_document_with("a\nb", opts).unwrap();
+    assert_eq!((doc.0.len(), truncated), (2, false));
+    let opts = ParseOptions { max_lines: None, ..Default::default() };
+    let (doc, truncated) = parse_document_with(&huge, opts).unwrap();
+    assert_eq!((doc.0.len(), truncated), (MAX_LINES + 10, false));
 }
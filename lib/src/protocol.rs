@@ -51,6 +51,59 @@ impl TryFrom<u32> for Status {
     }
 }
 
+impl From<Status> for u32 {
+    fn from(s: Status) -> u32 {
+        use Status::*;
+        match s {
+            Input => 10,
+            SensitiveInput => 11,
+            Success => 20,
+            RedirectTemporary => 30,
+            RedirectPermanent => 31,
+            TemporaryFailure => 40,
+            ServerUnavailable => 41,
+            CGIError => 42,
+            ProxyError => 43,
+            SlowDown => 44,
+            PermanentFailure => 50,
+            NotFound => 51,
+            Gone => 52,
+            ProxyRequestRefused => 53,
+            BadRequest => 59,
+            ClientCertificateRequired => 60,
+            CertificateNotAuthorized => 61,
+            CertificateNotValid => 62,
+        }
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Status::*;
+        let s = match self {
+            Input => "Input",
+            SensitiveInput => "Sensitive input",
+            Success => "Success",
+            RedirectTemporary => "Temporary redirect",
+            RedirectPermanent => "Permanent redirect",
+            TemporaryFailure => "Temporary failure",
+            ServerUnavailable => "Server unavailable",
+            CGIError => "CGI error",
+            ProxyError => "Proxy error",
+            SlowDown => "Slow down",
+            PermanentFailure => "Permanent failure",
+            NotFound => "Not found",
+            Gone => "Gone",
+            ProxyRequestRefused => "Proxy request refused",
+            BadRequest => "Bad request",
+            ClientCertificateRequired => "Client certificate required",
+            CertificateNotAuthorized => "Certificate not authorized",
+            CertificateNotValid => "Certificate not valid",
+        };
+        write!(f, "{} {}", u32::from(*self), s)
+    }
+}
+
 #[derive(Debug)]
 pub struct Response<'a> {
     pub status: Status,
@@ -58,6 +111,85 @@ pub struct Response<'a> {
     pub body: &'a [u8],
 }
 
+impl Response<'_> {
+    /// Returns the MIME type from a success response's meta
+    pub fn mime_type(&self) -> String {
+        mime_type(self.meta)
+    }
+
+    /// Returns the `charset` parameter from a success response's meta,
+    /// lowercased
+    pub fn charset(&self) -> Option<String> {
+        parse_media_type(self.meta).1.into_iter()
+            .find(|(k, _)| k == "charset")
+            .map(|(_, v)| v.to_ascii_lowercase())
+    }
+}
+
+/// Extracts the MIME type (without parameters) from a meta, lowercased so
+/// that it can be compared directly, since MIME types are case-insensitive.
+/// Parameters (e.g. `charset`) are dropped rather than lowercased.
+pub fn mime_type(meta: &str) -> String {
+    parse_media_type(meta).0
+}
+
+/// Splits a media type (e.g. `text/gemini; charset="utf-8"; lang=en`) into
+/// its MIME type and parameters.  The type and parameter names are
+/// lowercased, since they're case-insensitive, while values are kept as
+/// written, with quotes (and backslash escapes within them) removed.
+/// Parameters without a `=` are skipped.
+pub fn parse_media_type(meta: &str) -> (String, Vec<(String, String)>) {
+    let (mime, mut rest) = meta.split_once(';').unwrap_or((meta, ""));
+    let mut params = Vec::new();
+    while !rest.is_empty() {
+        let (name, value, next) = match rest.split_once('=') {
+            // A parameter without a value runs to the next ';'
+            Some((name, _)) if name.contains(';') => {
+                rest = rest.split_once(';').unwrap().1;
+                continue;
+            },
+            Some((name, value)) => {
+                let (value, next) = media_param_value(value.trim_start());
+                (name, value, next)
+            },
+            None => break,
+        };
+        let name = name.trim().to_ascii_lowercase();
+        if !name.is_empty() {
+            params.push((name, value));
+        }
+        rest = next;
+    }
+    (mime.trim().to_ascii_lowercase(), params)
+}
+
+/// Reads a parameter value, which is either a quoted string or runs up to
+/// the next ';', returning it and the input after its ';'
+fn media_param_value(s: &str) -> (String, &str) {
+    let quoted = match s.strip_prefix('"') {
+        Some(q) => q,
+        None => {
+            let (value, next) = s.split_once(';').unwrap_or((s, ""));
+            return (value.trim().to_owned(), next);
+        },
+    };
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => value.extend(chars.next().map(|(_, c)| c)),
+            '"' => {
+                // Anything between the closing quote and the ';' is ignored
+                let after = &quoted[i + 1..];
+                let next = after.split_once(';').map_or("", |(_, n)| n);
+                return (value, next);
+            },
+            c => value.push(c),
+        }
+    }
+    (value, "") // Unterminated quotes run to the end
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Line<'a> {
     Text(&'a str),
@@ -70,3 +202,38 @@ pub enum Line<'a> {
     List(&'a str),
     Quote(&'a str),
 }
+
+#[test]
+fn test_mime_type() {
+    assert_eq!(mime_type("text/gemini"), "text/gemini");
+    assert_eq!(mime_type("Text/Gemini; charset=UTF-8"), "text/gemini");
+    assert_eq!(mime_type("TEXT/PLAIN;lang=en"), "text/plain");
+    assert_eq!(mime_type(""), "");
+}
+
+#[test]
+fn test_parse_media_type() {
+    let p = |s| {
+        let (mime, params) = parse_media_type(s);
+        let params: Vec<String> = params.into_iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        (mime, params)
+    };
+    assert_eq!(p("text/gemini; charset=\"utf-8\"; lang=en"),
+               ("text/gemini".to_owned(), vec!["charset=utf-8".to_owned(),
+                                               "lang=en".to_owned()]));
+    assert_eq!(p(" Text/Gemini ;CHARSET = UTF-8 ;; lang=\"en;fr\""),
+               ("text/gemini".to_owned(), vec!["charset=UTF-8".to_owned(),
+                                               "lang=en;fr".to_owned()]));
+    assert_eq!(p("text/plain; flag; a=\"x\\\"y\" junk; b=2"),
+               ("text/plain".to_owned(), vec!["a=x\"y".to_owned(),
+                                              "b=2".to_owned()]));
+    assert_eq!(p("text/plain; a=\"open"),
+               ("text/plain".to_owned(), vec!["a=open".to_owned()]));
+    assert_eq!(p(""), (String::new(), vec![]));
+
+    let r = Response { status: Status::Success, body: b"",
+                       meta: "text/gemini; Charset=\"ISO-8859-1\"" };
+    assert_eq!(r.charset().as_deref(), Some("iso-8859-1"));
+}
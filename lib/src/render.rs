@@ -0,0 +1,166 @@
+use crate::document::Document;
+use crate::protocol::Line;
+use crate::theme::Theme;
+
+/// Returns the decoration drawn before a line.  Most prefixes are only drawn
+/// on the first line of a block (when it's wrapped across several rows),
+/// with continuation rows indented to match.
+pub fn prefix(line: &Line, first: bool) -> &'static str {
+    use Line::*;
+    let p = |a, b| if first { a } else { b };
+    match line {
+        Text(_) | Pre { .. } => "",
+        H1(_) => p("# ", "  "),
+        H2(_) => p("## ", "   "),
+        H3(_) => p("### ", "    "),
+        List(_) => p("• ", "  "),
+        Quote(_) => "> ",
+        NamedLink { .. } => p("→ ", "  "),
+        BareLink(_) => "→ ",
+    }
+}
+
+/// Returns the text displayed for a line, i.e. everything but the prefix
+pub fn text<'a>(line: &Line<'a>) -> &'a str {
+    use Line::*;
+    match line {
+        Text(t) | H1(t) | H2(t) | H3(t) | List(t) | Quote(t) => t,
+        NamedLink { name, .. } => name,
+        BareLink(url) => url,
+        Pre { text, .. } => text,
+    }
+}
+
+/// Renders a single line as it's drawn in the browser: the prefix, then the
+/// text, colored with ANSI escape codes according to the theme.  The line
+/// isn't wrapped; `first` selects the prefix as in [`prefix`].
+pub fn line_to_ansi(line: &Line, first: bool, theme: &Theme) -> String {
+    format!("{}{}", prefix(line, first), theme.style(line).apply(text(line)))
+}
+
+/// Output format for a [`Document`], with a method for each kind of line, so
+/// that a document can be rendered (e.g. as HTML, or into a GUI) without
+/// matching on [`Line`] directly.  Lines are rendered one at a time, in
+/// order, by [`Renderer::render`].
+pub trait Renderer {
+    fn text(&mut self, text: &str);
+    fn link(&mut self, url: &str, name: Option<&str>);
+    fn pre(&mut self, alt: Option<&str>, text: &str);
+    /// Renders a heading, where `level` is 1, 2, or 3
+    fn heading(&mut self, level: u8, text: &str);
+    fn list_item(&mut self, text: &str);
+    fn quote(&mut self, text: &str);
+
+    fn render_line(&mut self, line: &Line) {
+        use Line::*;
+        match *line {
+            Text(t) => self.text(t),
+            BareLink(url) => self.link(url, None),
+            NamedLink { url, name } => self.link(url, Some(name)),
+            Pre { alt, text } => self.pre(alt, text),
+            H1(t) => self.heading(1, t),
+            H2(t) => self.heading(2, t),
+            H3(t) => self.heading(3, t),
+            List(t) => self.list_item(t),
+            Quote(t) => self.quote(t),
+        }
+    }
+
+    fn render(&mut self, doc: &Document) {
+        for line in doc.0.iter() {
+            self.render_line(line);
+        }
+    }
+}
+
+/// Renders a document as unwrapped plain text, with the same decorations as
+/// the browser (see [`prefix`]) and one line of output per line of text
+#[derive(Clone, Debug, Default)]
+pub struct PlainTextRenderer {
+    out: String,
+}
+
+impl PlainTextRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns everything rendered so far
+    pub fn into_string(self) -> String {
+        self.out
+    }
+
+    fn line(&mut self, prefix: &str, text: &str) {
+        self.out += prefix;
+        self.out += text;
+        self.out.push('\n');
+    }
+}
+
+impl Renderer for PlainTextRenderer {
+    fn text(&mut self, text: &str) {
+        self.line("", text)
+    }
+    fn link(&mut self, url: &str, name: Option<&str>) {
+        self.line("→ ", name.unwrap_or(url))
+    }
+    fn pre(&mut self, _alt: Option<&str>, text: &str) {
+        self.line("", text)
+    }
+    fn heading(&mut self, level: u8, text: &str) {
+        self.out.extend(std::iter::repeat_n('#', level.into()));
+        self.line(" ", text)
+    }
+    fn list_item(&mut self, text: &str) {
+        self.line("• ", text)
+    }
+    fn quote(&mut self, text: &str) {
+        self.line("> ", text)
+    }
+}
+
+#[test]
+fn test_plain_text_renderer() {
+    let doc = crate::parser::parse_document("# h1
+> quote
+## h2
+
+```py
+for i in range(10):
+    print(i)
+```
+* item
+=> gemini://example.com Example
+=> other.gmi
+### h3
+hi there").unwrap();
+    let mut r = PlainTextRenderer::new();
+    r.render(&doc);
+    assert_eq!(r.into_string(), "# h1
+> quote
+## h2
+
+for i in range(10):
+    print(i)
+• item
+→ Example
+→ other.gmi
+### h3
+hi there
+");
+}
+
+#[test]
+fn test_line_to_ansi() {
+    let theme = Theme::default();
+    let link = Line::NamedLink { url: "gemini://example.com", name: "Home" };
+    assert_eq!(line_to_ansi(&link, true, &theme),
+               "→ \x1b[38;5;13mHome\x1b[0m");
+    assert_eq!(line_to_ansi(&link, false, &theme),
+               "  \x1b[38;5;13mHome\x1b[0m");
+    assert_eq!(line_to_ansi(&Line::H1("Title"), true, &theme),
+               "# \x1b[38;5;1mTitle\x1b[0m");
+
+    // Lines without colors aren't wrapped in escape codes
+    assert_eq!(line_to_ansi(&Line::Text("plain"), true, &theme), "plain");
+}
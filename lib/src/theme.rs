@@ -0,0 +1,176 @@
+use std::convert::TryFrom;
+use std::path::Path;
+
+use crossterm::style::{Color, ContentStyle};
+use crate::protocol::Line;
+
+const THEME_FILE: &str = "theme.conf";
+
+/// Colors used to draw each kind of line.  Themes are loaded from an
+/// optional `theme.conf` in the config directory, where each line has the
+/// form `h1 = red`, `quote = white on dark_grey`, or `pre = on #202020`.
+/// Colors are crossterm's names (e.g. `dark_cyan`), `#rrggbb`, or `default`.
+#[derive(Clone, Debug)]
+pub struct Theme {
+    pub text: ContentStyle,
+    pub h1: ContentStyle,
+    pub h2: ContentStyle,
+    pub h3: ContentStyle,
+    pub list: ContentStyle,
+    pub quote: ContentStyle,
+    pub link: ContentStyle,
+    pub visited: ContentStyle, // Links which have already been followed
+    pub pre: ContentStyle,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        let c = ContentStyle::new();
+        Theme {
+            text: c.clone(),
+            h1: c.clone().foreground(Color::DarkRed),
+            h2: c.clone().foreground(Color::DarkYellow),
+            h3: c.clone().foreground(Color::DarkCyan),
+            list: c.clone(),
+            quote: c.clone().foreground(Color::White),
+            link: c.clone().foreground(Color::Magenta),
+            visited: c.clone().foreground(Color::DarkMagenta),
+            pre: c.foreground(Color::Red),
+        }
+    }
+}
+
+impl Theme {
+    /// Loads the theme file from the given directory.  A missing file means
+    /// the built-in theme; other problems are recorded as warnings.
+    pub fn load(dir: &Path, warnings: &mut Vec<String>) -> Theme {
+        let path = dir.join(THEME_FILE);
+        if !path.exists() {
+            return Theme::default();
+        }
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text, warnings),
+            Err(e) => {
+                warnings.push(format!("Could not read {}: {}",
+                                      path.display(), e));
+                Theme::default()
+            },
+        }
+    }
+
+    /// Parses the text of a theme file.  Invalid entries keep the default
+    /// style for their line type and are recorded as warnings.
+    pub fn parse(text: &str, warnings: &mut Vec<String>) -> Theme {
+        let mut t = Theme::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((k, v)) => (k.trim(), v.trim()),
+                None => {
+                    warnings.push(format!("Theme line {}: expected \
+                                           `key = value`", i + 1));
+                    continue;
+                },
+            };
+            let out = match key {
+                "text" => &mut t.text,
+                "h1" => &mut t.h1,
+                "h2" => &mut t.h2,
+                "h3" => &mut t.h3,
+                "list" => &mut t.list,
+                "quote" => &mut t.quote,
+                "link" => &mut t.link,
+                "visited" => &mut t.visited,
+                "pre" => &mut t.pre,
+                _ => {
+                    warnings.push(format!(
+                        "Theme line {}: unknown key `{}`", i + 1, key));
+                    continue;
+                },
+            };
+            match parse_style(value) {
+                Ok(s) => *out = s,
+                Err(e) => warnings.push(format!(
+                    "Theme line {}: invalid color `{}`", i + 1, e)),
+            }
+        }
+        t
+    }
+
+    /// Returns the style with which to draw the given line
+    pub fn style(&self, line: &Line) -> ContentStyle {
+        use Line::*;
+        match line {
+            Text(_) => &self.text,
+            H1(_) => &self.h1,
+            H2(_) => &self.h2,
+            H3(_) => &self.h3,
+            List(_) => &self.list,
+            Quote(_) => &self.quote,
+            NamedLink { .. } | BareLink(_) => &self.link,
+            Pre { .. } => &self.pre,
+        }.clone()
+    }
+}
+
+/// Parses a style of the form `fg`, `fg on bg`, or `on bg`, returning the
+/// offending word on failure
+fn parse_style(s: &str) -> Result<ContentStyle, &str> {
+    let (fg, bg) = match s.split_once(" on ") {
+        Some((fg, bg)) => (fg.trim(), Some(bg.trim())),
+        None => match s.strip_prefix("on ") {
+            Some(bg) => ("default", Some(bg.trim())),
+            None => (s, None),
+        },
+    };
+    let mut out = ContentStyle::new();
+    out.foreground_color = parse_color(fg)?;
+    if let Some(bg) = bg {
+        out.background_color = parse_color(bg)?;
+    }
+    Ok(out)
+}
+
+/// Parses a single color, where `default` means the terminal's own color
+fn parse_color(s: &str) -> Result<Option<Color>, &str> {
+    if s == "default" {
+        return Ok(None);
+    }
+    let rgb = s.strip_prefix('#')
+        .filter(|h| h.len() == 6)
+        .and_then(|h| u32::from_str_radix(h, 16).ok())
+        .map(|v| Color::Rgb {
+            r: (v >> 16) as u8, g: (v >> 8) as u8, b: v as u8
+        });
+    rgb.or_else(|| Color::try_from(s).ok())
+        .map(Some)
+        .ok_or(s)
+}
+
+#[test]
+fn test_parse_theme() {
+    let mut warnings = Vec::new();
+    let t = Theme::parse("# comment\n\
+                          h1 = blue\n\
+                          quote = white on dark_grey\n\
+                          pre = on #102030\n\
+                          link = purple\n\
+                          h2 = red on\n\
+                          bold = red\n", &mut warnings);
+    assert_eq!(t.h1.foreground_color, Some(Color::Blue));
+    assert_eq!(t.quote.foreground_color, Some(Color::White));
+    assert_eq!(t.quote.background_color, Some(Color::DarkGrey));
+    assert_eq!(t.pre.foreground_color, None);
+    assert_eq!(t.pre.background_color,
+               Some(Color::Rgb { r: 0x10, g: 0x20, b: 0x30 }));
+
+    // Invalid colors fall back to the default, with a warning
+    let d = Theme::default();
+    assert_eq!(t.link.foreground_color, d.link.foreground_color);
+    assert_eq!(t.h2.foreground_color, d.h2.foreground_color);
+    assert_eq!(warnings.len(), 3);
+    assert!(warnings[0].contains("purple"));
+}
@@ -1,15 +1,176 @@
+use std::convert::TryInto;
 use std::sync::{RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 use crate::Error;
 
+/// Pinned certificates are stored as this tag, the certificate's expiry time
+/// (as big-endian seconds since the epoch), then the raw certificate.  Older
+/// databases stored the raw certificate alone; since a DER certificate always
+/// starts with 0x30 (a SEQUENCE), the tag tells the two formats apart.
+const PIN_TAG: u8 = 1;
+
+/// Expiry for certificates whose validity couldn't be parsed
+const NEVER: u64 = u64::MAX;
+
 pub struct GeminiCertificateVerifier {
-    db: RwLock<sled::Tree>
+    db: RwLock<sled::Tree>,
+    strict: bool, // Check that certificates are valid for the hostname
 }
 
 impl GeminiCertificateVerifier {
     pub fn new(root: &sled::Db) -> Result<GeminiCertificateVerifier, Error> {
-        let db = RwLock::new(root.open_tree("certs")?);
-        Ok(Self { db })
+        let db = root.open_tree("certs")?;
+
+        // Upgrade entries from before expiry times were stored
+        for r in db.iter() {
+            let (k, v) = r?;
+            if v.first() != Some(&PIN_TAG) {
+                db.insert(k, encode(&v))?;
+            }
+        }
+        Ok(Self { db: RwLock::new(db), strict: false })
+    }
+
+    /// Builds a verifier which also checks that each certificate covers the
+    /// requested hostname, rejecting mismatches before they're pinned.
+    /// Certificates are still trusted on first use rather than checked
+    /// against a set of roots.
+    pub fn new_strict(root: &sled::Db)
+        -> Result<GeminiCertificateVerifier, Error>
+    {
+        Ok(Self { strict: true, ..Self::new(root)? })
+    }
+
+    /// Returns every pinned hostname and its raw certificate
+    pub fn list_trusted(&self) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        self.db.read().unwrap().iter()
+            .map(|r| {
+                let (k, v) = r?;
+                Ok((String::from_utf8_lossy(&k).into_owned(),
+                    decode(&v).1.to_vec()))
+            })
+            .collect()
+    }
+
+    /// Returns the SHA-256 fingerprint of the certificate pinned for a host
+    pub fn fingerprint(&self, host: &str) -> Result<Option<String>, Error> {
+        Ok(self.db.read().unwrap().get(host)?
+            .map(|v| fingerprint(decode(&v).1)))
+    }
+
+    /// Removes the pinned certificate for a host, so that the next
+    /// certificate it presents will be pinned instead.  Returns `false` if
+    /// there was no certificate pinned for the host.
+    pub fn forget(&self, host: &str) -> Result<bool, Error> {
+        Ok(self.db.write().unwrap().remove(host)?.is_some())
+    }
+}
+
+/// Builds the stored value for a pinned certificate
+fn encode(cert: &[u8]) -> Vec<u8> {
+    let mut v = vec![PIN_TAG];
+    v.extend_from_slice(&not_after(cert).unwrap_or(NEVER).to_be_bytes());
+    v.extend_from_slice(cert);
+    v
+}
+
+/// Splits a stored value into the certificate's expiry time and its bytes
+fn decode(v: &[u8]) -> (u64, &[u8]) {
+    match v {
+        [PIN_TAG, rest @ ..] if rest.len() >= 8 => (
+            u64::from_be_bytes(rest[..8].try_into().unwrap()),
+            &rest[8..]),
+        _ => (NEVER, v),
+    }
+}
+
+/// Splits a DER element into its tag, contents, and the remaining input
+fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&len, mut input) = input.split_first()?;
+    let len = if len < 0x80 {
+        len as usize
+    } else {
+        // Long form: the low bits are the number of length bytes
+        let n = (len & 0x7f) as usize;
+        if n == 0 || n > 4 || input.len() < n {
+            return None;
+        }
+        let len = input[..n].iter().fold(0, |a, &b| (a << 8) | b as usize);
+        input = &input[n..];
+        len
+    };
+    if input.len() < len {
+        return None;
+    }
+    Some((tag, &input[..len], &input[len..]))
+}
+
+/// Returns a certificate's notAfter time, as seconds since the epoch
+fn not_after(cert: &[u8]) -> Option<u64> {
+    validity(cert).map(|(_, end)| end)
+}
+
+/// Returns a certificate's notBefore and notAfter times, as seconds since
+/// the epoch
+fn validity(cert: &[u8]) -> Option<(u64, u64)> {
+    let (_, cert, _) = der_element(cert)?;
+    let (_, tbs, _) = der_element(cert)?;
+    let (tag, _, mut rest) = der_element(tbs)?;
+    // Skip the optional explicit version, then the serial number,
+    // signature algorithm, and issuer, to reach the validity sequence
+    if tag == 0xa0 {
+        rest = der_element(rest)?.2;
+    }
+    for _ in 0..2 {
+        rest = der_element(rest)?.2;
+    }
+    let (_, validity, _) = der_element(rest)?;
+    let (tag, start, validity) = der_element(validity)?;
+    let start = der_time(tag, start)?;
+    let (tag, end, _) = der_element(validity)?;
+    Some((start, der_time(tag, end)?))
+}
+
+/// Parses a DER UTCTime or GeneralizedTime (given its tag and contents) to
+/// seconds since the epoch
+fn der_time(tag: u8, time: &[u8]) -> Option<u64> {
+    let time = std::str::from_utf8(time).ok()?.strip_suffix('Z')?;
+
+    // UTCTime has a two-digit year; GeneralizedTime has four
+    let (year, time) = match tag {
+        0x17 => {
+            let y: i64 = time.get(..2)?.parse().ok()?;
+            (if y >= 50 { 1900 + y } else { 2000 + y }, &time[2..])
+        },
+        0x18 => (time.get(..4)?.parse().ok()?, &time[4..]),
+        _ => return None,
+    };
+    if time.len() != 10 || !time.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
     }
+    let field = |i: usize| time[i..i + 2].parse::<i64>().unwrap();
+    let days = days_from_civil(year, field(0), field(2));
+    let secs = days * 86400 + field(4) * 3600 + field(6) * 60 + field(8);
+    Some(secs.max(0) as u64)
+}
+
+/// Converts a date to days since the epoch, in the proleptic Gregorian
+/// calendar (Howard Hinnant's algorithm)
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Returns the SHA-256 fingerprint of a certificate as a hex string
+pub fn fingerprint(cert: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA256, cert).as_ref().iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
 }
 
 impl rustls::ServerCertVerifier for GeminiCertificateVerifier {
@@ -25,23 +186,151 @@ impl rustls::ServerCertVerifier for GeminiCertificateVerifier {
         if presented_certs.is_empty() {
             return Err(TLSError::NoCertificatesPresented)
         }
+        if self.strict {
+            webpki::EndEntityCert::from(presented_certs[0].as_ref())
+                .and_then(|c| c.verify_is_valid_for_dns_name(dns_name))
+                .map_err(TLSError::WebPKIError)?;
+        }
 
         let dns_name = dns_name.to_owned();
         let d : &str = AsRef::<str>::as_ref(&dns_name);
-        let r = self.db.read().unwrap().get(&d)
+        let r = self.db.read().unwrap().get(d)
             .map_err(|e| TLSError::General(e.to_string()))?;
 
-        if let Some(c) = r {
-            if c == presented_certs[0].as_ref() {
+        // Once the pinned certificate has expired, whatever certificate the
+        // server presents next (presumably a renewal) is pinned in its place
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let presented = presented_certs[0].as_ref();
+        match r.as_ref().map(|v| decode(v)) {
+            Some((_, c)) if c == presented =>
+                Ok(ServerCertVerified::assertion()),
+            Some((expires, _)) if expires > now =>
+                Err(TLSError::WebPKIError(webpki::Error::CertNotValidForName)),
+            _ => {
+                // There's nothing to compare a new certificate against, but
+                // one which is outside its validity period is never pinned
+                // (certificates whose dates can't be parsed are let through)
+                match validity(presented) {
+                    Some((start, _)) if now < start => return Err(
+                        TLSError::WebPKIError(webpki::Error::CertNotValidYet)),
+                    Some((_, end)) if now > end => return Err(
+                        TLSError::WebPKIError(webpki::Error::CertExpired)),
+                    _ => (),
+                }
+                self.db.write().unwrap()
+                    .insert(d, encode(presented))
+                    .map_err(|e| TLSError::General(e.to_string()))?;
                 Ok(ServerCertVerified::assertion())
-            } else {
-                Err(TLSError::WebPKIError(webpki::Error::CertNotValidForName))
-            }
-        } else {
-            self.db.write().unwrap()
-                .insert(d, presented_certs[0].as_ref())
-                .map_err(|e| TLSError::General(e.to_string()))?;
-            Ok(ServerCertVerified::assertion())
+            },
         }
     }
 }
+
+#[test]
+fn test_trust_management() {
+    use rustls::ServerCertVerifier;
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let v = GeminiCertificateVerifier::new(&db).unwrap();
+    let roots = rustls::RootCertStore::empty();
+    let name = webpki::DNSNameRef::try_from_ascii_str("example.com").unwrap();
+    let first = rustls::Certificate(b"first cert".to_vec());
+    let second = rustls::Certificate(b"second cert".to_vec());
+
+    assert!(v.verify_server_cert(&roots, &[first], name, &[]).is_ok());
+    assert_eq!(v.list_trusted().unwrap(),
+               vec![("example.com".to_owned(), b"first cert".to_vec())]);
+    assert_eq!(v.fingerprint("example.com").unwrap().unwrap(),
+        "6cdd30fbc06a50182090c8802e3314263ff7287089f9d492613a92ca173e4a37");
+    assert_eq!(v.fingerprint("other.com").unwrap(), None);
+
+    // A rotated certificate is rejected until the old one is forgotten
+    let certs = [second];
+    assert!(v.verify_server_cert(&roots, &certs, name, &[]).is_err());
+    assert!(v.forget("example.com").unwrap());
+    assert!(!v.forget("example.com").unwrap());
+    assert!(v.verify_server_cert(&roots, &certs, name, &[]).is_ok());
+    assert_eq!(v.list_trusted().unwrap()[0].1, b"second cert".to_vec());
+}
+
+#[test]
+fn test_strict_hostname() {
+    use rustls::ServerCertVerifier;
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let roots = rustls::RootCertStore::empty();
+    let cert = rustls::Certificate(
+        include_bytes!("../testdata/example.com.der").to_vec());
+    let name = webpki::DNSNameRef::try_from_ascii_str("example.com").unwrap();
+    let other = webpki::DNSNameRef::try_from_ascii_str("other.com").unwrap();
+    let certs = [cert];
+
+    // The lax verifier pins whatever it's given
+    let v = GeminiCertificateVerifier::new(&db).unwrap();
+    assert!(v.verify_server_cert(&roots, &certs, other, &[]).is_ok());
+    assert!(v.forget("other.com").unwrap());
+
+    // The strict verifier rejects a certificate for a different name, and
+    // doesn't pin it
+    let v = GeminiCertificateVerifier::new_strict(&db).unwrap();
+    assert!(v.verify_server_cert(&roots, &certs, other, &[]).is_err());
+    assert_eq!(v.fingerprint("other.com").unwrap(), None);
+    assert!(v.verify_server_cert(&roots, &certs, name, &[]).is_ok());
+
+    // Certificates which can't be parsed are rejected too
+    let junk = [rustls::Certificate(b"not a cert".to_vec())];
+    assert!(v.verify_server_cert(&roots, &junk, name, &[]).is_err());
+}
+
+#[test]
+fn test_not_after() {
+    let cert = include_bytes!("../testdata/example.com.der");
+    assert_eq!(not_after(cert), Some(4945661660)); // 2126-09-21T10:54:20Z
+    let cert = include_bytes!("../testdata/expired.der");
+    assert_eq!(not_after(cert), Some(1577836800)); // 2020-01-01T00:00:00Z
+    assert_eq!(not_after(b"not a cert"), None);
+    assert_eq!(validity(cert), Some((1546300800, 1577836800))); // 2019-2020
+}
+
+#[test]
+fn test_expired_pin() {
+    use rustls::ServerCertVerifier;
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let v = GeminiCertificateVerifier::new(&db).unwrap();
+    let roots = rustls::RootCertStore::empty();
+    let name = webpki::DNSNameRef::try_from_ascii_str("example.com").unwrap();
+    let expired = [rustls::Certificate(
+        include_bytes!("../testdata/expired.der").to_vec())];
+    let renewed = [rustls::Certificate(
+        include_bytes!("../testdata/example.com.der").to_vec())];
+
+    // An expired certificate is never pinned on first use
+    assert!(v.verify_server_cert(&roots, &expired, name, &[]).is_err());
+    assert!(v.list_trusted().unwrap().is_empty());
+
+    // An expired pin (from when it was still valid) is replaced by the next
+    // certificate, which is then enforced as usual
+    db.open_tree("certs").unwrap()
+        .insert("example.com", encode(&expired[0].0)).unwrap();
+    assert!(v.verify_server_cert(&roots, &expired, name, &[]).is_ok());
+    assert!(v.verify_server_cert(&roots, &renewed, name, &[]).is_ok());
+    assert_eq!(v.list_trusted().unwrap()[0].1, renewed[0].0);
+    assert!(v.verify_server_cert(&roots, &expired, name, &[]).is_err());
+}
+
+#[test]
+fn test_migrate_pins() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let cert = include_bytes!("../testdata/example.com.der");
+    db.open_tree("certs").unwrap().insert("example.com", &cert[..]).unwrap();
+
+    let v = GeminiCertificateVerifier::new(&db).unwrap();
+    assert_eq!(v.list_trusted().unwrap(),
+               vec![("example.com".to_owned(), cert.to_vec())]);
+    let stored = db.open_tree("certs").unwrap()
+        .get("example.com").unwrap().unwrap();
+    assert_eq!(decode(&stored), (4945661660, &cert[..]));
+}
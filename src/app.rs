@@ -1,118 +1,790 @@
+use std::borrow::Cow;
 use std::io::Write;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
 use std::sync::{Arc};
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
 
-use silo::tofu::GeminiCertificateVerifier;
+use silo::cache::Cache;
+use silo::dns::DnsCache;
+use silo::tofu::{self, GeminiCertificateVerifier};
 use silo::fetch;
-use silo::parser::{parse_response, parse_text_gemini};
-use silo::protocol::{Line, Status};
+use silo::identity::Identities;
+use silo::markdown;
+use silo::parser::{parse_response, parse_text_gemini_lossy, ParseOptions};
+use silo::protocol::{self, Line, Status};
 
+use crate::breadcrumb;
+use crate::caps::Caps;
+use crate::clipboard::{self, Clipboard};
 use crate::command::Command;
-use silo::document::Document;
+use crate::config::{Config, EmptyInput, TlsVersion};
+use crate::history::{self, History};
+use silo::document::{Document, DocumentBuilder};
+use crate::idle::Idle;
 use crate::input;
+use crate::prefetch::Prefetch;
+use crate::preview;
+use crate::print;
+use crate::screen::Screen;
+use crate::source::{self, Source};
+use crate::tab::{self, Positions, Tab};
+use crate::transcript::{Outcome, Transcript};
 use crate::view::View;
+use crate::visited::Visited;
 
 use crossterm::{
     cursor,
     execute,
     terminal,
-    event::{read, Event, KeyCode, KeyEvent, KeyModifiers},
+    event::{self, read, Event, KeyCode, KeyEvent, KeyModifiers, MouseButton,
+            MouseEvent},
     terminal::{Clear, ClearType},
     style::{style, Color, Print, PrintStyledContent},
 };
 
+/// Number of hosts whose addresses are cached for the session
+const DNS_CACHE_SIZE: usize = 64;
+
 pub struct App {
     config: Arc<rustls::ClientConfig>,
+    verifier: Arc<GeminiCertificateVerifier>,
+    options: Config,
+    caps: Caps,
+    cache: Cache,
+    clipboard: Box<dyn Clipboard>,
+    dns: Arc<DnsCache>,
+    transcript: Transcript,
+    visited: Visited,
+    history: History,
+    positions: Positions,
+    prefetch: Prefetch,
+    identities: Identities,
+
+    /// Host of the most recent TLS connection, and what it negotiated
+    tls: Option<(String, fetch::TlsInfo)>,
+
+    /// Serve gemini pages only from the cache, without touching the network
+    offline: bool,
+
+    /// Command to run, and text to find, once the first page is shown
+    startup: Option<Command>,
+    search: Option<String>,
+
+    /// Open tabs, and the index of the one on screen
+    tabs: Vec<Tab>,
+    tab: usize,
+    pending_g: bool, // Was the last key 'g', starting a 'gt' or 'gT'?
+
+    /// Whether the page being loaded goes in a new tab, which is only
+    /// opened once there's a page to put in it
+    new_tab: bool,
+
+    /// Columns of the status row taken up by each breadcrumb segment, with
+    /// the URL which that segment leads to
+    crumbs: Vec<(Range<usize>, url::Url)>,
+
+    /// Message to show in the command bar once the next page is displayed
+    notice: Option<Result<String>>,
     has_cmd_error: bool,
     size: (u16, u16), // width, height
 }
 
 impl App {
-    pub fn new(db: &sled::Db) -> Result<App> {
-        let mut config = rustls::ClientConfig::new();
-        let verifier = GeminiCertificateVerifier::new(&db)?;
-        config.dangerous().set_certificate_verifier(Arc::new(verifier));
-        let config = Arc::new(config);
+    pub fn new(db: &sled::Db, mut options: Config) -> Result<App> {
+        let verifier = verifier(db, &options)?;
+        let config = client_config(verifier.clone(), options.tls_min_version);
+        let cache = Cache::new(db,
+            Duration::from_secs(options.cache_max_age))?;
+        let clipboard = clipboard::from_name(&options.clipboard);
+        let dns = Arc::new(DnsCache::new(
+            Duration::from_secs(options.dns_ttl), DNS_CACHE_SIZE));
+        let transcript = match options.transcript.clone() {
+            None => Transcript::default(),
+            Some(path) => Transcript::to_file(&path).unwrap_or_else(|e| {
+                options.warnings.push(format!(
+                    "Could not open transcript {}: {}", path.display(), e));
+                Transcript::default()
+            }),
+        };
+        let visited = Visited::new(
+            Some(db).filter(|_| options.remember_visited))?;
+        let history = History::new(db, options.history_size)?;
+        let prefetch = Prefetch::new(options.prefetch_max);
+        let identities = Identities::new(db)?;
+        let caps = Caps::detect();
+        options.ascii |= !caps.unicode;
         let size = terminal::size()
             .expect("Could not get terminal size");
-        Ok(App { config, has_cmd_error: false, size })
+        // Report any problems from loading the config file once the first
+        // page is on screen, since there's nowhere to show them earlier
+        let notice = if options.warnings.is_empty() {
+            None
+        } else {
+            Some(Err(anyhow!("{}", options.warnings.join("; "))))
+        };
+        Ok(App { config, verifier, options, cache, clipboard, dns, transcript,
+                 visited, history, positions: Positions::default(),
+                 prefetch, identities, tls: None, offline: false,
+                 caps,
+                 startup: None, search: None,
+                 tabs: vec![Tab::default()], tab: 0, pending_g: false,
+                 new_tab: false,
+                 crumbs: Vec::new(),
+                 notice, has_cmd_error: false, size })
     }
 
-    pub fn run(&mut self, mut target: url::Url) -> Result<()> {
+    /// Sets a command to run and text to find once the first page has been
+    /// drawn, as if they'd been typed there
+    pub fn startup(&mut self, cmd: Option<Command>, search: Option<String>) {
+        self.startup = cmd;
+        self.search = search;
+    }
+
+    pub fn run(&mut self, target: url::Url) -> Result<()> {
+        let mut cmd = Command::Load(target);
         loop {
-            // TODO: don't use a clone here?
-            match self.fetch(target.clone())? {
+            let r = match cmd {
                 Command::Exit => break Ok(()),
-                Command::Load(s) => target = s,
-                Command::TryLoad(s) => {
-                    let mut url = url::Url::parse(&s);
-                    if url == Err(url::ParseError::RelativeUrlWithoutBase) {
-                        url = target.join(&s);
-                    }
-                    match url {
-                        // TODO: how to display error here?
-                        Err(e) => continue,
-                        Ok(url) => target = url,
+                Command::Load(s) => self.fetch(s),
+                Command::Go(url) => match source::coerce_typed(url,
+                    &self.options.scheme_aliases, self.options.default_port)
+                {
+                    Ok(url) => self.fetch(url),
+                    Err(e) => Err(e.into()),
+                },
+                Command::Home => self.fetch(self.options.home.clone()),
+                Command::LoadTab(url) => {
+                    self.new_tab = true;
+                    let r = self.fetch(url);
+                    self.new_tab = false;
+                    r
+                },
+                Command::SwitchTab(i) => {
+                    self.tab = i;
+                    self.show_tab()
+                },
+                Command::CloseTab => {
+                    self.tabs.remove(self.tab);
+                    if self.tabs.is_empty() {
+                        Ok(Command::Exit)
+                    } else {
+                        self.tab = self.tab.min(self.tabs.len() - 1);
+                        self.show_tab()
                     }
                 },
+                Command::Certs => self.certs(),
+                Command::Identities => self.show_identities(),
+                Command::Links => self.links(),
+                Command::History => self.show_history(),
+                Command::ViewSource => {
+                    // The page and its source each keep their own position
+                    let t = &mut self.tabs[self.tab];
+                    t.raw = match t.raw.take() {
+                        Some(pos) => {
+                            t.pos = pos;
+                            None
+                        },
+                        None => Some(std::mem::take(&mut t.pos)),
+                    };
+                    self.show_tab()
+                },
+                Command::Transcript => Ok(self.show_transcript()),
+                // Everything else is handled while a page is displayed, so
+                // getting one here is a bug, but not one worth crashing for
+                cmd => Err(anyhow!("Can't run {:?} here", cmd)),
+            };
+            cmd = self.recover(r)?;
+        }
+    }
+
+    /// Passes on the command which ended a page, or if something failed
+    /// (e.g. fetching a dead link), shows the error over the current tab's
+    /// page instead.  Failing to use the terminal can't be shown, so that
+    /// still ends the session.
+    fn recover(&mut self, r: Result<Command>) -> Result<Command> {
+        match r {
+            Ok(cmd) => Ok(cmd),
+            Err(e) if e.is::<crossterm::ErrorKind>() => Err(e),
+            Err(e) => {
+                self.notice = Some(Err(e));
+                self.show_tab()
+            },
+        }
+    }
+
+    /// Offers to save a non-text response body to a file, then returns to
+    /// the previous page
+    fn download(&mut self, url: &url::Url, meta: &str, body: &[u8])
+        -> Result<Command>
+    {
+        self.save(url, meta, body)?;
+        self.show_tab()
+    }
+
+    /// Fetches a URL and offers to save its body to a file, regardless of
+    /// its MIME type, without leaving the current page
+    fn fetch_to_file(&mut self, mut url: url::Url) -> Result<()> {
+        for _ in 0..5 {
+            let (_, plaintext) = self.read(&url, false)?;
+            let response = parse_response(&plaintext)?;
+            match response.status {
+                Status::Success => {
+                    self.save(&url, response.meta, response.body)?;
+                    return Ok(());
+                },
+                Status::RedirectTemporary | Status::RedirectPermanent =>
+                    url = fetch::redirect(&url, response.meta)?,
+                status => return Err(anyhow!("{}: {}", status,
+                                             response.meta)),
+            }
+        }
+        Err(anyhow!("Too many redirects"))
+    }
+
+    /// Prompts for a path (defaulting to the last segment of the URL's path)
+    /// and writes the body there, leaving the outcome in the notice
+    fn save(&mut self, url: &url::Url, meta: &str, body: &[u8])
+        -> Result<()>
+    {
+        let default = default_filename(Some(url));
+        let path = match self.prompt(
+            &format!("Save {} to [{}]: ", meta, default))?
+        {
+            None => {
+                self.notice = Some(Ok("Download cancelled".to_owned()));
+                return Ok(());
+            },
+            Some(p) if p.is_empty() => PathBuf::from(default),
+            Some(p) => PathBuf::from(p),
+        };
+
+        if path.exists() {
+            let msg = format!("{} exists; overwrite? (y/n) ", path.display());
+            if self.prompt(&msg)?.as_deref() != Some("y") {
+                self.notice = Some(Ok("Download cancelled".to_owned()));
+                return Ok(());
+            }
+        }
+
+        self.notice = Some(std::fs::write(&path, body)
+            .map(|_| format!("Saved {} bytes to {}", body.len(),
+                             path.display()))
+            .map_err(|e| anyhow!("Could not write {}: {}",
+                                 path.display(), e)));
+        Ok(())
+    }
+
+    /// Saves the current page for `:save` or `:save-text`, showing the
+    /// outcome in the command bar
+    fn save_page(&mut self, doc: &Document, path: Option<&str>,
+                 rendered: bool, generated: bool)
+    {
+        match self.write_page(doc, path, rendered, generated) {
+            Ok(msg) => self.set_cmd_message(&msg),
+            Err(err) => self.set_cmd_error(&format!("{}", err)),
+        }
+    }
+
+    /// Writes the current tab's response body (or, if `rendered` is set,
+    /// the displayed page as wrapped text) to a file without prompting.  If
+    /// no path is given, one is derived from the URL, but an existing file
+    /// at that path isn't overwritten.
+    ///
+    /// A `generated` page (like `:history`) has no response body, so the
+    /// displayed document is written out as text/gemini instead.
+    fn write_page(&self, doc: &Document, path: Option<&str>, rendered: bool,
+                  generated: bool)
+        -> Result<String>
+    {
+        let t = &self.tabs[self.tab];
+        let body = if rendered {
+            print::render(doc, self.size.0 as usize).into_bytes()
+        } else if generated {
+            doc.to_string().into_bytes()
+        } else {
+            let (_, data) = t.page.as_ref()
+                .ok_or_else(|| anyhow!("No page to save"))?;
+            parse_response(data)?.body.to_vec()
+        };
+        let path = match path {
+            Some(p) => PathBuf::from(p),
+            None => {
+                let url = if generated { None } else { t.url.as_ref() };
+                let p = PathBuf::from(default_filename(url));
+                let p = if rendered {
+                    p.with_extension("txt")
+                } else if generated {
+                    p.with_extension("gmi")
+                } else {
+                    p
+                };
+                if p.exists() {
+                    return Err(anyhow!("{} exists; give a path to \
+                                        overwrite it", p.display()));
+                }
+                p
+            },
+        };
+        std::fs::write(&path, &body)
+            .map_err(|e| anyhow!("Could not write {}: {}",
+                                 path.display(), e))?;
+        Ok(format!("Saved {} bytes to {}", body.len(), path.display()))
+    }
+
+    /// Displays the page in the current tab, restoring its position
+    fn show_tab(&mut self) -> Result<Command> {
+        let (source, data) = match &self.tabs[self.tab].page {
+            Some((source, data)) => (source.clone(), data.clone()),
+            None => {
+                let doc = Document::new(vec![Line::Text("")]);
+                return Ok(self.display_doc(&doc, &Source::Data));
+            },
+        };
+        let response = parse_response(&data)?;
+        let (body, truncated) = fetch::decode_body_lossy(response.body);
+        let mut notes = Vec::new();
+        if truncated {
+            notes.push("Page was cut off mid-character".to_owned());
+        }
+        if let Cow::Owned(_) = body {
+            notes.push("Invalid UTF-8 was replaced".to_owned());
+        }
+        // Only UTF-8 (and its ASCII subset) is decoded
+        if let Some(charset) = response.charset()
+            .filter(|c| c != "utf-8" && c != "us-ascii")
+        {
+            notes.push(format!("Page is in {}, but was read as UTF-8",
+                               charset));
+        }
+        if self.tabs[self.tab].raw.is_some() {
+            let doc = Document::new(vec![Line::Pre { alt: None, text: &body }]);
+            self.notice = Some(Ok("Viewing source (Ctrl-U to return)"
+                                  .to_owned()));
+            let pos = self.tabs[self.tab].pos;
+            let (cmd, pos) = self.display(&doc, &source, pos, None, false);
+            self.tabs[self.tab].pos = pos;
+            return Ok(cmd);
+        }
+        let (doc, cut, errors) = self.parse_body(response.meta, &body);
+        if errors > 0 {
+            notes.push(format!("Recovered from {} malformed line{}", errors,
+                               if errors == 1 { "" } else { "s" }));
+        }
+        if cut {
+            notes.push(format!("Document truncated at {} lines",
+                               doc.0.len()));
+        }
+        if !notes.is_empty() {
+            self.notice = Some(Ok(notes.join("; ")));
+        }
+
+        let t = &mut self.tabs[self.tab];
+        t.title = tab::title(&doc, t.url.as_ref(), self.options.ascii);
+        let anchor = t.fragment.take().and_then(|f| tab::anchor(&doc, &f));
+        if self.caps.set_title {
+            let title = format!("{} - titan", t.title);
+            execute!(&mut std::io::stdout(), terminal::SetTitle(&title))
+                .expect("Could not set title");
+        }
+        let pos = t.pos;
+        let (cmd, pos) = self.display(&doc, &source, pos, anchor, false);
+        let t = &mut self.tabs[self.tab];
+        t.pos = pos;
+        if let Some(url) = &t.url {
+            self.positions.set(url, pos);
+        }
+        Ok(cmd)
+    }
+
+    /// Parses a response body for display, also returning whether it was
+    /// cut short for being too long and how many malformed lines were kept
+    /// as plain text
+    fn parse_body<'a>(&self, meta: &str, body: &'a str)
+        -> (Document<'a>, bool, usize)
+    {
+        let mime = protocol::mime_type(meta);
+        if mime == "text/gemini" {
+            let opts = ParseOptions { dash_lists: self.options.dash_lists,
+                                      ..Default::default() };
+            let (rest, doc, errors) = parse_text_gemini_lossy(body, opts);
+            (doc, !rest.is_empty(), errors)
+        } else if mime == "text/markdown" {
+            (markdown::parse_markdown(body), false, 0)
+        } else {
+            // Read other text/ MIME types as a single preformatted line
+            (Document(vec![Line::Pre { alt: None, text: body }]), false, 0)
+        }
+    }
+
+    /// Displays a message in the command bar and reads a line of input,
+    /// which is `None` if the user cancelled it
+    fn prompt(&mut self, msg: &str) -> Result<Option<String>> {
+        execute!(&mut std::io::stdout(),
+            cursor::MoveTo(0, self.cmd_row()),
+            Clear(ClearType::CurrentLine),
+            Print(msg),
+        ).expect("Could not draw prompt");
+        let out = input::Input::new().run();
+        self.clear_cmd();
+        out
+    }
+
+    /// Asks a yes-or-no question in the command bar, returning whether it
+    /// was answered with 'y'
+    fn confirm(&mut self, msg: &str) -> Result<bool> {
+        Ok(self.prompt(msg)?.as_deref() == Some("y"))
+    }
+
+    /// Displays the list of pinned certificates as a document
+    fn certs(&mut self) -> Result<Command> {
+        let mut b = DocumentBuilder::new()
+            .h1("Trusted certificates")
+            .text("Use :forget <host> to remove a pinned certificate, so \
+                   that the next certificate presented by that host is \
+                   trusted instead.")
+            .text("");
+        for (host, cert) in self.verifier.list_trusted()? {
+            b = b.h2(host).text(tofu::fingerprint(&cert));
+        }
+        Ok(self.display_doc(&b.document(), &Source::Data))
+    }
+
+    /// Displays the client certificate identities, and the hosts to which
+    /// each is offered, as a document
+    fn show_identities(&mut self) -> Result<Command> {
+        let mut b = DocumentBuilder::new()
+            .h1("Identities")
+            .text("Use :identity <name> to offer an identity's certificate \
+                   to the current page's host (or :identity alone to stop), \
+                   and :identity-add <name> <file> to add one from a PEM \
+                   file holding a certificate and its private key.")
+            .text("");
+        let ids = self.identities.list()?;
+        if ids.is_empty() {
+            b = b.text("There are no identities yet.");
+        }
+        let hosts = self.identities.hosts()?;
+        for (name, fingerprint) in ids {
+            b = b.h2(name.as_str()).text(fingerprint);
+            for (host, _) in hosts.iter().filter(|(_, n)| *n == name) {
+                b = b.list(host.as_str());
             }
         }
+        Ok(self.display_doc(&b.document(), &Source::Data))
+    }
+
+    /// Sets (or clears) the identity offered to the host of the current
+    /// page, returning the page's URL so that it can be reloaded
+    fn identity(&mut self, source: &Source, name: Option<&str>)
+        -> Result<url::Url>
+    {
+        let url = source.url()
+            .filter(|u| u.scheme() == "gemini")
+            .ok_or_else(|| anyhow!("This page isn't from a gemini host"))?;
+        let host = url.host_str().unwrap_or("");
+        self.identities.activate(host, name)?;
+        self.notice = Some(Ok(match name {
+            Some(name) => format!("Using identity {} for {}", name, host),
+            None => format!("Not using an identity for {}", host),
+        }));
+        Ok(url.clone())
+    }
+
+    /// Displays the links on the current tab's page as a document, with
+    /// relative links resolved so that they can be followed from it
+    fn links(&mut self) -> Result<Command> {
+        let title = self.tabs[self.tab].title.clone();
+        let mut b = DocumentBuilder::new()
+            .h1(format!("Links on {}", title))
+            .text("");
+        let mut count = 0;
+        if let Some((source, data)) = self.tabs[self.tab].page.clone() {
+            let response = parse_response(&data)?;
+            let (body, _) = fetch::decode_body_lossy(response.body);
+            let (doc, ..) = self.parse_body(response.meta, &body);
+            for (url, name) in doc.links() {
+                let url = source.resolve(url)
+                    .map(|u| u.to_string())
+                    .unwrap_or_else(|_| url.to_owned());
+                b = match name {
+                    Some(name) => b.link(url, name),
+                    None => b.bare_link(url),
+                };
+                count += 1;
+            }
+        }
+        if count == 0 {
+            b = b.text("This page has no links.");
+        }
+        Ok(self.display_doc(&b.document(), &Source::Data))
+    }
+
+    /// Displays the persistent history as a document of links, with the
+    /// most recent visits first
+    fn show_history(&mut self) -> Result<Command> {
+        let mut b = DocumentBuilder::new()
+            .h1("History")
+            .text("");
+        let entries = self.history.entries()?;
+        if entries.is_empty() {
+            b = b.text("No pages have been visited yet.");
+        }
+        for (url, time) in entries {
+            let name = format!("{} {}", history::format_time(time), url);
+            b = b.link(url, name);
+        }
+        Ok(self.display_doc(&b.document(), &Source::Data))
+    }
+
+    /// Displays the current session's transcript as a document
+    fn show_transcript(&mut self) -> Command {
+        let entries = self.transcript.entries().to_vec();
+        let mut lines = vec![
+            Line::H1("Session transcript"),
+            Line::Text("Time, URL, status, meta, elapsed time, body size, and \
+                        TLS version of each request made during this \
+                        session."),
+            Line::Text(""),
+        ];
+        lines.extend(entries.iter().map(|e| Line::Text(e)));
+        self.display_doc(&Document::new(lines), &Source::Data)
+    }
+
+    /// Copies a link target (resolved against the page it's on) or, if
+    /// there isn't one, the page's own URL to the clipboard
+    fn yank(&mut self, source: &Source, target: Option<&str>) {
+        let url = match target {
+            Some(t) => source.resolve(t),
+            None => source.url().cloned()
+                .ok_or_else(|| anyhow!("This page has no URL")),
+        };
+        let r = url.and_then(|url| {
+            self.clipboard.copy(url.as_str())?;
+            Ok(url)
+        });
+        match r {
+            Ok(url) => self.set_cmd_message(&format!("Copied {}", url)),
+            Err(err) => self.set_cmd_error(&format!("{}", err)),
+        }
+    }
+
+    fn forget(&mut self, host: &str) {
+        match self.verifier.forget(host) {
+            Ok(true) => self.set_cmd_message(
+                &format!("Forgot certificate for {}", host)),
+            Ok(false) => self.set_cmd_error(
+                &format!("No certificate pinned for {}", host)),
+            Err(err) => self.set_cmd_error(&format!("{}", err)),
+        }
     }
 
     pub fn fetch(&mut self, url: url::Url) -> Result<Command> {
-        self.fetch_(url, 0)
+        self.fetch_(url, 0, false, Vec::new())
     }
 
-    fn fetch_(&mut self, url: url::Url, depth: u8) -> Result<Command> {
+    /// Reads the raw response for a URL, recording it in the transcript.
+    /// If the server asks us to slow down, waits and retries (up to the
+    /// configured limit), returning the final response either way.
+    fn read(&mut self, url: &url::Url, redact: bool)
+        -> Result<(Source, Vec<u8>)>
+    {
+        let mut retries = 0;
+        loop {
+            let start = Instant::now();
+            let mut tls = None;
+            let mut prefetched = false;
+            let (source, plaintext) = match url.scheme() {
+                "file" => source::read_file(url)?,
+                "data" => (Source::Data, source::read_data(url)?),
+                // Offline, cached pages are used however old they are.  A
+                // miss is an error rather than a page, so that it isn't
+                // recorded as a visit.
+                _ if self.offline => match self.cache.get(url)? {
+                    Some(entry) => (Source::Network(url.clone()), entry.data),
+                    None => return Err(anyhow!(
+                        "{} isn't cached, so it can't be shown while offline",
+                        url)),
+                },
+                _ => {
+                    // Pages read with an identity may be private to it, so
+                    // they're neither prefetched nor cached
+                    let host = url.host_str().unwrap_or("");
+                    let identity = self.identities.active(host)?.is_some();
+                    let ready = if identity {
+                        None
+                    } else {
+                        self.prefetch.take(url)
+                    };
+                    prefetched = ready.is_some();
+                    let plaintext = match ready {
+                        Some(p) => p,
+                        None => {
+                            self.show_loading(url, redact);
+                            let config = self.identities.config_for(
+                                &self.config, host)?;
+                            let r = if identity {
+                                fetch::read_session(&config, url,
+                                                    Some(&self.dns))
+                            } else {
+                                fetch::read_cached(&config, url, &self.cache,
+                                                   Some(&self.dns))
+                            };
+                            let (p, info) = match r {
+                                Ok(r) => r,
+                                Err(e) => {
+                                    self.record(url,
+                                        Outcome::Failed(&e.to_string()),
+                                        start, redact);
+                                    return Err(e.into());
+                                },
+                            };
+                            if let Some(info) = info {
+                                let host = url.host_str().unwrap_or("");
+                                self.tls = Some((host.to_owned(), info));
+                            }
+                            tls = info;
+                            p
+                        },
+                    };
+                    (Source::Network(url.clone()), plaintext)
+                },
+            };
+            let response = match parse_response(&plaintext) {
+                Ok(r) => r,
+                Err(e) => {
+                    self.record(url, Outcome::Failed(&e.to_string()), start,
+                                redact);
+                    return Err(e.into());
+                },
+            };
+            self.record(url, Outcome::Response {
+                    status: response.status, meta: response.meta,
+                    bytes: response.body.len(), tls, prefetched,
+                }, start, redact);
+
+            if response.status != Status::SlowDown ||
+               retries >= self.options.slow_down_retries
+            {
+                return Ok((source, plaintext));
+            }
+            let max = Duration::from_secs(self.options.slow_down_max_wait);
+            let wait = slow_down_wait(response.meta, retries, max);
+            self.set_cmd_message(&format!("Rate limited, retrying in {}s",
+                                          wait.as_secs()));
+            std::thread::sleep(wait);
+            retries += 1;
+        }
+    }
+
+    /// Records a request which started at `start` in the transcript.
+    /// Failing to write the transcript file doesn't stop the page from
+    /// loading, so it's only reported.
+    fn record(&mut self, url: &url::Url, outcome: Outcome, start: Instant,
+              redact: bool)
+    {
+        let r = self.transcript.record(url, outcome, start.elapsed(), redact);
+        if let Err(e) = r {
+            self.notice = Some(Err(anyhow!("Could not write transcript: {}",
+                                           e)));
+        }
+    }
+
+    /// Fetches and displays a URL, following redirects and input prompts.
+    /// `redact` indicates that the URL's query holds sensitive input, which
+    /// must be kept out of the transcript.  `chain` holds the URLs which
+    /// redirected to this one, starting with the URL that was requested, so
+    /// that the final page can say where it came from.
+    fn fetch_(&mut self, url: url::Url, depth: u8, redact: bool,
+              mut chain: Vec<url::Url>) -> Result<Command>
+    {
         if depth >= 5 {
             return Err(anyhow!("Too much recursion"));
         }
 
-        let plaintext = fetch::read(&self.config, &url)?;
+        let url = self.coerce(url)?;
+        let (source, plaintext) = self.read(&url, redact)?;
         let response = parse_response(&plaintext)?;
+        // Sensitive input isn't written to the database
+        if !redact {
+            self.visited.insert(&url)?;
+        }
 
         use Status::*;
+        if !matches!(response.status,
+                     RedirectTemporary | RedirectPermanent | Input |
+                     SensitiveInput)
+        {
+            if let Some(msg) = redirect_notice(&chain, &url) {
+                self.notice = Some(Ok(msg));
+            }
+        }
+        if let Some(r) = fetch::as_redirect(&url, &response)? {
+            // A permanent redirect also moves the URL's history entry
+            if !redact {
+                self.history.redirected(&url, &r)?;
+            }
+            chain.push(url);
+            return self.fetch_(r.target, depth + 1, false, chain);
+        }
         match response.status {
-            RedirectTemporary | RedirectPermanent => {
-                let next = url::Url::parse(response.meta)?;
-                self.fetch_(next, depth + 1)
-            },
-
             Input | SensitiveInput => {
-                if let Some(input) = input::Input::new().run() {
-                    // Serialize the input string and set it as the query param
-                    use url::form_urlencoded::byte_serialize;
-                    let input: String = byte_serialize(input.as_bytes())
-                        .collect();
-
-                    let mut url = url;
-                    url.set_query(Some(&input));
-                    self.fetch_(url, depth + 1)
+                // Cancelling the prompt isn't an error: it abandons this
+                // navigation and returns to the page we were on
+                let secret = redact || response.status == SensitiveInput;
+                let input = input::Input::with_text(
+                    &previous_query(&url, secret)).run()?;
+                let policy = self.options.empty_input;
+                let input = input_query(input, policy, || {
+                    self.confirm("Send empty input? (y/n) ")
+                })?;
+                if let Some(input) = input {
+                    let url = fetch::with_query(&url, &input);
+                    let redact = response.status == SensitiveInput;
+                    self.fetch_(url, depth + 1, redact, Vec::new())
                 } else {
-                    Err(anyhow!("Failed to get input"))
+                    self.notice = Some(Ok("Input cancelled".to_owned()));
+                    self.show_tab()
                 }
             },
             // Only read the response body if we got a Success response status
             Success => {
+                if !redact {
+                    self.history.record(&url)?;
+                }
                 // TODO: Figure out how to draw the header
-                if response.meta.starts_with("text/gemini") {
-                    let body = std::str::from_utf8(response.body)?;
-                    let (_, doc) = parse_text_gemini(body).map_err(
-                        |e| anyhow!("text/gemini parsing failed: {}", e))?;
-                    Ok(self.display_doc(&doc))
-                } else if response.meta.starts_with("text/") {
-                    // Read other text/ MIME types as a single preformatted line
-                    let body = std::str::from_utf8(response.body)?;
-                    let text = Line::Pre { alt: None, text: body };
-                    Ok(self.display_doc(&Document(vec![text])))
+                if response.mime_type().starts_with("text/") {
+                    // Pages which were shown before reopen where they were
+                    // left, which display() clamps if they've changed
+                    let tab = Tab {
+                        pos: self.positions.get(&url),
+                        fragment: url.fragment().map(str::to_owned),
+                        url: Some(url),
+                        page: Some((source, Rc::new(plaintext))),
+                        ..Default::default()
+                    };
+                    if std::mem::take(&mut self.new_tab) {
+                        self.tab += 1;
+                        self.tabs.insert(self.tab, tab);
+                    } else {
+                        self.tabs[self.tab] = tab;
+                    }
+                    self.show_tab()
                 } else {
-                    Err(anyhow!("Unknown meta: {}", response.meta))
+                    self.download(&url, response.meta, response.body)
                 }
             },
 
-            // Otherwise, invoke the header cb
-            _ => Ok(Command::Exit), // TODO cb.header(&header)?;
+            // Otherwise, show the failure as a page of its own
+            status => {
+                let title = format!("{}", status);
+                let doc = error_page(&title, response.meta);
+                Ok(self.display_doc(&doc, &Source::Network(url)))
+            },
         }
     }
 
@@ -132,17 +804,47 @@ impl App {
         // TODO: search mode with '/'
         // TODO: multiple up/down commands, e.g. 10j
 
+        // Tab keys are only taken without modifiers, so that the view still
+        // gets Ctrl and Alt combinations (like Ctrl-G)
+        let n = self.tabs.len();
+        let plain = k.modifiers.is_empty();
+        let gt = std::mem::take(&mut self.pending_g);
         match k.code {
+            KeyCode::Char('t') if gt =>
+                Some(Ok(Command::SwitchTab((self.tab + 1) % n))),
+            KeyCode::Char('T') if gt =>
+                Some(Ok(Command::SwitchTab((self.tab + n - 1) % n))),
+            KeyCode::Char(c @ '1'..='9') if plain => {
+                let i = c as usize - '1' as usize;
+                if i < n {
+                    Some(Ok(Command::SwitchTab(i)))
+                } else {
+                    None
+                }
+            },
+            KeyCode::Char('g') if plain => {
+                self.pending_g = true;
+                None
+            },
+            KeyCode::Char('x') if plain => Some(Ok(Command::CloseTab)),
+            KeyCode::Char('H') => Some(Ok(Command::Home)),
+            // 'u' goes up to the directory above the current page
+            KeyCode::Char('u') if k.modifiers.is_empty() =>
+                self.tabs[self.tab].url.as_ref()
+                    .and_then(breadcrumb::parent)
+                    .map(|url| Ok(Command::Load(url))),
             KeyCode::Char(':') => {
                 execute!(&mut std::io::stdout(),
-                    cursor::MoveTo(0, self.size.1 + 1),
+                    cursor::MoveTo(0, self.cmd_row()),
                     Print(":"),
                 ).expect("Could not start drawing command line");
-                if let Some(cmd) = input::Input::new().run() {
-                    Some(Command::parse(cmd))
-                } else {
-                    self.clear_cmd();
-                    None
+                match input::Input::new().run() {
+                    Ok(Some(cmd)) => Some(Command::parse(cmd)),
+                    Ok(None) => {
+                        self.clear_cmd();
+                        None
+                    },
+                    Err(e) => Some(Err(e)),
                 }
             },
             _ => None,
@@ -152,22 +854,49 @@ impl App {
     fn set_cmd_error(&mut self, err: &str) {
         let mut out = std::io::stdout();
         execute!(&mut out,
-            cursor::MoveTo(0, self.size.1 + 1),
+            cursor::MoveTo(0, self.cmd_row()),
             Clear(ClearType::CurrentLine),
             PrintStyledContent(style(err).with(Color::DarkRed)),
         ).expect("Failed to queue cmd error");
         self.has_cmd_error = true;
     }
 
+    fn set_cmd_message(&mut self, msg: &str) {
+        let mut out = std::io::stdout();
+        execute!(&mut out,
+            cursor::MoveTo(0, self.cmd_row()),
+            Clear(ClearType::CurrentLine),
+            Print(msg),
+        ).expect("Failed to queue cmd message");
+        self.has_cmd_error = true;
+    }
+
+    /// Returns the row of the command bar, at the bottom of the terminal
+    fn cmd_row(&self) -> u16 {
+        self.size.1.saturating_sub(1)
+    }
+
     fn clear_cmd(&mut self) {
         let mut out = std::io::stdout();
         execute!(&mut out,
-            cursor::MoveTo(0, self.size.1 + 1),
+            cursor::MoveTo(0, self.cmd_row()),
             Clear(ClearType::CurrentLine),
         ).expect("Failed to queue cmd clear");
         self.has_cmd_error = false;
     }
 
+    /// Handles some events ourselves, before possibly passing them to the
+    /// document view (unless it's waiting for a link hint to be typed)
+    fn dispatch(&mut self, v: &mut View, evt: Event)
+        -> Option<Result<Command>>
+    {
+        if v.hinting() {
+            v.event(evt)
+        } else {
+            self.event(evt).or_else(|| v.event(evt))
+        }
+    }
+
     fn event(&mut self, evt: Event) -> Option<Result<Command>> {
         match evt {
             Event::Key(event) => self.key(event),
@@ -175,6 +904,14 @@ impl App {
                 self.resize((w, h));
                 None
             },
+            Event::Mouse(MouseEvent::Down(MouseButton::Left, col, row, _))
+                if row == self.status_row() && self.options.breadcrumb_clicks =>
+            {
+                let col = col as usize;
+                self.crumbs.iter()
+                    .find(|(r, _)| r.contains(&col))
+                    .map(|(_, url)| Ok(Command::Load(url.clone())))
+            },
             _ => None,
         }
     }
@@ -183,19 +920,729 @@ impl App {
         self.size = size;
     }
 
-    fn display_doc(&mut self, doc: &Document) -> Command {
-        let mut v = View::new(doc);
+    /// Shows which URL is being fetched in the command bar, since the
+    /// screen doesn't change until the response arrives.  The terminal may
+    /// have been resized since the last page was drawn, so its size is
+    /// checked again first.
+    fn show_loading(&mut self, url: &url::Url, redact: bool) {
+        if let Ok(size) = terminal::size() {
+            self.size = size;
+        }
+        let msg = loading_message(url, redact, self.options.ascii,
+                                  self.size.0.into());
+        self.set_cmd_message(&msg);
+    }
+
+    fn show_notice(&mut self) {
+        match self.notice.take() {
+            Some(Ok(msg)) => self.set_cmd_message(&msg),
+            Some(Err(err)) => self.set_cmd_error(&format!("{}", err)),
+            None => (),
+        }
+    }
+
+    /// Starts fetching the target of the link under the cursor in the
+    /// background, if prefetching is enabled and it's a gemini link.  Hosts
+    /// which are offered an identity aren't prefetched, since read() won't
+    /// use the result.
+    fn prefetch_link(&self, v: &View, source: &Source) {
+        if !self.options.prefetch || self.offline {
+            return;
+        }
+        let url = v.cursor_link().and_then(|t| source.resolve(t).ok());
+        if let Some(url) = url.filter(|u| u.scheme() == "gemini") {
+            let host = url.host_str().unwrap_or("");
+            if matches!(self.identities.active(host), Ok(None)) {
+                self.prefetch.request(&self.config, &self.dns, &url);
+            }
+        }
+    }
+
+    /// Applies the configured scheme aliases to a URL
+    fn coerce(&self, url: url::Url) -> Result<url::Url> {
+        Ok(source::coerce_url(url, &self.options.scheme_aliases)?)
+    }
+
+    /// Decides how to follow a link.  Links with schemes that we can't
+    /// fetch ourselves go through the gateway (if one is configured for
+    /// that scheme), or else are handed to the system's default handler, in
+    /// which case this returns None.
+    fn route(&mut self, url: url::Url) -> Result<Option<url::Url>> {
+        let url = self.coerce(url)?;
+        match route_url(url, self.options.gateway.as_deref(),
+                        &self.options.gateway_schemes)?
+        {
+            Route::Fetch(url) => Ok(Some(url)),
+            Route::External(url) => {
+                open_external(&url)?;
+                self.set_cmd_message(&format!("Opened {} externally", url));
+                Ok(None)
+            },
+        }
+    }
+
+    /// Fetches a link's target on another thread and shows it in a pane
+    /// below the page, which is shrunk to make room.  Enter (or 't', for a
+    /// new tab) follows the link, and Esc closes the pane, which stops
+    /// waiting for the preview (a fetch which is still running finishes in
+    /// the background).  The caller restores the page's size.
+    fn peek(&mut self, v: &mut View, source: &Source, target: &str)
+        -> Result<Option<Command>>
+    {
+        let url = source.resolve(target)?;
+        if self.offline && url.scheme() == "gemini" {
+            return Err(anyhow!("Can't preview {} while offline", url));
+        }
+        let (tx, rx) = std::sync::mpsc::channel();
+        {
+            let config = self.identities.config_for(
+                &self.config, url.host_str().unwrap_or(""))?;
+            let dns = self.dns.clone();
+            let url = url.clone();
+            std::thread::spawn(move || {
+                // The receiver is gone if the pane was closed
+                let _ = tx.send(preview::read(&config, &url, Some(&dns)));
+            });
+        }
+
+        let loading = Ok(vec!["Loading...".to_owned()]);
+        let mut body = None;
         loop {
-            let evt = read().expect("Could not read event");
+            // The pane takes half of the rows above the status bar
+            let (w, h) = self.size;
+            let pane = h.saturating_sub(2) / 2;
+            if pane < 2 {
+                return Err(anyhow!("Terminal is too small to preview"));
+            }
+            v.resize((w, h - pane));
+            let top = h - 2 - pane;
+            preview::draw(&url, body.as_ref().unwrap_or(&loading),
+                          top, pane, w);
 
-            // Handle some events ourselves, before possibly
-            // passing them to the document view
-            if let Some(r) = self.event(evt).or_else(|| v.event(evt)) {
+            // Wait for input, checking for the fetch every so often
+            let evt = loop {
+                if body.is_none() {
+                    if let Ok(r) = rx.try_recv() {
+                        let opts = ParseOptions {
+                            dash_lists: self.options.dash_lists,
+                            ..Default::default()
+                        };
+                        let width = (w as usize).saturating_sub(4).max(1);
+                        body = Some(r.and_then(|data|
+                                preview::lines(&data, width, opts))
+                            .map_err(|e| format!("{}", e)));
+                        preview::draw(&url, body.as_ref().unwrap(),
+                                      top, pane, w);
+                    }
+                }
+                if event::poll(Duration::from_millis(50))? {
+                    break read()?;
+                }
+            };
+            match evt {
+                Event::Key(k) => match k.code {
+                    KeyCode::Enter => return Ok(self.route(url)?
+                        .map(Command::Load)),
+                    KeyCode::Char('t') => return Ok(self.route(url)?
+                        .map(Command::LoadTab)),
+                    KeyCode::Esc => return Ok(None),
+                    _ => (),
+                },
+                Event::Resize(w, h) => self.resize((w, h)),
+                Event::Mouse(..) => (),
+            }
+        }
+    }
+
+    /// Pipes text to the configured pager, waiting for it to exit.  This
+    /// should be called with the screen suspended.
+    fn pager(&self, text: &str) -> Result<()> {
+        let mut args = self.options.pager.split_whitespace();
+        let program = args.next()
+            .ok_or_else(|| anyhow!("No pager configured"))?;
+        let mut child = process::Command::new(program)
+            .args(args)
+            .stdin(process::Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow!("Could not run {}: {}", program, e))?;
+
+        // The pager may exit without reading everything (e.g. if the user
+        // quits early), so failing to write isn't an error.
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(text.as_bytes());
+        }
+        child.wait()?;
+        Ok(())
+    }
+
+    /// Returns the row of the status bar, just above the command bar
+    fn status_row(&self) -> u16 {
+        self.size.1.saturating_sub(2)
+    }
+
+    /// Draws the status bar, which holds the tab bar (if more than one tab
+    /// is open) followed by the breadcrumb of the document's URL.  In reader
+    /// mode, its row is left blank instead.
+    fn draw_status(&mut self, source: &Source) {
+        if self.options.reader_mode {
+            self.crumbs.clear();
+            execute!(&mut std::io::stdout(),
+                cursor::MoveTo(0, self.status_row()),
+                Clear(ClearType::CurrentLine),
+            ).expect("Could not clear status bar");
+            return;
+        }
+        let width = self.size.0 as usize;
+        let bar = if self.tabs.len() > 1 {
+            let titles: Vec<&str> = self.tabs.iter()
+                .map(|t| t.title.as_str())
+                .collect();
+            tab::bar(&titles, self.tab, width / 2, self.options.ascii) + "  "
+        } else {
+            String::new()
+        };
+        let bar = if self.offline { format!("[offline] {}", bar) } else { bar };
+        let offset = bar.chars().count();
+
+        let crumbs = source.url().map(breadcrumb::crumbs).unwrap_or_default();
+        let (text, ranges) = breadcrumb::render(&crumbs, self.options.ascii);
+        self.crumbs = ranges.into_iter()
+            .map(|r| r.start + offset..r.end + offset)
+            .zip(crumbs.into_iter().map(|c| c.url))
+            .collect();
+        let text: String = text.chars()
+            .take(width.saturating_sub(offset))
+            .collect();
+
+        execute!(&mut std::io::stdout(),
+            cursor::MoveTo(0, self.status_row()),
+            Clear(ClearType::CurrentLine),
+            PrintStyledContent(style(bar).on(Color::Black)),
+            PrintStyledContent(style(text).with(Color::DarkGrey)),
+        ).expect("Could not draw status bar");
+    }
+
+    fn display_doc(&mut self, doc: &Document, source: &Source) -> Command {
+        self.display(doc, source, (0, 0), None, true).0
+    }
+
+    /// Displays a document, starting from the given scroll and cursor
+    /// positions (or at the given line of the document, if there is one),
+    /// until a command is issued.  Returns that command and the final
+    /// positions.  A `generated` document isn't the current tab's page, so
+    /// saving it doesn't write out the tab's response.
+    fn display(&mut self, doc: &Document, source: &Source,
+               pos: (usize, usize), anchor: Option<usize>, generated: bool)
+        -> (Command, (usize, usize))
+    {
+        let screen = Screen::enter(self.caps.mouse);
+        // Resizes while a page was loading haven't been seen yet
+        let size = terminal::size().expect("Could not get terminal size");
+        self.size = size;
+        let mut v = View::new(doc, size, &self.options);
+        v.set_visited(self.visited.targets(doc, source));
+        match anchor {
+            Some(line) => v.goto_line(line),
+            None => v.set_position(pos),
+        }
+        if let Some(term) = self.search.take() {
+            if !v.find(&term) {
+                self.notice = Some(Err(anyhow!("Not found: {}", term)));
+            }
+        }
+        v.draw();
+        self.draw_status(source);
+        self.clear_cmd();
+        self.show_notice();
+        self.prefetch_link(&v, source);
+
+        let mut idle = Idle::new(self.options.idle_dim, Instant::now());
+        let cmd = loop {
+            let r = if let Some(cmd) = self.startup.take() {
+                Some(Ok(cmd))
+            } else {
+                // Wait for input, dimming the page if it doesn't arrive in
+                // time
+                let evt = match idle.remaining(Instant::now()) {
+                    Some(t) if !event::poll(t)
+                        .expect("Could not poll event") =>
+                    {
+                        if idle.check(Instant::now()) {
+                            v.set_dim(true);
+                        }
+                        continue;
+                    },
+                    _ => read().expect("Could not read event"),
+                };
+
+                // The first key press or click after dimming only restores
+                // the page, rather than being acted upon
+                if idle.input(Instant::now()) {
+                    v.set_dim(false);
+                    if !matches!(evt, Event::Resize(..)) {
+                        continue;
+                    }
+                }
+
+                let r = self.dispatch(&mut v, evt);
+                if let Event::Resize(..) = evt {
+                    self.draw_status(source);
+                }
+                self.prefetch_link(&v, source);
+                r
+            };
+            if let Some(r) = r {
                 match r {
                     Err(err) => self.set_cmd_error(&format!("{}", err)),
+                    // Relative links are resolved based on where the
+                    // document came from, which only we know about
+                    Ok(Command::TryLoad(s)) => {
+                        match source.resolve(&s).and_then(|u| self.route(u)) {
+                            Err(e) => self.set_cmd_error(&format!("{}", e)),
+                            Ok(Some(url)) => break Command::Load(url),
+                            Ok(None) => (),
+                        }
+                    },
+                    Ok(Command::TryLoadTab(s)) => {
+                        match source.resolve(&s).and_then(|u| self.route(u)) {
+                            Err(e) => self.set_cmd_error(&format!("{}", e)),
+                            Ok(Some(url)) => break Command::LoadTab(url),
+                            Ok(None) => (),
+                        }
+                    },
+                    Ok(Command::Forget(host)) => self.forget(&host),
+                    // Switching identities reloads the page with the new one
+                    Ok(Command::Identity(name)) =>
+                        match self.identity(source, name.as_deref()) {
+                            Ok(url) => break Command::Load(url),
+                            Err(e) => self.set_cmd_error(&format!("{}", e)),
+                        },
+                    Ok(Command::IdentityAdd(name, path)) => {
+                        let r = std::fs::read(&path)
+                            .map_err(|e| anyhow!("Could not read {}: {}",
+                                                 path, e))
+                            .and_then(|pem| Ok(self.identities
+                                                   .add(&name, &pem)?));
+                        match r {
+                            Ok(()) => self.set_cmd_message(
+                                &format!("Added identity {}", name)),
+                            Err(e) => self.set_cmd_error(&format!("{}", e)),
+                        }
+                    },
+                    Ok(Command::Offline) => {
+                        self.offline = !self.offline;
+                        self.draw_status(source);
+                        self.set_cmd_message(if self.offline {
+                            "Offline: pages are only read from the cache"
+                        } else {
+                            "Online"
+                        });
+                    },
+                    Ok(Command::Tls) => match &self.tls {
+                        Some((host, tls)) => self.set_cmd_message(
+                            &format!("{}: {}", host, tls)),
+                        None => self.set_cmd_error(
+                            "No TLS connection yet this session"),
+                    },
+                    Ok(Command::Yank(t)) => self.yank(source, t.as_deref()),
+                    Ok(Command::Save(p)) =>
+                        self.save_page(doc, p.as_deref(), false, generated),
+                    Ok(Command::SaveText(p)) =>
+                        self.save_page(doc, p.as_deref(), true, generated),
+                    Ok(Command::Download(s)) => {
+                        let r = source.resolve(&s)
+                            .and_then(|url| self.fetch_to_file(url));
+                        v.draw();
+                        match r {
+                            Err(err) => self.set_cmd_error(&format!("{}", err)),
+                            Ok(()) => self.show_notice(),
+                        }
+                    },
+                    // The setting is kept for pages shown later
+                    Ok(Command::LineNumbers(n)) => {
+                        self.options.line_numbers = n;
+                        v.set_line_numbers(n);
+                    },
+                    Ok(Command::WrapWidth(n)) => {
+                        self.options.wrap_width = n;
+                        v.set_wrap_width(n);
+                    },
+                    Ok(Command::Reader(r)) => {
+                        self.options.reader_mode = r;
+                        v.set_reader(r);
+                        self.draw_status(source);
+                    },
+                    Ok(Command::Peek(s)) => {
+                        let r = self.peek(&mut v, source, &s);
+                        v.resize(self.size);
+                        self.draw_status(source);
+                        match r {
+                            Ok(Some(cmd)) => break cmd,
+                            Ok(None) => (),
+                            Err(e) => self.set_cmd_error(&format!("{}", e)),
+                        }
+                    },
+                    Ok(Command::Pager(text)) => {
+                        let r = screen.suspend(|| self.pager(&text));
+                        v.draw();
+                        self.draw_status(source);
+                        if let Err(err) = r {
+                            self.set_cmd_error(&format!("{}", err));
+                        }
+                    },
+                    // Quitting may need confirming, if it would lose tabs
+                    Ok(Command::Exit) => {
+                        let msg = quit_prompt(self.options.confirm_quit,
+                                              self.tabs.len());
+                        match msg.map_or(Ok(true), |m| self.confirm(&m)) {
+                            Ok(true) => break Command::Exit,
+                            Ok(false) => (),
+                            Err(e) => self.set_cmd_error(&format!("{}", e)),
+                        }
+                    },
                     Ok(r) => break r,
                 }
             }
-        }
+        };
+        (cmd, v.position())
+    }
+}
+
+/// Builds the certificate verifier, which is strict about hostnames if
+/// requested in the config
+pub fn verifier(db: &sled::Db, options: &Config)
+    -> Result<Arc<GeminiCertificateVerifier>>
+{
+    Ok(Arc::new(if options.strict_hostnames {
+        GeminiCertificateVerifier::new_strict(db)?
+    } else {
+        GeminiCertificateVerifier::new(db)?
+    }))
+}
+
+/// Builds a TLS configuration which checks certificates with the given
+/// trust-on-first-use verifier, and accepts TLS versions from `min` up
+pub fn client_config(verifier: Arc<GeminiCertificateVerifier>,
+                     min: TlsVersion) -> Arc<rustls::ClientConfig>
+{
+    let mut config = rustls::ClientConfig::new();
+    config.dangerous().set_certificate_verifier(verifier);
+    if min == TlsVersion::Tls13 {
+        config.versions = vec![rustls::ProtocolVersion::TLSv1_3];
+    }
+    Arc::new(config)
+}
+
+/// Returns the question to ask before quitting, or None to quit right away.
+/// Only having more than one tab open is worth asking about.
+fn quit_prompt(confirm: bool, tabs: usize) -> Option<String> {
+    if confirm && tabs > 1 {
+        Some(format!("Quit with {} tabs open? (y/n) ", tabs))
+    } else {
+        None
+    }
+}
+
+/// Returns how long to wait before retrying after a SlowDown response.  The
+/// meta should be a number of seconds; if it isn't, we back off
+/// exponentially based on the number of retries so far.
+fn slow_down_wait(meta: &str, retries: u32, max: Duration) -> Duration {
+    let secs = meta.trim().parse()
+        .unwrap_or_else(|_| 1u64 << retries.min(16));
+    Duration::from_secs(secs).min(max)
+}
+
+/// How a link is followed
+#[derive(Debug, Eq, PartialEq)]
+enum Route {
+    /// Fetched and shown here, possibly through a gateway
+    Fetch(url::Url),
+    /// Handed to the system's default handler
+    External(url::Url),
+}
+
+/// Decides how to follow a link.  Schemes we can read ourselves are
+/// fetched directly, those listed in `schemes` go through the gateway (if
+/// there is one), and anything else is opened externally.
+fn route_url(url: url::Url, gateway: Option<&str>, schemes: &[String])
+    -> Result<Route>
+{
+    let scheme = url.scheme();
+    if let "gemini" | "file" | "data" = scheme {
+        return Ok(Route::Fetch(url));
     }
+    match gateway.filter(|_| schemes.iter().any(|s| s == scheme)) {
+        Some(g) => Ok(Route::Fetch(source::via_gateway(g, &url)?)),
+        None => Ok(Route::External(url)),
+    }
+}
+
+/// Returns the program (and its leading arguments) which opens a URL with
+/// the default handler on the given OS.  The URL comes from a page, so it
+/// mustn't go through a shell: `cmd /C start` on Windows would run anything
+/// after a `&` in it as another command.
+fn opener(os: &str) -> (&'static str, &'static [&'static str]) {
+    match os {
+        "macos" => ("open", &[]),
+        "windows" => ("rundll32", &["url.dll,FileProtocolHandler"]),
+        _ => ("xdg-open", &[]),
+    }
+}
+
+/// Opens a URL with the system's default handler, without waiting for it
+fn open_external(url: &url::Url) -> Result<()> {
+    let (program, args) = opener(std::env::consts::OS);
+    process::Command::new(program)
+        .args(args)
+        .arg(url.as_str())
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .spawn()
+        .map_err(|e| anyhow!("Could not run {}: {}", program, e))?;
+    Ok(())
+}
+
+/// Picks a filename for saving a URL's body, which is the last non-empty
+/// segment of its path
+fn default_filename(url: Option<&url::Url>) -> String {
+    url.and_then(|u| u.path_segments())
+        .and_then(|mut s| s.rfind(|s| !s.is_empty()))
+        .unwrap_or("download")
+        .to_owned()
+}
+
+/// Describes the redirects (if any) which led from the first URL in `chain`
+/// to the final URL, for the status bar.  Intermediate hops are listed when
+/// there are several, so that the whole chain is visible.
+fn redirect_notice(chain: &[url::Url], url: &url::Url) -> Option<String> {
+    match chain {
+        [] => None,
+        [first, ..] if first == url => None,
+        [_] => Some(format!("Redirected to {}", url)),
+        [_, via @ ..] => Some(format!("Redirected to {} (via {})", url,
+            via.iter().map(|u| u.as_str()).collect::<Vec<_>>().join(", "))),
+    }
+}
+
+/// Builds the message shown while a URL is fetched, cut to fit the width.
+/// A query holding sensitive input is left out.
+fn loading_message(url: &url::Url, redact: bool, ascii: bool, width: usize)
+    -> String
+{
+    let mut url = url.clone();
+    if redact {
+        url.set_query(None);
+    }
+    let msg = format!("Loading {}{}", url, if ascii { "..." } else { "…" });
+    msg.chars().take(width).collect()
+}
+
+/// Returns the query of a URL which was reached by answering an input
+/// prompt, decoded so that it can be edited when prompting again.  If it
+/// can't be decoded, or it's a `secret` (which the input would echo), the
+/// prompt starts out empty.
+fn previous_query(url: &url::Url, secret: bool) -> String {
+    if secret {
+        return String::new();
+    }
+    percent_encoding::percent_decode_str(url.query().unwrap_or(""))
+        .decode_utf8()
+        .map(|s| s.into_owned())
+        .unwrap_or_default()
+}
+
+/// Decides what to send in reply to an input prompt, returning None to
+/// cancel.  An empty answer is only sent if the policy allows it, since
+/// servers often respond to it with the same prompt.
+fn input_query<F>(input: Option<String>, policy: EmptyInput, confirm: F)
+    -> Result<Option<String>>
+    where F: FnOnce() -> Result<bool>
+{
+    let input = match input {
+        Some(input) => input,
+        None => return Ok(None),
+    };
+    let send = !input.is_empty() || match policy {
+        EmptyInput::Cancel => false,
+        EmptyInput::Confirm => confirm()?,
+        EmptyInput::Send => true,
+    };
+    Ok(Some(input).filter(|_| send))
+}
+
+/// Builds a page describing a failure response.  The meta is shown as body
+/// text (rather than in the command bar) so that it wraps and scrolls, since
+/// servers may send up to 1024 bytes of it.
+fn error_page<'a>(title: &'a str, meta: &'a str) -> Document<'a> {
+    Document::new(vec![Line::H1(title), Line::Text(meta)])
+}
+
+#[test]
+fn test_error_page_long_meta() {
+    let meta = (0..200).map(|i| format!("{:04}", i))
+        .collect::<Vec<String>>()
+        .join(" ") + "!";
+    assert_eq!(meta.len(), 1000);
+
+    let title = format!("{}", Status::TemporaryFailure);
+    let doc = error_page(&title, &meta);
+    let wrapped = crate::wrapped::word_wrap(&doc, 76, Default::default());
+    assert!(wrapped.0.len() > 10);
+
+    let text: Vec<&str> = wrapped.0.iter()
+        .filter_map(|w| match w.line {
+            Line::Text(t) => Some(t),
+            _ => None,
+        })
+        .collect();
+    assert!(text.iter().all(|t| t.len() <= 76));
+    assert_eq!(text.join(" "), meta);
+}
+
+#[test]
+fn test_slow_down_wait() {
+    let max = Duration::from_secs(30);
+    assert_eq!(slow_down_wait("5", 0, max), Duration::from_secs(5));
+    assert_eq!(slow_down_wait(" 7 ", 2, max), Duration::from_secs(7));
+    assert_eq!(slow_down_wait("3600", 0, max), max);
+
+    // Invalid metas back off exponentially, up to the maximum
+    assert_eq!(slow_down_wait("soon", 0, max), Duration::from_secs(1));
+    assert_eq!(slow_down_wait("", 3, max), Duration::from_secs(8));
+    assert_eq!(slow_down_wait("-1", 10, max), max);
+}
+
+#[test]
+fn test_empty_input() {
+    let s = |s: &str| Some(s.to_owned());
+    let no = || Ok(false);
+    let yes = || Ok(true);
+    let q = |input, policy, confirm: fn() -> Result<bool>|
+        input_query(input, policy, confirm);
+
+    // An empty answer is cancelled, rather than re-requesting the page
+    // (which would likely prompt again, forever)
+    assert_eq!(q(s(""), EmptyInput::Cancel, yes).unwrap(), None);
+    assert_eq!(q(s(""), EmptyInput::Confirm, no).unwrap(), None);
+    assert_eq!(q(s(""), EmptyInput::Confirm, yes).unwrap(), s(""));
+    assert_eq!(q(s(""), EmptyInput::Send, no).unwrap(), s(""));
+
+    assert_eq!(q(s("cats"), EmptyInput::Cancel, no).unwrap(), s("cats"));
+    assert_eq!(q(None, EmptyInput::Send, yes).unwrap(), None);
+
+    // Failing to read the confirmation is an error, not a cancellation
+    let fail = || Err(anyhow!("terminal went away"));
+    assert!(q(s(""), EmptyInput::Confirm, fail).is_err());
+    assert_eq!(q(None, EmptyInput::Confirm, fail).unwrap(), None);
+}
+
+#[test]
+fn test_route_url() {
+    let u = |s| url::Url::parse(s).unwrap();
+    let gateway = Some("gemini://gateway.example/?url={url}");
+    let schemes = vec!["https".to_owned()];
+    let r = |s, g| route_url(u(s), g, &schemes).unwrap();
+
+    assert_eq!(r("gemini://example.com/", gateway),
+               Route::Fetch(u("gemini://example.com/")));
+    assert_eq!(r("file:///tmp/a.gmi", None),
+               Route::Fetch(u("file:///tmp/a.gmi")));
+    assert_eq!(r("https://example.com/", gateway),
+               Route::Fetch(u("gemini://gateway.example/\
+                               ?url=https%3A%2F%2Fexample%2Ecom%2F")));
+    assert_eq!(r("https://example.com/", None),
+               Route::External(u("https://example.com/")));
+    assert_eq!(r("mailto:a@example.com", gateway),
+               Route::External(u("mailto:a@example.com")));
+
+    // External URLs never go through a shell
+    assert_eq!(opener("windows").0, "rundll32");
+    assert_eq!(opener("macos").0, "open");
+    assert_eq!(opener("linux").0, "xdg-open");
+}
+
+#[test]
+fn test_quit_prompt() {
+    assert_eq!(quit_prompt(true, 1), None);
+    assert_eq!(quit_prompt(true, 3).unwrap(), "Quit with 3 tabs open? (y/n) ");
+    assert_eq!(quit_prompt(false, 3), None);
+}
+
+#[test]
+fn test_loading_message() {
+    let url = url::Url::parse("gemini://example.com/login?hunter2").unwrap();
+    assert_eq!(loading_message(&url, false, false, 80),
+               "Loading gemini://example.com/login?hunter2…");
+    assert_eq!(loading_message(&url, true, true, 80),
+               "Loading gemini://example.com/login...");
+    assert_eq!(loading_message(&url, true, false, 12), "Loading gemi");
+}
+
+#[test]
+fn test_previous_query() {
+    let q = |s| previous_query(&url::Url::parse(s).unwrap(), false);
+    assert_eq!(q("gemini://example.com/search"), "");
+    assert_eq!(q("gemini://example.com/search?hello%20world%21"),
+               "hello world!");
+    assert_eq!(q("gemini://example.com/search?1+1"), "1+1");
+    assert_eq!(q("gemini://example.com/search?caf%C3%A9"), "café");
+    assert_eq!(q("gemini://example.com/search?%FF"), "");
+
+    // Decoding undoes the encoding that's used when sending a query
+    let s = "a+b c&d=é";
+    let url = url::Url::parse("gemini://example.com/?old=1").unwrap();
+    assert_eq!(previous_query(&fetch::with_query(&url, s), false), s);
+
+    // Answers to sensitive prompts aren't shown again
+    let url = url::Url::parse("gemini://example.com/login?hunter2").unwrap();
+    assert_eq!(previous_query(&url, true), "");
+}
+
+#[test]
+fn test_default_filename() {
+    let f = |s| default_filename(Some(&url::Url::parse(s).unwrap()));
+    assert_eq!(f("gemini://example.com/a/b.gmi"), "b.gmi");
+    assert_eq!(f("gemini://example.com/a/dir/"), "dir");
+    assert_eq!(f("gemini://example.com/"), "download");
+    assert_eq!(default_filename(None), "download");
+}
+
+#[test]
+fn test_redirect_notice() {
+    let u = |s| url::Url::parse(s).unwrap();
+    let a = u("gemini://example.com/a");
+    let b = u("gemini://example.com/b");
+    let c = u("gemini://example.com/c");
+    assert_eq!(redirect_notice(&[], &a), None);
+    assert_eq!(redirect_notice(std::slice::from_ref(&a), &b).unwrap(),
+               "Redirected to gemini://example.com/b");
+    assert_eq!(redirect_notice(&[a.clone(), b.clone()], &c).unwrap(),
+               "Redirected to gemini://example.com/c \
+                (via gemini://example.com/b)");
+
+    // Coming back around to the requested URL isn't worth mentioning
+    assert_eq!(redirect_notice(&[a.clone(), b], &a), None);
+}
+
+#[test]
+fn test_dispatch_modifiers() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let mut app = App::new(&db, Config::default()).unwrap();
+    let doc = Document::new(vec![Line::Text("text")]);
+    let mut v = View::new(&doc, (80, 24), &app.options);
+    let key = |c, m| Event::Key(KeyEvent::new(KeyCode::Char(c), m));
+    let mut send = |c, m| app.dispatch(&mut v, key(c, m));
+    let none = KeyModifiers::NONE;
+
+    // Ctrl-G reaches the view, rather than starting a 'gt'
+    let r = send('g', KeyModifiers::CONTROL);
+    assert!(matches!(r, Some(Ok(Command::LineNumbers(_)))));
+    assert!(!matches!(send('t', none), Some(Ok(Command::SwitchTab(_)))));
+
+    assert!(send('g', none).is_none());
+    assert!(matches!(send('t', none), Some(Ok(Command::SwitchTab(0)))));
+
+    assert!(!matches!(send('x', KeyModifiers::ALT),
+                      Some(Ok(Command::CloseTab))));
+    assert!(matches!(send('x', none), Some(Ok(Command::CloseTab))));
 }
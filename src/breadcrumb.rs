@@ -0,0 +1,133 @@
+use std::ops::Range;
+
+/// Separator drawn between segments of the breadcrumb, and its ASCII form
+const SEPARATOR: &str = " › ";
+const ASCII_SEPARATOR: &str = " > ";
+
+/// A segment of the breadcrumb, with the URL it leads to
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Crumb {
+    pub label: String,
+    pub url: url::Url,
+}
+
+/// Splits a URL into its host and the path segments leading to it, e.g.
+/// `host › docs › guide`.  Each segment leads to its own directory, except
+/// the last, which is the URL itself (minus any query or fragment).
+pub fn crumbs(url: &url::Url) -> Vec<Crumb> {
+    if url.cannot_be_a_base() {
+        return Vec::new();
+    }
+    let mut base = url.clone();
+    base.set_query(None);
+    base.set_fragment(None);
+    base.set_path("/");
+
+    let mut out = vec![Crumb {
+        label: url.host_str().unwrap_or("/").to_owned(),
+        url: base.clone(),
+    }];
+    let segments: Vec<&str> = url.path_segments()
+        .map(|s| s.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+    let mut path = String::new();
+    for (i, s) in segments.iter().enumerate() {
+        path += "/";
+        path += s;
+        let mut u = base.clone();
+        if i + 1 == segments.len() {
+            u.set_path(url.path());
+        } else {
+            u.set_path(&format!("{}/", path));
+        }
+        let label = percent_encoding::percent_decode_str(s)
+            .decode_utf8_lossy()
+            .into_owned();
+        out.push(Crumb { label, url: u });
+    }
+    out
+}
+
+/// Returns the URL of the directory above a URL's path, keeping its scheme,
+/// host, and port but dropping any query or fragment.  A file's parent is
+/// the directory it's in, and a directory's (with a trailing slash) is the
+/// one above it.  Returns None at the root, or for URLs without a path.
+pub fn parent(url: &url::Url) -> Option<url::Url> {
+    if url.cannot_be_a_base() || url.path() == "/" || url.path().is_empty() {
+        return None;
+    }
+    let mut out = url.clone();
+    out.set_query(None);
+    out.set_fragment(None);
+    // Popping leaves segments encoded as they were, unlike pushing them
+    out.path_segments_mut().ok()?.pop_if_empty().pop().push("");
+    Some(out)
+}
+
+/// Renders the breadcrumb as text, along with the range of columns taken up
+/// by each segment (for mapping clicks back to segments)
+pub fn render(crumbs: &[Crumb], ascii: bool) -> (String, Vec<Range<usize>>) {
+    let sep = if ascii { ASCII_SEPARATOR } else { SEPARATOR };
+    let mut text = String::new();
+    let mut ranges = Vec::new();
+    let mut col = 0;
+    for (i, c) in crumbs.iter().enumerate() {
+        if i > 0 {
+            text += sep;
+            col += sep.chars().count();
+        }
+        let n = c.label.chars().count();
+        text += &c.label;
+        ranges.push(col..col + n);
+        col += n;
+    }
+    (text, ranges)
+}
+
+#[test]
+fn test_crumbs() {
+    let url = url::Url::parse(
+        "gemini://example.com/docs/user%20guide/intro.gmi?q#f").unwrap();
+    let c = crumbs(&url);
+    let labels: Vec<&str> = c.iter().map(|c| c.label.as_str()).collect();
+    assert_eq!(labels, vec!["example.com", "docs", "user guide", "intro.gmi"]);
+    let urls: Vec<&str> = c.iter().map(|c| c.url.as_str()).collect();
+    assert_eq!(urls, vec!["gemini://example.com/",
+                          "gemini://example.com/docs/",
+                          "gemini://example.com/docs/user%20guide/",
+                          "gemini://example.com/docs/user%20guide/intro.gmi"]);
+
+    let (text, ranges) = render(&c[..3], false);
+    assert_eq!(text, "example.com › docs › user guide");
+    assert_eq!(ranges, vec![0..11, 14..18, 21..31]);
+    let (text, ranges) = render(&c[..3], true);
+    assert_eq!(text, "example.com > docs > user guide");
+    assert_eq!(ranges, vec![0..11, 14..18, 21..31]);
+
+    // Directories keep their trailing slash
+    let url = url::Url::parse("gemini://example.com/a/b/").unwrap();
+    assert_eq!(crumbs(&url).last().unwrap().url.as_str(),
+               "gemini://example.com/a/b/");
+
+    let url = url::Url::parse("data:text/gemini,hello").unwrap();
+    assert!(crumbs(&url).is_empty());
+}
+
+#[test]
+fn test_parent() {
+    let p = |s| parent(&url::Url::parse(s).unwrap()).map(url::Url::into_string);
+    assert_eq!(p("gemini://example.com/a/b/c.gmi?q#f").as_deref(),
+               Some("gemini://example.com/a/b/"));
+    assert_eq!(p("gemini://example.com/a/b/").as_deref(),
+               Some("gemini://example.com/a/"));
+    assert_eq!(p("gemini://example.com:1966/a").as_deref(),
+               Some("gemini://example.com:1966/"));
+    assert_eq!(p("gemini://example.com/caf%C3%A9/x%20y/").as_deref(),
+               Some("gemini://example.com/caf%C3%A9/"));
+
+    // There's nothing above the root
+    assert_eq!(p("gemini://example.com/"), None);
+    assert_eq!(p("gemini://example.com"), None);
+    assert_eq!(p("gemini://example.com/?q"), None);
+    assert_eq!(p("data:text/gemini,hi"), None);
+}
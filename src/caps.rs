@@ -0,0 +1,78 @@
+/// Terminal features which can't be taken for granted, particularly when
+/// running inside a terminal multiplexer.  GNU screen doesn't understand
+/// the title-setting escape or the SGR mouse reports that crossterm asks
+/// for, so they'd be echoed as garbage; tmux passes both through.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Caps {
+    /// Whether the window title can be set
+    pub set_title: bool,
+    /// Whether mouse capture can be enabled
+    pub mouse: bool,
+    /// Whether non-ASCII characters can be drawn, which is assumed unless
+    /// the locale is set to something other than UTF-8
+    pub unicode: bool,
+}
+
+impl Caps {
+    /// Detects capabilities from the process environment
+    pub fn detect() -> Caps {
+        Self::from_env(|k| std::env::var(k).ok())
+    }
+
+    fn from_env<F: Fn(&str) -> Option<String>>(get: F) -> Caps {
+        let term = get("TERM").unwrap_or_default();
+        let tmux = get("TMUX").is_some() || term.starts_with("tmux");
+        // tmux also sets TERM=screen by default, so check it first
+        let screen = !tmux &&
+            (get("STY").is_some() || term.starts_with("screen"));
+        let dumb = term == "dumb";
+        // The first of these which is set decides the character encoding
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"].iter()
+            .filter_map(|k| get(k))
+            .find(|v| !v.is_empty());
+        let unicode = locale.is_none_or(|v| {
+            let v = v.to_ascii_uppercase();
+            v.contains("UTF-8") || v.contains("UTF8")
+        });
+        Caps {
+            set_title: !screen && !dumb && term != "linux",
+            mouse: !screen && !dumb,
+            unicode,
+        }
+    }
+}
+
+#[test]
+fn test_from_env() {
+    let env = |vars: &'static [(&'static str, &'static str)]| {
+        move |k: &str| vars.iter()
+            .find(|(name, _)| *name == k)
+            .map(|(_, v)| v.to_string())
+    };
+    let all = Caps { set_title: true, mouse: true, unicode: true };
+
+    assert_eq!(Caps::from_env(env(&[("TERM", "xterm-256color")])), all);
+    assert_eq!(Caps::from_env(env(&[("TERM", "screen-256color"),
+                                     ("TMUX", "/tmp/tmux-1000/default")])),
+               all);
+    assert_eq!(Caps::from_env(env(&[("TERM", "tmux-256color")])), all);
+
+    // Title-setting is skipped under GNU screen, however it's detected
+    let screen = Caps::from_env(env(&[("TERM", "screen"),
+                                      ("STY", "1234.pts-0.host")]));
+    assert!(!screen.set_title && !screen.mouse);
+    let screen = Caps::from_env(env(&[("TERM", "xterm"), ("STY", "1.a")]));
+    assert!(!screen.set_title);
+
+    assert!(!Caps::from_env(env(&[("TERM", "linux")])).set_title);
+    assert_eq!(Caps::from_env(env(&[("TERM", "dumb")])),
+               Caps { set_title: false, mouse: false, unicode: true });
+
+    // Only a locale which isn't UTF-8 turns off unicode
+    let caps = |vars| Caps::from_env(env(vars)).unicode;
+    assert!(caps(&[("LANG", "en_US.UTF-8")]));
+    assert!(caps(&[("LANG", "de_DE.utf8")]));
+    assert!(!caps(&[("LANG", "C")]));
+    assert!(!caps(&[("LC_ALL", "POSIX"), ("LANG", "en_US.UTF-8")]));
+    assert!(caps(&[("LC_ALL", ""), ("LC_CTYPE", "C.UTF-8"), ("LANG", "C")]));
+}
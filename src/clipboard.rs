@@ -0,0 +1,76 @@
+use std::io::Write;
+use std::process;
+
+use anyhow::{anyhow, Result};
+
+/// Somewhere that text can be copied to
+pub trait Clipboard {
+    fn copy(&mut self, text: &str) -> Result<()>;
+}
+
+/// Copies text by asking the terminal to do it, with an OSC 52 escape
+/// sequence.  This reaches the local clipboard even over SSH, as long as
+/// the terminal supports it.
+pub struct Osc52<W>(pub W);
+
+impl<W: Write> Clipboard for Osc52<W> {
+    fn copy(&mut self, text: &str) -> Result<()> {
+        write!(self.0, "\x1b]52;c;{}\x07", base64::encode(text))?;
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+/// Copies text by piping it into a program, e.g. `xclip -selection
+/// clipboard` or `pbcopy`
+pub struct Program(pub String);
+
+impl Clipboard for Program {
+    fn copy(&mut self, text: &str) -> Result<()> {
+        let mut args = self.0.split_whitespace();
+        let program = args.next()
+            .ok_or_else(|| anyhow!("No clipboard command configured"))?;
+        let mut child = process::Command::new(program)
+            .args(args)
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .spawn()
+            .map_err(|e| anyhow!("Could not run {}: {}", program, e))?;
+        // A failed write shows up in the exit status, which is more useful
+        // than the broken pipe error (e.g. if the program isn't running)
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(text.as_bytes());
+        }
+        let status = child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(anyhow!("Could not copy with {} ({})", program, status))
+        }
+    }
+}
+
+/// Picks a clipboard backend by name: `osc52` writes to stdout, and
+/// anything else is a command for [`Program`]
+pub fn from_name(name: &str) -> Box<dyn Clipboard> {
+    match name.trim() {
+        "osc52" => Box::new(Osc52(std::io::stdout())),
+        cmd => Box::new(Program(cmd.to_owned())),
+    }
+}
+
+#[test]
+fn test_osc52() {
+    let mut c = Osc52(Vec::new());
+    c.copy("gemini://example.com/").unwrap();
+    assert_eq!(c.0, b"\x1b]52;c;Z2VtaW5pOi8vZXhhbXBsZS5jb20v\x07");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_program() {
+    assert!(Program("true".to_owned()).copy("text").is_ok());
+    assert!(Program("false".to_owned()).copy("text").is_err());
+    assert!(Program("".to_owned()).copy("text").is_err());
+}
@@ -1,10 +1,40 @@
 use anyhow::{anyhow, Result};
 
+use crate::config::LineNumbers;
+use crate::source;
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum Command {
     Exit,
     Load(url::Url),
+    /// Loads an address typed by the user, which gets the default port
+    Go(url::Url),
+    Home,
     TryLoad(String),
+    TryLoadTab(String),
+    LoadTab(url::Url),
+    SwitchTab(usize),
+    CloseTab,
+    Download(String),
+    Save(Option<String>),
+    SaveText(Option<String>),
+    Pager(String),
+    Peek(String),
+    Yank(Option<String>),
+    LineNumbers(LineNumbers),
+    Reader(bool),
+    WrapWidth(u16),
+    Certs,
+    Tls,
+    Links,
+    History,
+    ViewSource,
+    Forget(String),
+    Identities,
+    Identity(Option<String>),
+    IdentityAdd(String, String),
+    Transcript,
+    Offline,
 }
 
 impl Command {
@@ -16,17 +46,62 @@ impl Command {
             match c {
                 "q" => Ok(Command::Exit),
                 "g" => if let Some(t) = itr.next() {
-                    let mut url = url::Url::parse(t);
-                    if url == Err(url::ParseError::RelativeUrlWithoutBase) {
-                        url = url::Url::parse(&format!("gemini://{}", t));
-                    }
-                    match url {
-                        Ok(url) => Ok(Command::Load(url)),
+                    match source::parse_address(t) {
+                        Ok(url) => Ok(Command::Go(url)),
                         Err(e) => Err(anyhow!("Invalid URL {}: {}", t, e)),
                     }
                 } else {
                     Err(anyhow!("Missing URL"))
                 },
+                "home" => Ok(Command::Home),
+                "save" | "save-text" => {
+                    let path = itr.collect::<Vec<_>>().join(" ");
+                    let path = Some(path).filter(|p| !p.is_empty());
+                    Ok(if c == "save" { Command::Save(path) }
+                       else { Command::SaveText(path) })
+                },
+                "certs" => Ok(Command::Certs),
+                "tls" => Ok(Command::Tls),
+                "links" => Ok(Command::Links),
+                "history" => Ok(Command::History),
+                "source" => Ok(Command::ViewSource),
+                "transcript" => Ok(Command::Transcript),
+                "offline" => Ok(Command::Offline),
+                "close" => Ok(Command::CloseTab),
+                "set" => match (itr.next(), itr.next()) {
+                    (Some("number"), None) =>
+                        Ok(Command::LineNumbers(LineNumbers::Wrapped)),
+                    (Some("number"), Some("source")) =>
+                        Ok(Command::LineNumbers(LineNumbers::Source)),
+                    (Some("nonumber"), None) =>
+                        Ok(Command::LineNumbers(LineNumbers::Off)),
+                    (Some("reader"), None) => Ok(Command::Reader(true)),
+                    (Some("noreader"), None) => Ok(Command::Reader(false)),
+                    (Some("wrap"), Some(n)) => match n.parse() {
+                        Ok(n) => Ok(Command::WrapWidth(n)),
+                        Err(_) => Err(anyhow!("Invalid wrap width: {}", n)),
+                    },
+                    _ => Err(anyhow!("Unknown setting: {}", cmd)),
+                },
+                "identities" => Ok(Command::Identities),
+                "identity" =>
+                    Ok(Command::Identity(itr.next().map(str::to_owned))),
+                "identity-add" => match itr.next() {
+                    Some(name) => {
+                        let path = itr.collect::<Vec<_>>().join(" ");
+                        if path.is_empty() {
+                            Err(anyhow!("Missing PEM file"))
+                        } else {
+                            Ok(Command::IdentityAdd(name.to_owned(), path))
+                        }
+                    },
+                    None => Err(anyhow!("Missing identity name")),
+                },
+                "forget" => if let Some(host) = itr.next() {
+                    Ok(Command::Forget(host.to_owned()))
+                } else {
+                    Err(anyhow!("Missing hostname"))
+                },
                 _ => Err(anyhow!("Unknown command: {}", cmd))
             }
         } else {
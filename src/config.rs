@@ -0,0 +1,566 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use silo::theme::Theme;
+
+const CONFIG_FILE: &str = "titan.conf";
+const DEFAULT_HOME: &str = "gemini://gemini.circumlunar.space";
+
+const DEFAULT_CONFIG: &str = "\
+# titan configuration
+#
+# Each setting is a line of the form `key = value`.  Blank lines and lines
+# starting with '#' are ignored.  Unset keys use their built-in defaults.
+
+# Page which is loaded at startup, and by the `H` key and `:home` command.
+# home = gemini://gemini.circumlunar.space
+
+# Seconds for which a cached page is served without re-fetching it.  Cached
+# pages are also used as a fallback whenever the network is unreachable.
+# cache_max_age = 0
+
+# Seconds for which a host's resolved addresses are reused, rather than
+# looking it up again for every request.  0 disables the DNS cache.
+# dns_ttl = 60
+
+# Whether to fetch the target of the link under the cursor in the
+# background, so that following it is instant.  At most `prefetch_max`
+# fetches run at once.
+# prefetch = false
+# prefetch_max = 3
+
+# Oldest TLS version to accept: '1.2' (which Gemini requires servers to
+# support) or '1.3'.  The version and cipher suite of the most recent
+# connection are shown by `:tls`.
+# tls_min_version = 1.2
+
+# File to which the URL, status, meta, timing, size, and TLS version of
+# every request is appended, as tab-separated lines (failed requests have
+# `error` as their status).  Unset by default, which disables the
+# transcript file.  Setting TITAN_TRANSCRIPT in the environment overrides
+# this: it may be a path, or `1` to write transcript.log in the data
+# directory.
+# transcript = /path/to/transcript.log
+
+# Whether moving past the last line of a page wraps around to the first
+# (and vice versa), rather than stopping at the boundary.
+# cursor_wrap = false
+
+# How many times to automatically retry a request when the server responds
+# with status 44 (slow down), and the longest wait in seconds before each
+# retry.
+# slow_down_retries = 3
+# slow_down_max_wait = 30
+
+# Whether to cut preformatted lines which are wider than the screen (marking
+# them with '›'), rather than letting them overflow.
+# truncate_pre = false
+
+# Number of heading levels which are drawn with an underline row beneath
+# them: 1 underlines H1 with '=', and 2 also underlines H2 with '-'.
+# underline_headings = 0
+
+# Whether lines starting with '- ' are shown as list items, like those
+# starting with '* '.  The gemtext spec only allows '* '.
+# dash_lists = false
+
+# Line numbers shown in a gutter to the left of the page: 'off', 'wrapped'
+# to number each row on screen, or 'source' to number the lines of the
+# page's source.  This can also be changed with Ctrl-G or `:set number`.
+# line_numbers = off
+
+# Whether bare links (which show their URL) are drawn with percent-encoded
+# characters decoded, e.g. `%C3%A9t%C3%A9` as `été`.  The link still goes
+# to the URL as written.
+# decode_urls = false
+
+# Whether a scroll bar is drawn along the right edge of pages which are
+# too long to fit on screen, showing which part of the page is visible.
+# scrollbar = true
+
+# Whether to draw pages using only ASCII, with `* ` and `-> ` in place of
+# the bullets and arrows before list items and links, for terminals or
+# fonts which can't show them.  This is turned on automatically when the
+# locale (LC_ALL, LC_CTYPE, or LANG) is set to something other than UTF-8.
+# ascii = false
+
+# Widest column to which text is wrapped, so that lines stay readable on
+# very wide terminals (or 0 to use the terminal's whole width).  The column
+# sits at the left edge unless `wrap_centered` is set.  This can also be
+# changed with `:set wrap N`, or narrowed and widened with `[` and `]`.
+# wrap_width = 0
+# wrap_centered = false
+
+# Whether to start in reader mode, which hides the status bar and centers
+# the page in a column of at most `reader_width` characters.  This can also
+# be toggled with `R` or `:set reader`.
+# reader_mode = false
+# reader_width = 80
+
+# Whether runs of consecutive blank lines are drawn as a single blank line,
+# rather than exactly as written.
+# collapse_blank_lines = false
+
+# Seconds without input after which the page is dimmed, until the next
+# key press.  0 disables dimming.
+# idle_dim = 0
+
+# Whether clicking a segment of the breadcrumb (the path of the current
+# page, shown in the status bar) loads that segment's directory.
+# breadcrumb_clicks = false
+
+# What to do when an empty answer is given to an input prompt, which many
+# servers treat as no answer and prompt again: 'cancel' returns to the
+# page, 'confirm' asks first, and 'send' sends it anyway.
+# empty_input = cancel
+
+# Whether quitting (with Ctrl-C or `:q`) asks for confirmation first when
+# more than one tab is open, since their pages would be lost.
+# confirm_quit = true
+
+# Gateway through which links with other schemes are fetched, as a URL in
+# which {url} is replaced by the link's (percent-encoded) URL, and the
+# schemes it handles.  Links which it doesn't cover are opened with the
+# system's default handler.
+# gateway = gemini://gateway.example/?url={url}
+# gateway_schemes = http, https
+
+# Port used for typed gemini addresses which don't give one, e.g. to point
+# titan at a local server while testing (links and redirects keep the usual
+# port), and shorthand schemes which are expanded to others (as
+# `alias=scheme` pairs), so that `g://example.com` can be typed or linked
+# for `gemini://example.com`.
+# default_port = 1965
+# scheme_aliases = g=gemini, gem=gemini
+
+# Whether to check that a server's certificate is valid for its hostname
+# before trusting it, rather than trusting any certificate on first use.
+# strict_hostnames = false
+
+# Whether to remember which links have been followed across sessions,
+# rather than only for the current session.
+# remember_visited = false
+
+# Number of URLs kept in the history shown by :history, with the oldest
+# visits dropped first (or 0 to keep no history).
+# history_size = 1000
+
+# How text is copied to the clipboard: 'osc52' asks the terminal to do it
+# (which works over SSH, if the terminal supports it), and anything else is
+# a command into which the text is piped, e.g. `xclip -selection clipboard`
+# or `pbcopy`.
+# clipboard = osc52
+
+# Command (with arguments) to which a preformatted block is piped when
+# pressing 'p' on it.
+# pager = less
+
+# Colors are set separately in theme.conf, alongside this file.  Each line
+# of it maps a kind of line (text, h1, h2, h3, list, quote, link, or pre) to
+# a color, e.g. `h1 = red` or `quote = white on dark_grey`.  Links which
+# have already been followed use the `visited` color.
+";
+
+/// What to do with an empty answer to an input prompt
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum EmptyInput {
+    Cancel,
+    Confirm,
+    Send,
+}
+
+impl FromStr for EmptyInput {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "cancel" => Ok(EmptyInput::Cancel),
+            "confirm" => Ok(EmptyInput::Confirm),
+            "send" => Ok(EmptyInput::Send),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Oldest TLS version which is accepted when connecting
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+impl FromStr for TlsVersion {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "1.2" => Ok(TlsVersion::Tls12),
+            "1.3" => Ok(TlsVersion::Tls13),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Which line numbers are drawn in the gutter
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LineNumbers {
+    Off,
+    Wrapped,
+    Source,
+}
+
+impl LineNumbers {
+    /// Returns the next mode, for cycling through them with a single key
+    pub fn next(self) -> LineNumbers {
+        match self {
+            LineNumbers::Off => LineNumbers::Wrapped,
+            LineNumbers::Wrapped => LineNumbers::Source,
+            LineNumbers::Source => LineNumbers::Off,
+        }
+    }
+}
+
+impl FromStr for LineNumbers {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "off" => Ok(LineNumbers::Off),
+            "wrapped" => Ok(LineNumbers::Wrapped),
+            "source" => Ok(LineNumbers::Source),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    /// Start page
+    pub home: url::Url,
+
+    /// Seconds for which cached pages are served without hitting the network
+    pub cache_max_age: u64,
+
+    /// Seconds for which resolved addresses are cached
+    pub dns_ttl: u64,
+
+    /// Fetch the link under the cursor in the background
+    pub prefetch: bool,
+
+    /// Most background fetches which run at once
+    pub prefetch_max: usize,
+
+    /// Oldest TLS version to accept
+    pub tls_min_version: TlsVersion,
+
+    /// File to which a transcript of every response is appended
+    pub transcript: Option<PathBuf>,
+
+    /// Wrap the cursor between the top and bottom of the page
+    pub cursor_wrap: bool,
+
+    /// Number of automatic retries after a SlowDown response
+    pub slow_down_retries: u32,
+
+    /// Longest time (in seconds) to wait before retrying after SlowDown
+    pub slow_down_max_wait: u64,
+
+    /// Cut overly long preformatted lines at the screen width
+    pub truncate_pre: bool,
+
+    /// Number of heading levels which are underlined
+    pub underline_headings: u8,
+
+    /// Parse lines starting with "- " as list items
+    pub dash_lists: bool,
+
+    /// Line numbers drawn in the gutter
+    pub line_numbers: LineNumbers,
+
+    /// Show bare links' URLs percent-decoded
+    pub decode_urls: bool,
+
+    /// Draw a scroll bar on long pages
+    pub scrollbar: bool,
+
+    /// Hide the status bar and center the page in a narrower column
+    pub reader_mode: bool,
+
+    /// Draw decoration using only ASCII characters
+    pub ascii: bool,
+
+    /// Widest column of wrapped text, or 0 for the terminal's width
+    pub wrap_width: u16,
+
+    /// Center the column of wrapped text, rather than aligning it left
+    pub wrap_centered: bool,
+
+    /// Widest column of text in reader mode
+    pub reader_width: u16,
+
+    /// Draw runs of blank lines as a single blank line
+    pub collapse_blank_lines: bool,
+
+    /// Seconds of inactivity before dimming the page, or 0 to never dim
+    pub idle_dim: u64,
+
+    /// Load a breadcrumb segment's URL when it's clicked
+    pub breadcrumb_clicks: bool,
+
+    /// How to handle empty answers to input prompts
+    pub empty_input: EmptyInput,
+
+    /// Ask before quitting with more than one tab open
+    pub confirm_quit: bool,
+
+    /// Template for fetching other schemes through a gateway
+    pub gateway: Option<String>,
+
+    /// Schemes which are fetched through the gateway
+    pub gateway_schemes: Vec<String>,
+
+    /// Port for typed gemini addresses which don't specify one
+    pub default_port: u16,
+
+    /// Schemes which are rewritten to others, as (alias, scheme) pairs
+    pub scheme_aliases: Vec<(String, String)>,
+
+    /// Reject certificates which don't cover the requested hostname
+    pub strict_hostnames: bool,
+
+    /// Store visited URLs in the database
+    pub remember_visited: bool,
+
+    /// Number of entries kept in the persistent history
+    pub history_size: usize,
+
+    /// Clipboard backend, either "osc52" or a command
+    pub clipboard: String,
+
+    /// Command to which preformatted blocks are piped
+    pub pager: String,
+
+    /// Colors for each kind of line, loaded from a separate theme file
+    pub theme: Theme,
+
+    /// Non-fatal problems found while loading, shown to the user at startup
+    pub warnings: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            warnings: Vec::new(),
+            home: url::Url::parse(DEFAULT_HOME).unwrap(),
+            cache_max_age: 0,
+            dns_ttl: 60,
+            prefetch: false,
+            prefetch_max: 3,
+            tls_min_version: TlsVersion::Tls12,
+            transcript: None,
+            cursor_wrap: false,
+            slow_down_retries: 3,
+            slow_down_max_wait: 30,
+            truncate_pre: false,
+            underline_headings: 0,
+            dash_lists: false,
+            line_numbers: LineNumbers::Off,
+            decode_urls: false,
+            scrollbar: true,
+            ascii: false,
+            reader_mode: false,
+            wrap_width: 0,
+            wrap_centered: false,
+            reader_width: 80,
+            collapse_blank_lines: false,
+            idle_dim: 0,
+            breadcrumb_clicks: false,
+            empty_input: EmptyInput::Cancel,
+            confirm_quit: true,
+            gateway: None,
+            gateway_schemes: vec!["http".to_owned(), "https".to_owned()],
+            default_port: 1965,
+            scheme_aliases: Vec::new(),
+            strict_hostnames: false,
+            remember_visited: false,
+            history_size: 1000,
+            clipboard: "osc52".to_owned(),
+            pager: "less".to_owned(),
+            theme: Theme::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file from the given directory, creating the directory
+    /// and a default config file on first run.  Any failure (e.g. a read-only
+    /// home directory) falls back to the built-in defaults.
+    pub fn load(dir: &Path) -> Config {
+        let path = dir.join(CONFIG_FILE);
+        if !path.exists() {
+            if let Err(e) = Self::write_default(dir, &path) {
+                let mut c = Config::default();
+                c.warnings.push(format!("Could not create {}: {}",
+                                        path.display(), e));
+                return c;
+            }
+        }
+        let mut c = match std::fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text),
+            Err(e) => {
+                let mut c = Config::default();
+                c.warnings.push(format!("Could not read {}: {}",
+                                        path.display(), e));
+                c
+            },
+        };
+        c.theme = Theme::load(dir, &mut c.warnings);
+        c
+    }
+
+    fn write_default(dir: &Path, path: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(path, DEFAULT_CONFIG)
+    }
+
+    /// Parses the text of a config file.  Malformed lines and unknown keys
+    /// are recorded as warnings rather than failing.
+    pub fn parse(text: &str) -> Config {
+        let mut c = Config::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((k, v)) => (k.trim(), v.trim()),
+                None => {
+                    c.warnings.push(format!("Config line {}: expected \
+                                             `key = value`", i + 1));
+                    continue;
+                },
+            };
+            let w = &mut c.warnings;
+            match key {
+                "home" => set(&mut c.home, w, i, key, value),
+                "cache_max_age" => set(&mut c.cache_max_age, w, i, key, value),
+                "dns_ttl" => set(&mut c.dns_ttl, w, i, key, value),
+                "prefetch" => set(&mut c.prefetch, w, i, key, value),
+                "prefetch_max" => set(&mut c.prefetch_max, w, i, key, value),
+                "tls_min_version" =>
+                    set(&mut c.tls_min_version, w, i, key, value),
+                "transcript" => c.transcript = Some(PathBuf::from(value)),
+                "cursor_wrap" => set(&mut c.cursor_wrap, w, i, key, value),
+                "slow_down_retries" =>
+                    set(&mut c.slow_down_retries, w, i, key, value),
+                "slow_down_max_wait" =>
+                    set(&mut c.slow_down_max_wait, w, i, key, value),
+                "truncate_pre" => set(&mut c.truncate_pre, w, i, key, value),
+                "underline_headings" =>
+                    set(&mut c.underline_headings, w, i, key, value),
+                "dash_lists" => set(&mut c.dash_lists, w, i, key, value),
+                "line_numbers" => set(&mut c.line_numbers, w, i, key, value),
+                "decode_urls" => set(&mut c.decode_urls, w, i, key, value),
+                "scrollbar" => set(&mut c.scrollbar, w, i, key, value),
+                "ascii" => set(&mut c.ascii, w, i, key, value),
+                "reader_mode" => set(&mut c.reader_mode, w, i, key, value),
+                "wrap_width" => set(&mut c.wrap_width, w, i, key, value),
+                "wrap_centered" =>
+                    set(&mut c.wrap_centered, w, i, key, value),
+                "reader_width" => set(&mut c.reader_width, w, i, key, value),
+                "collapse_blank_lines" =>
+                    set(&mut c.collapse_blank_lines, w, i, key, value),
+                "idle_dim" => set(&mut c.idle_dim, w, i, key, value),
+                "breadcrumb_clicks" =>
+                    set(&mut c.breadcrumb_clicks, w, i, key, value),
+                "empty_input" => set(&mut c.empty_input, w, i, key, value),
+                "confirm_quit" => set(&mut c.confirm_quit, w, i, key, value),
+                "gateway" => c.gateway = Some(value.to_owned()),
+                "gateway_schemes" => c.gateway_schemes = value.split(',')
+                    .map(|s| s.trim().to_owned())
+                    .filter(|s| !s.is_empty())
+                    .collect(),
+                "default_port" => set(&mut c.default_port, w, i, key, value),
+                "scheme_aliases" => {
+                    c.scheme_aliases.clear();
+                    for pair in value.split(',').map(str::trim)
+                        .filter(|s| !s.is_empty())
+                    {
+                        match pair.split_once('=') {
+                            Some((a, s)) => c.scheme_aliases.push(
+                                (a.trim().to_ascii_lowercase(),
+                                 s.trim().to_ascii_lowercase())),
+                            None => w.push(format!(
+                                "Config line {}: invalid {} `{}`",
+                                i + 1, key, pair)),
+                        }
+                    }
+                },
+                "strict_hostnames" =>
+                    set(&mut c.strict_hostnames, w, i, key, value),
+                "remember_visited" =>
+                    set(&mut c.remember_visited, w, i, key, value),
+                "history_size" => set(&mut c.history_size, w, i, key, value),
+                "clipboard" => c.clipboard = value.to_owned(),
+                "pager" => c.pager = value.to_owned(),
+                _ => w.push(format!(
+                    "Config line {}: unknown key `{}`", i + 1, key)),
+            }
+        }
+        c
+    }
+}
+
+/// Parses a config value into `out`, recording a warning if it's invalid
+fn set<T: FromStr>(out: &mut T, warnings: &mut Vec<String>, i: usize,
+                   key: &str, value: &str)
+{
+    match value.parse() {
+        Ok(v) => *out = v,
+        Err(_) => warnings.push(format!(
+            "Config line {}: invalid {} `{}`", i + 1, key, value)),
+    }
+}
+
+#[test]
+fn test_first_run() {
+    let dir = std::env::temp_dir()
+        .join(format!("titan-config-test-{}", std::process::id()))
+        .join("nested");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let c = Config::load(&dir);
+    assert!(c.warnings.is_empty());
+    assert_eq!(std::fs::read_to_string(dir.join(CONFIG_FILE)).unwrap(),
+               DEFAULT_CONFIG);
+
+    // Loading a second time reads the existing file
+    let c = Config::load(&dir);
+    assert!(c.warnings.is_empty());
+
+    std::fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_parse_warnings() {
+    let c = Config::parse("# comment\n\nnonsense\nfoo = bar\n");
+    assert_eq!(c.warnings.len(), 2);
+
+    let c = Config::parse("cache_max_age = 60\ncache_max_age = soon");
+    assert_eq!(c.cache_max_age, 60);
+    assert_eq!(c.warnings.len(), 1);
+
+    // An invalid home page is reported, keeping the default
+    let c = Config::parse("home = not a url");
+    assert_eq!(c.home, Config::default().home);
+    assert_eq!(c.warnings.len(), 1);
+    let c = Config::parse("home = gemini://example.com/");
+    assert_eq!(c.home.as_str(), "gemini://example.com/");
+
+    let c = Config::parse("scheme_aliases = g=gemini, nonsense,gem = Gemini");
+    assert_eq!(c.scheme_aliases,
+               vec![("g".to_owned(), "gemini".to_owned()),
+                    ("gem".to_owned(), "gemini".to_owned())]);
+    assert_eq!(c.warnings.len(), 1);
+
+    let c = Config::parse("tls_min_version = 1.3\ntls_min_version = 1.1");
+    assert_eq!(c.tls_min_version, TlsVersion::Tls13);
+    assert_eq!(c.warnings.len(), 1);
+}
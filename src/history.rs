@@ -0,0 +1,185 @@
+use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use silo::fetch::{Redirect, RedirectKind};
+
+/// Persistent log of visited URLs, stored in the database as URL → visit
+/// time (big-endian seconds since the epoch).  Keying by URL means that a
+/// revisit replaces the old entry, so each URL appears once with its latest
+/// visit time.
+///
+/// Writes go to sled's in-memory log (which is flushed in the background),
+/// and pruning down to the size limit only happens once the log has grown
+/// past it by a margin, so recording a visit is cheap on the fetch path.
+pub struct History {
+    db: sled::Tree,
+    max: usize,
+    len: usize,
+}
+
+impl History {
+    /// Opens the history, keeping at most `max` entries
+    pub fn new(db: &sled::Db, max: usize) -> Result<History> {
+        let db = db.open_tree("history")?;
+        let len = db.len();
+        let mut h = History { db, max, len };
+        h.prune()?;
+        Ok(h)
+    }
+
+    /// Records a visit to the given URL at the current time
+    pub fn record(&mut self, url: &url::Url) -> Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.insert(url, now)
+    }
+
+    fn insert(&mut self, url: &url::Url, time: u64) -> Result<()> {
+        if self.max == 0 {
+            return Ok(());
+        }
+        if self.db.insert(url.as_str(), &time.to_be_bytes())?.is_none() {
+            self.len += 1;
+        }
+        if self.len > self.max + self.max / 8 {
+            self.prune()?;
+        }
+        Ok(())
+    }
+
+    /// Updates the history after `url` redirected.  A permanent redirect
+    /// means that the URL has moved, so its entry is moved to the target
+    /// (unless the target already has one); temporary redirects change
+    /// nothing.
+    pub fn redirected(&mut self, url: &url::Url, r: &Redirect) -> Result<()> {
+        if r.kind != RedirectKind::Permanent {
+            return Ok(());
+        }
+        if let Some(time) = self.db.remove(url.as_str())? {
+            if self.db.contains_key(r.target.as_str())? {
+                self.len -= 1;
+            } else {
+                self.db.insert(r.target.as_str(), time)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns every entry as `(url, time)`, newest first
+    pub fn entries(&self) -> Result<Vec<(String, u64)>> {
+        let mut out = Vec::new();
+        for kv in self.db.iter() {
+            let (k, v) = kv?;
+            let time = v.as_ref().try_into().map(u64::from_be_bytes)
+                .unwrap_or(0);
+            out.push((String::from_utf8_lossy(&k).into_owned(), time));
+        }
+        out.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(out)
+    }
+
+    /// Removes the oldest entries until there are at most `max`
+    fn prune(&mut self) -> Result<()> {
+        if self.len <= self.max {
+            return Ok(());
+        }
+        let entries = self.entries()?;
+        for (url, _) in &entries[self.max..] {
+            self.db.remove(url.as_bytes())?;
+        }
+        self.len = self.max;
+        Ok(())
+    }
+}
+
+/// Formats a time as `YYYY-MM-DD HH:MM` (in UTC)
+pub fn format_time(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let (h, m) = ((secs % 86400) / 3600, (secs % 3600) / 60);
+
+    // Converts days since the epoch to a civil date, following
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, h, m)
+}
+
+#[test]
+fn test_history() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let url = |s: &str| url::Url::parse(s).unwrap();
+
+    let mut h = History::new(&db, 3).unwrap();
+    h.insert(&url("gemini://a.example/"), 10).unwrap();
+    h.insert(&url("gemini://b.example/"), 20).unwrap();
+    h.insert(&url("gemini://a.example/"), 30).unwrap();
+    assert_eq!(h.entries().unwrap(), vec![
+        ("gemini://a.example/".to_owned(), 30),
+        ("gemini://b.example/".to_owned(), 20),
+    ]);
+
+    // Going over the limit drops the oldest entries
+    h.insert(&url("gemini://c.example/"), 40).unwrap();
+    h.insert(&url("gemini://d.example/"), 50).unwrap();
+    assert_eq!(h.entries().unwrap().len(), 3);
+    assert!(!h.entries().unwrap().iter().any(|(u, _)| u.contains("b.")));
+
+    // Reopening with a smaller limit prunes immediately
+    let h = History::new(&db, 1).unwrap();
+    assert_eq!(h.entries().unwrap(),
+               vec![("gemini://d.example/".to_owned(), 50)]);
+
+    let mut h = History::new(&db, 0).unwrap();
+    h.record(&url("gemini://e.example/")).unwrap();
+    assert!(h.entries().unwrap().is_empty());
+}
+
+#[test]
+fn test_history_redirect() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let url = |s: &str| url::Url::parse(s).unwrap();
+    let redirect = |kind, s| Redirect { kind, target: url(s) };
+
+    let mut h = History::new(&db, 10).unwrap();
+    h.insert(&url("gemini://a.example/old"), 10).unwrap();
+    h.insert(&url("gemini://b.example/"), 20).unwrap();
+
+    // Temporary redirects leave the stored URL alone
+    h.redirected(&url("gemini://a.example/old"),
+                 &redirect(RedirectKind::Temporary, "gemini://a.example/tmp"))
+        .unwrap();
+    assert_eq!(h.entries().unwrap()[1].0, "gemini://a.example/old");
+
+    // Permanent ones rewrite it
+    h.redirected(&url("gemini://a.example/old"),
+                 &redirect(RedirectKind::Permanent, "gemini://a.example/new"))
+        .unwrap();
+    assert_eq!(h.entries().unwrap(), vec![
+        ("gemini://b.example/".to_owned(), 20),
+        ("gemini://a.example/new".to_owned(), 10),
+    ]);
+
+    // A target which is already in the history keeps its own entry
+    h.redirected(&url("gemini://a.example/new"),
+                 &redirect(RedirectKind::Permanent, "gemini://b.example/"))
+        .unwrap();
+    assert_eq!(h.entries().unwrap(),
+               vec![("gemini://b.example/".to_owned(), 20)]);
+    assert_eq!(h.len, 1);
+}
+
+#[test]
+fn test_format_time() {
+    assert_eq!(format_time(0), "1970-01-01 00:00");
+    assert_eq!(format_time(951782400 + 3600 + 120), "2000-02-29 01:02");
+    assert_eq!(format_time(1700000000), "2023-11-14 22:13");
+}
@@ -0,0 +1,71 @@
+use std::time::{Duration, Instant};
+
+/// Tracks how long it's been since the last input, so that the screen can
+/// be dimmed after a period of inactivity
+pub struct Idle {
+    timeout: Option<Duration>, // None if dimming is disabled
+    last: Instant,
+    dimmed: bool,
+}
+
+impl Idle {
+    /// Builds a tracker which dims after the given number of seconds, or
+    /// never if it's zero
+    pub fn new(secs: u64, now: Instant) -> Idle {
+        let timeout = Some(Duration::from_secs(secs)).filter(|_| secs > 0);
+        Idle { timeout, last: now, dimmed: false }
+    }
+
+    /// Returns how long to wait for input before dimming, or None to wait
+    /// indefinitely (because dimming is disabled or has already happened)
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        if self.dimmed {
+            return None;
+        }
+        self.timeout.map(|t| t.saturating_sub(now - self.last))
+    }
+
+    /// Checks whether the idle period has elapsed, returning true if the
+    /// screen should be dimmed now
+    pub fn check(&mut self, now: Instant) -> bool {
+        if self.remaining(now) == Some(Duration::from_secs(0)) {
+            self.dimmed = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Records an input event, returning true if the screen was dimmed
+    /// (and so should be restored)
+    pub fn input(&mut self, now: Instant) -> bool {
+        self.last = now;
+        std::mem::replace(&mut self.dimmed, false)
+    }
+}
+
+#[test]
+fn test_idle() {
+    let t0 = Instant::now();
+    let s = Duration::from_secs;
+
+    let mut idle = Idle::new(0, t0);
+    assert_eq!(idle.remaining(t0 + s(1000)), None);
+    assert!(!idle.check(t0 + s(1000)));
+
+    let mut idle = Idle::new(60, t0);
+    assert_eq!(idle.remaining(t0 + s(10)), Some(s(50)));
+    assert!(!idle.check(t0 + s(59)));
+
+    // Input resets the timer
+    assert!(!idle.input(t0 + s(30)));
+    assert!(!idle.check(t0 + s(60)));
+    assert!(idle.check(t0 + s(90)));
+
+    // Once dimmed, we wait indefinitely, and the next input restores it
+    assert_eq!(idle.remaining(t0 + s(100)), None);
+    assert!(!idle.check(t0 + s(200)));
+    assert!(idle.input(t0 + s(200)));
+    assert!(!idle.input(t0 + s(201)));
+    assert_eq!(idle.remaining(t0 + s(201)), Some(s(60)));
+}
@@ -1,10 +1,12 @@
 use std::io::Write;
+use std::time::Duration;
 
+use anyhow::Result;
 use crossterm::{
     cursor,
     execute,
     cursor::MoveLeft,
-    event::{read, Event, KeyCode, KeyEvent, KeyModifiers},
+    event::{poll, read, Event, KeyCode, KeyEvent, KeyModifiers},
     style::{Print},
 };
 
@@ -23,25 +25,65 @@ impl Input {
         Input(String::new())
     }
 
-    pub fn run(&mut self) -> Option<String> {
+    /// Builds an input which starts out holding the given text, so that it
+    /// can be edited rather than retyped
+    pub fn with_text(text: &str) -> Input {
+        Input(text.to_owned())
+    }
+
+    /// Reads a line of input, returning `Ok(None)` if the user cancelled it
+    /// (with Esc or Ctrl-C).  Errors are reserved for failing to read from
+    /// the terminal, so that callers can tell the two apart.
+    pub fn run(&mut self) -> Result<Option<String>> {
         execute!(std::io::stdout(),
             cursor::Show,
-        ).expect("Failed to execute");
+            Print(&self.0),
+        )?;
+        let mut next = None;
         loop {
-            let evt = read().expect("Failed to read event");
-            match evt {
+            let event = match next.take() {
+                Some(e) => e,
+                None => read()?,
+            };
+            match event {
                 Event::Key(KeyEvent { code: KeyCode::Enter, .. }) => {
-                    return Some(self.0.clone());
+                    return Ok(Some(self.0.clone()));
+                },
+                // A paste arrives as a burst of key events, so characters
+                // which are already waiting are inserted and drawn together
+                // rather than one at a time
+                Event::Key(KeyEvent { code: KeyCode::Char(c), modifiers })
+                    if !modifiers.contains(KeyModifiers::CONTROL) =>
+                {
+                    let waiting = std::iter::from_fn(|| {
+                        match poll(Duration::from_secs(0)) {
+                            Ok(true) => Some(read().map_err(Into::into)),
+                            Ok(false) => None,
+                            Err(e) => Some(Err(e.into())),
+                        }
+                    });
+                    let (text, e) = burst(c, waiting)?;
+                    next = e;
+                    self.insert(&text)?;
                 },
                 Event::Key(event) =>
                     if !self.key(event) {
-                        return None;
+                        return Ok(None);
                     }
                 _ => continue,
             }
         }
     }
 
+    /// Inserts text at the end of the buffer, cleaning up control characters
+    /// so that pasted text can't smuggle in escape sequences
+    fn insert(&mut self, text: &str) -> Result<()> {
+        let text = printable(text);
+        self.0.push_str(&text);
+        execute!(std::io::stdout(), Print(text))?;
+        Ok(())
+    }
+
     fn key(&mut self, k: KeyEvent) -> bool {
         let sigint = k.code == KeyCode::Char('c') &&
                      k.modifiers == KeyModifiers::CONTROL;
@@ -54,15 +96,13 @@ impl Input {
         // Otherwise, edit the buffer and redraw
         let mut out = std::io::stdout();
         match k.code {
-            KeyCode::Backspace => {
-                if !self.0.is_empty() {
-                    self.0.pop();
-                    execute!(&mut out,
-                        MoveLeft(1),
-                        Print(" "),
-                        MoveLeft(1),
-                    ).expect("Failed to execute");
-                }
+            KeyCode::Backspace if !self.0.is_empty() => {
+                self.0.pop();
+                execute!(&mut out,
+                    MoveLeft(1),
+                    Print(" "),
+                    MoveLeft(1),
+                ).expect("Failed to execute");
             },
             KeyCode::Char(r) => {
                 self.0.push(r);
@@ -75,3 +115,77 @@ impl Input {
         true
     }
 }
+
+/// Collects a burst of typed characters (e.g. a paste) which follows `c`
+/// from events which are already waiting, returning its text along with the
+/// first event which isn't part of it.  Enter and Tab are part of a burst,
+/// since they're line breaks and tabs within the pasted text, so a paste
+/// which spans lines doesn't submit the input partway through.
+fn burst<I>(c: char, events: I) -> Result<(String, Option<Event>)>
+    where I: Iterator<Item=Result<Event>>
+{
+    let mut text = c.to_string();
+    for e in events {
+        match e? {
+            Event::Key(KeyEvent { code: KeyCode::Char(c), modifiers })
+                if !modifiers.contains(KeyModifiers::CONTROL) => text.push(c),
+            Event::Key(KeyEvent { code: KeyCode::Enter, .. }) =>
+                text.push('\n'),
+            Event::Key(KeyEvent { code: KeyCode::Tab, .. }) =>
+                text.push('\t'),
+            e => return Ok((text, Some(e))),
+        }
+    }
+    Ok((text, None))
+}
+
+/// Turns tabs and line breaks into spaces, since the input is a single
+/// line, and removes other control characters
+fn printable(text: &str) -> String {
+    text.replace("\r\n", " ")
+        .chars()
+        .filter_map(|c| match c {
+            '\t' | '\r' | '\n' => Some(' '),
+            c if c.is_control() => None,
+            c => Some(c),
+        })
+        .collect()
+}
+
+#[test]
+fn test_printable() {
+    assert_eq!(printable("gemini://example.com/"), "gemini://example.com/");
+    assert_eq!(printable("a\tb\r\nc\x1b[2Jd\u{9b}é"), "a b c[2Jdé");
+}
+
+#[test]
+fn test_burst() {
+    let key = |code| Ok(Event::Key(KeyEvent::new(code, KeyModifiers::NONE)));
+    let chars = |s: &str| s.chars().map(KeyCode::Char).map(key).collect();
+
+    // A paste which spans lines is kept whole, rather than being submitted
+    // at its first line break
+    let mut events: Vec<_> = chars("ello");
+    events.push(key(KeyCode::Enter));
+    events.extend(chars("world"));
+    events.push(key(KeyCode::Tab));
+    events.push(key(KeyCode::Char('!')));
+    let (text, next) = burst('h', events.into_iter()).unwrap();
+    assert_eq!(text, "hello\nworld\t!");
+    assert_eq!(next, None);
+    assert_eq!(printable(&text), "hello world !");
+
+    // The burst stops at anything which isn't text, like Ctrl-C or Esc
+    let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+    let events = vec![key(KeyCode::Char('b')), Ok(Event::Key(ctrl_c)),
+                      key(KeyCode::Char('d'))];
+    let (text, next) = burst('a', events.into_iter()).unwrap();
+    assert_eq!(text, "ab");
+    assert_eq!(next, Some(Event::Key(ctrl_c)));
+
+    let events = vec![key(KeyCode::Esc)];
+    let (text, next) = burst('a', events.into_iter()).unwrap();
+    assert_eq!(text, "a");
+    assert_eq!(next, Some(Event::Key(KeyEvent::new(KeyCode::Esc,
+                                                   KeyModifiers::NONE))));
+}
@@ -1,22 +1,137 @@
-use anyhow::Result;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
 
 mod app;
+mod breadcrumb;
+mod caps;
+mod clipboard;
 mod command;
+mod config;
+mod history;
+mod idle;
 mod input;
+mod prefetch;
+mod preview;
+mod print;
+mod screen;
+mod source;
+mod tab;
+mod transcript;
 mod view;
+mod visited;
 mod wrapped;
 
 use crate::app::App;
+use crate::command::Command;
+use crate::config::Config;
+
+use silo::cache::Cache;
 
 ////////////////////////////////////////////////////////////////////////////////
 
 fn main() -> Result<()> {
     let dirs = directories::ProjectDirs::from("com", "mkeeter", "titan")
-        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other,
-                                           "Could not get ProjectDirs"))?;
+        .ok_or_else(|| std::io::Error::other("Could not get ProjectDirs"))?;
     let db = sled::open(dirs.data_dir())?;
+    let mut config = Config::load(dirs.config_dir());
+    if let Some(path) = std::env::var_os("TITAN_TRANSCRIPT") {
+        config.transcript = Some(if path == "1" {
+            dirs.data_dir().join("transcript.log")
+        } else {
+            path.into()
+        });
+    }
+
+    let args = Args::parse(std::env::args().skip(1))?;
+    // In cached mode, any page in the cache is served regardless of its age
+    if args.cached {
+        config.cache_max_age = u64::MAX;
+    }
+
+    // If a URL is given on the command line, print it instead of browsing
+    if let Some(url) = args.target {
+        let url = source::coerce_typed(url, &config.scheme_aliases,
+                                       config.default_port)?;
+        let tls = app::client_config(app::verifier(&db, &config)?,
+                                     config.tls_min_version);
+        let cache = Cache::new(&db,
+            Duration::from_secs(config.cache_max_age))?;
+        let code = print::run(&tls, &cache, url, args.raw)?;
+        // Exiting skips destructors, so the database (with any certificates
+        // pinned and pages cached while printing) must be flushed first
+        db.flush()?;
+        std::process::exit(code);
+    }
 
-    let mut app = App::new(&db)?;
-    app.run(url::Url::parse("gemini://gemini.circumlunar.space")?)?;
+    screen::install_panic_hook();
+    let home = config.home.clone();
+    let mut app = App::new(&db, config)?;
+    app.startup(args.command, args.search);
+    app.run(home)?;
     Ok(())
 }
+
+/// Command-line arguments
+#[derive(Debug, Default, Eq, PartialEq)]
+struct Args {
+    cached: bool,
+    raw: bool,
+    /// URL to print, rather than browsing
+    target: Option<url::Url>,
+    /// Command to run once the home page is shown, from `--command`
+    command: Option<Command>,
+    /// Text to find on the home page, from `--search`
+    search: Option<String>,
+}
+
+impl Args {
+    fn parse<I: Iterator<Item=String>>(mut args: I) -> Result<Args> {
+        let mut out = Args::default();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next()
+                .ok_or_else(|| anyhow!("Missing value for {}", arg));
+            match arg.as_str() {
+                "--cached" => out.cached = true,
+                "--raw" => out.raw = true,
+                // The command is checked here, so that a typo is reported
+                // before the browser takes over the terminal
+                "--command" => {
+                    let cmd = value()?;
+                    let cmd = cmd.strip_prefix(':').unwrap_or(&cmd);
+                    out.command = Some(Command::parse(cmd.to_owned())
+                        .map_err(|e| anyhow!("Invalid --command: {}", e))?);
+                },
+                "--search" => out.search = Some(value()?),
+                a if a.starts_with("--") =>
+                    return Err(anyhow!("Unknown flag {}", a)),
+                _ => out.target = Some(source::parse_address(&arg)?),
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[test]
+fn test_args() {
+    let parse = |s: &[&str]| Args::parse(s.iter().map(|s| s.to_string()));
+
+    let a = parse(&["--command", ":g gemini://host/x"]).unwrap();
+    let url = url::Url::parse("gemini://host/x").unwrap();
+    assert_eq!(a.command, Some(Command::Go(url)));
+    assert_eq!(parse(&["--command", "certs"]).unwrap().command,
+               Some(Command::Certs));
+    assert!(parse(&["--command", ":bogus"]).is_err());
+    assert!(parse(&["--command"]).is_err());
+
+    let a = parse(&["--search", "gemini", "--cached"]).unwrap();
+    assert_eq!(a.search.as_deref(), Some("gemini"));
+    assert!(a.cached);
+    assert!(parse(&["--bogus"]).is_err());
+
+    // Addresses are parsed as in the `g` command
+    let url = url::Url::parse("gemini://example.com/x").unwrap();
+    assert_eq!(parse(&["example.com/x"]).unwrap().target, Some(url.clone()));
+    assert_eq!(parse(&["--command", "g example.com/x"]).unwrap().command,
+               Some(Command::Go(url)));
+}
@@ -0,0 +1,147 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use silo::dns::DnsCache;
+use silo::fetch;
+use silo::parser::parse_response;
+use silo::protocol::Status;
+
+/// How long a prefetched response is kept before it's considered stale
+pub const PREFETCH_TTL: Duration = Duration::from_secs(60);
+
+/// Most prefetched responses which are kept waiting to be used
+const MAX_STORED: usize = 16;
+
+/// Largest body which is prefetched; bigger pages are left for the
+/// foreground, where their size is checked as usual
+const MAX_BODY: usize = 1 << 20;
+
+/// How long a background fetch may run before it's abandoned
+const DEADLINE: Duration = Duration::from_secs(30);
+
+/// Fetches link targets on background threads before they're followed, so
+/// that following them is instant.  Only successful `text/*` responses are
+/// kept: anything else (redirects, input prompts, errors, downloads) is
+/// fetched again when the link is followed, so that it's handled in the
+/// foreground as usual.
+pub struct Prefetch {
+    max: usize, // Most fetches which run at once
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    pending: HashSet<String>,
+    done: HashMap<String, (Instant, Vec<u8>)>,
+}
+
+impl Prefetch {
+    pub fn new(max: usize) -> Prefetch {
+        Prefetch { max, state: Arc::new(Mutex::new(State::default())) }
+    }
+
+    /// Starts fetching a gemini URL in the background, unless it's already
+    /// been fetched (or is being fetched), or too many fetches are running
+    pub fn request(&self, config: &Arc<rustls::ClientConfig>,
+                   dns: &Arc<DnsCache>, url: &url::Url)
+    {
+        let config = config.clone();
+        let dns = dns.clone();
+        let target = url.clone();
+        self.request_with(url, move || {
+            let deadline = Instant::now() + DEADLINE;
+            fetch::read_text(&config, &target, Some(&dns), MAX_BODY,
+                             deadline)?
+                .ok_or_else(|| anyhow!("{} isn't a small text page", target))
+        });
+    }
+
+    fn request_with<F>(&self, url: &url::Url, f: F)
+        where F: FnOnce() -> Result<Vec<u8>> + Send + 'static
+    {
+        let k = key(url);
+        {
+            let mut s = self.state.lock().unwrap();
+            s.done.retain(|_, (t, _)| t.elapsed() < PREFETCH_TTL);
+            if s.pending.len() >= self.max || s.pending.contains(&k) ||
+               s.done.contains_key(&k)
+            {
+                return;
+            }
+            s.pending.insert(k.clone());
+        }
+        let state = self.state.clone();
+        std::thread::spawn(move || {
+            let data = f().ok().filter(|d| parse_response(d)
+                .is_ok_and(|r| r.status == Status::Success));
+            let mut s = state.lock().unwrap();
+            s.pending.remove(&k);
+            if let Some(data) = data {
+                if s.done.len() >= MAX_STORED {
+                    let oldest = s.done.iter()
+                        .min_by_key(|(_, (t, _))| *t)
+                        .map(|(k, _)| k.clone());
+                    if let Some(oldest) = oldest {
+                        s.done.remove(&oldest);
+                    }
+                }
+                s.done.insert(k, (Instant::now(), data));
+            }
+        });
+    }
+
+    /// Removes and returns the prefetched response for a URL, if there's a
+    /// fresh one
+    pub fn take(&self, url: &url::Url) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().done.remove(&key(url))
+            .filter(|(t, _)| t.elapsed() < PREFETCH_TTL)
+            .map(|(_, data)| data)
+    }
+}
+
+/// Fragments aren't sent to the server, so they don't change the response
+fn key(url: &url::Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    url.into_string()
+}
+
+#[test]
+fn test_prefetch() {
+    let url = |s| url::Url::parse(s).unwrap();
+    let wait = |p: &Prefetch, u: &url::Url| {
+        for _ in 0..200 {
+            if let Some(data) = p.take(u) {
+                return Some(data);
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        None
+    };
+
+    let p = Prefetch::new(1);
+    let a = url("gemini://example.com/a");
+    p.request_with(&a, || Ok(b"20 text/gemini\r\n# hi".to_vec()));
+    assert_eq!(wait(&p, &url("gemini://example.com/a#frag")).unwrap(),
+               b"20 text/gemini\r\n# hi");
+    assert!(p.take(&a).is_none());
+
+    // Responses which need handling in the foreground aren't kept
+    let b = url("gemini://example.com/b");
+    p.request_with(&b, || Ok(b"10 Name?\r\n".to_vec()));
+    assert!(wait(&p, &b).is_none());
+
+    // Requests beyond the limit are dropped while a fetch is running
+    let (tx, rx) = std::sync::mpsc::channel::<()>();
+    let c = url("gemini://example.com/c");
+    p.request_with(&c, move || {
+        rx.recv().unwrap();
+        Ok(b"20 text/gemini\r\nc".to_vec())
+    });
+    p.request_with(&a, || Ok(b"20 text/gemini\r\na".to_vec()));
+    tx.send(()).unwrap();
+    assert!(wait(&p, &c).is_some());
+    assert!(p.take(&a).is_none());
+}
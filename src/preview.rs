@@ -0,0 +1,129 @@
+use std::io::Write;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use crossterm::{
+    cursor,
+    queue,
+    terminal::{Clear, ClearType},
+    style::{style, Color, PrintStyledContent},
+};
+
+use silo::dns::DnsCache;
+use silo::fetch;
+use silo::markdown::parse_markdown;
+use silo::parser::{parse_response, parse_text_gemini_lossy, ParseOptions};
+use silo::protocol::Status;
+
+use crate::print;
+use crate::source;
+use crate::wrapped;
+
+/// Reads a URL for previewing.  This doesn't touch the cache or transcript,
+/// so that it can run on another thread while the page stays responsive.
+/// The `config` should already carry the host's identity, if it has one.
+pub fn read(config: &Arc<rustls::ClientConfig>, url: &url::Url,
+            dns: Option<&DnsCache>)
+    -> Result<Vec<u8>>
+{
+    match url.scheme() {
+        "gemini" => Ok(fetch::read_via(config, url, dns)?),
+        "file" => Ok(source::read_file(url)?.1),
+        "data" => source::read_data(url),
+        s => Err(anyhow!("Cannot preview {} links", s)),
+    }
+}
+
+/// Returns the text shown in the preview pane for a response, which is the
+/// page itself (wrapped to the given width) for text responses, or a short
+/// description of the response otherwise
+pub fn lines(plaintext: &[u8], width: usize, opts: ParseOptions)
+    -> Result<Vec<String>>
+{
+    let response = parse_response(plaintext)?;
+    let out = match response.status {
+        Status::Success => {
+            let mime = response.mime_type();
+            if mime.starts_with("text/") {
+                let (body, _) = fetch::decode_body_lossy(response.body);
+                let doc = match mime.as_str() {
+                    "text/gemini" =>
+                        Some(parse_text_gemini_lossy(&body, opts).1),
+                    "text/markdown" => Some(parse_markdown(&body)),
+                    _ => None,
+                };
+                if let Some(doc) = doc {
+                    return Ok(print::render(&doc, width).lines()
+                        .map(str::to_owned)
+                        .collect());
+                }
+                body.lines()
+                    .map(|s| wrapped::sanitize(s.into(), false).into_owned())
+                    .collect()
+            } else {
+                vec![format!("{} ({} bytes)", response.meta,
+                             response.body.len())]
+            }
+        },
+        Status::Input | Status::SensitiveInput =>
+            vec![format!("Asks for input: {}", response.meta)],
+        Status::RedirectTemporary | Status::RedirectPermanent =>
+            vec![format!("Redirects to {}", response.meta)],
+        s => vec![format!("{}: {}", s, response.meta)],
+    };
+    Ok(out)
+}
+
+/// Draws the preview pane, which is a header row followed by as many lines
+/// as fit in the given number of rows (starting at row `top`).  Errors are
+/// drawn in red.
+pub fn draw(url: &url::Url, body: &Result<Vec<String>, String>, top: u16,
+            rows: u16, width: u16)
+{
+    let width = width as usize;
+    let header = format!(" {} (Enter to open, Esc to close)", url);
+    let header: String = format!("{:<w$}", header, w = width)
+        .chars().take(width).collect();
+
+    let mut out = std::io::stdout();
+    queue!(out,
+        cursor::MoveTo(0, top),
+        PrintStyledContent(style(header).with(Color::Black).on(Color::Grey)),
+    ).expect("Could not queue preview header");
+
+    let (lines, color) = match body {
+        Ok(lines) => (&lines[..], Color::Reset),
+        Err(err) => (std::slice::from_ref(err), Color::DarkRed),
+    };
+    for row in 1..rows {
+        let text: String = lines.get(row as usize - 1)
+            .map(|t| t.chars().take(width.saturating_sub(2)).collect())
+            .unwrap_or_default();
+        queue!(out,
+            cursor::MoveTo(0, top + row),
+            Clear(ClearType::CurrentLine),
+            cursor::MoveTo(2, top + row),
+            PrintStyledContent(style(text).with(color)),
+        ).expect("Could not queue preview line");
+    }
+    out.flush().expect("Could not flush stdout");
+}
+
+#[test]
+fn test_lines() {
+    let opts = ParseOptions::default();
+    let l = |s: &[u8]| lines(s, 20, opts).unwrap();
+    assert_eq!(l(b"20 text/gemini\r\n# Title\nSome text which wraps\n"),
+               vec!["# Title", "Some text which", "wraps"]);
+    assert_eq!(l(b"20 text/markdown\r\n## Sub ##\n* item"),
+               vec!["## Sub", "• item"]);
+    assert_eq!(l(b"20 Text/Plain\r\na\nb"), vec!["a", "b"]);
+    assert_eq!(l(b"20 text/plain\r\na\0b\x1b[2Jc\xff"),
+               vec!["a␀b␛[2Jc\u{FFFD}"]);
+    assert_eq!(l(b"20 image/png\r\n\x89PNG"), vec!["image/png (4 bytes)"]);
+    assert_eq!(l(b"31 gemini://example.com/\r\n"),
+               vec!["Redirects to gemini://example.com/"]);
+    assert_eq!(l(b"10 Name?\r\n"), vec!["Asks for input: Name?"]);
+    assert_eq!(l(b"51 Nothing here\r\n"), vec!["51 Not found: Nothing here"]);
+    assert!(lines(b"nonsense", 20, opts).is_err());
+}
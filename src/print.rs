@@ -0,0 +1,83 @@
+use std::io::Write;
+use std::sync::Arc;
+
+use anyhow::Result;
+use crossterm::{terminal, tty::IsTty};
+
+use silo::cache::Cache;
+use silo::document::Document;
+use silo::fetch;
+use silo::protocol::Status;
+
+use crate::wrapped::{self, WrapOptions};
+
+/// Fetches a single page and prints it to stdout, without entering the TUI.
+/// If `raw` is set, the response body is printed as-is; otherwise, it's
+/// rendered as in the browser (minus colors).
+///
+/// Returns the exit code, which is 0 on success or the first digit of the
+/// response status (e.g. 5 for a permanent failure).  Errors before we got a
+/// response (e.g. a network failure) are reported as 2, since there's no
+/// 2x failure status.
+pub fn run(config: &Arc<rustls::ClientConfig>, cache: &Cache, url: url::Url,
+           raw: bool) -> Result<i32>
+{
+    let doc = match fetch::fetch_cached(config, url, cache) {
+        Ok(doc) => doc,
+        Err(silo::Error::TooManyRedirects) => {
+            eprintln!("Too many redirects");
+            return Ok(3);
+        },
+        Err(err) => {
+            eprintln!("{}", err);
+            return Ok(2);
+        },
+    };
+
+    let status = doc.status();
+    if status != Status::Success {
+        eprintln!("{}: {}", status, doc.meta());
+        return Ok((u32::from(status) / 10) as i32);
+    }
+
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    match doc.doc() {
+        Some(d) if !raw => {
+            let width = if out.is_tty() {
+                terminal::size().map(|s| s.0 as usize).unwrap_or(80)
+            } else {
+                80
+            };
+            out.write_all(render(d, width).as_bytes())?;
+        },
+        // Raw output and non-text content are written as-is
+        _ => out.write_all(doc.body())?,
+    }
+    Ok(0)
+}
+
+/// Renders a document as plain text, wrapped to the given width
+pub fn render(doc: &Document, width: usize) -> String {
+    let mut out = String::new();
+    for w in wrapped::word_wrap(doc, width, WrapOptions::default()).0.iter() {
+        out += &wrapped::prefix(w);
+        out += &wrapped::sanitize(wrapped::text(w), false);
+        out += "\n";
+    }
+    out
+}
+
+#[test]
+fn test_render() {
+    use silo::protocol::Line;
+    let doc = Document::new(vec![
+        Line::H1("Title"),
+        Line::Text("Some text which wraps"),
+        Line::List("item"),
+        Line::NamedLink { url: "/foo", name: "Foo" },
+        Line::Pre { alt: None, text: "a\nb" },
+    ]);
+    assert_eq!(render(&doc, 12), "# Title\nSome text\nwhich wraps\n\
+                                  • item\n→ Foo\na\nb\n");
+}
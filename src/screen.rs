@@ -0,0 +1,91 @@
+use std::io::Write;
+
+use crossterm::{
+    cursor,
+    event,
+    execute,
+    terminal,
+    terminal::ClearType,
+};
+
+/// Guard which puts the terminal into raw mode (with the cursor hidden and
+/// mouse capture enabled, if supported) while a page is on screen,
+/// restoring it on drop.
+pub struct Screen {
+    mouse: bool,
+}
+
+impl Screen {
+    pub fn enter(mouse: bool) -> Screen {
+        let s = Screen { mouse };
+        s.setup();
+        s
+    }
+
+    /// Temporarily restores the terminal while running `f` (e.g. to hand it
+    /// over to an external program).  The caller is responsible for
+    /// redrawing afterwards.
+    pub fn suspend<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        self.restore();
+        execute!(std::io::stdout(), cursor::MoveTo(0, 0))
+            .expect("Could not move cursor");
+        let out = f();
+        self.setup();
+        out
+    }
+
+    fn setup(&self) {
+        terminal::enable_raw_mode()
+            .expect("Could not enable raw mode");
+        execute!(std::io::stdout(), cursor::Hide)
+            .expect("Could not hide cursor");
+        if self.mouse {
+            execute!(std::io::stdout(), event::EnableMouseCapture)
+                .expect("Could not enable mouse capture");
+        }
+    }
+
+    fn restore(&self) {
+        // While unwinding from a panic, the hook has already restored the
+        // terminal and printed the message, which clearing would wipe away;
+        // panicking again here would also abort
+        if std::thread::panicking() {
+            let _ = execute!(std::io::stdout(), cursor::Show);
+            let _ = terminal::disable_raw_mode();
+            return;
+        }
+        if self.mouse {
+            execute!(std::io::stdout(), event::DisableMouseCapture)
+                .expect("Could not disable mouse capture");
+        }
+        execute!(std::io::stdout(),
+            cursor::Show,
+            terminal::Clear(ClearType::All),
+        ).expect("Could not renable cursor");
+        terminal::disable_raw_mode()
+            .expect("Could not disable raw mode");
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+/// Installs a panic hook which puts the terminal back to normal before the
+/// panic is printed.  Unwinding would restore it when the [`Screen`] is
+/// dropped, but only after the message had been printed in raw mode (and
+/// not at all if the panic happened in a destructor).
+pub fn install_panic_hook() {
+    let prev = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // Errors are ignored, since there's nothing more we could do
+        let _ = execute!(std::io::stdout(),
+            event::DisableMouseCapture,
+            cursor::Show,
+        );
+        let _ = terminal::disable_raw_mode();
+        prev(info);
+    }));
+}
@@ -0,0 +1,260 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use silo::fetch;
+
+/// Snapshot files begin with this tag and the URL they were captured from,
+/// followed by the raw response (header and body)
+pub const SNAPSHOT_TAG: &[u8] = b"titan-snapshot ";
+
+/// Where a document was loaded from, which determines how relative links
+/// within it are resolved.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Source {
+    /// Fetched over the network: links resolve against the URL itself
+    Network(url::Url),
+    /// Read from a local file: links resolve against the file's directory
+    File(url::Url),
+    /// Decoded from a `data:` URL: there's no base, so relative links are
+    /// inert and only absolute links can be followed
+    Data,
+    /// Loaded from a saved snapshot: links resolve against the URL from
+    /// which the snapshot was captured
+    Snapshot(url::Url),
+}
+
+impl Source {
+    /// Returns the URL of the document, if it has one
+    pub fn url(&self) -> Option<&url::Url> {
+        match self {
+            Source::Network(url) |
+            Source::File(url) |
+            Source::Snapshot(url) => Some(url),
+            Source::Data => None,
+        }
+    }
+
+    /// Resolves a link target found in a document from this source
+    pub fn resolve(&self, target: &str) -> Result<url::Url> {
+        match self {
+            Source::Network(base) |
+            Source::File(base) |
+            Source::Snapshot(base) => Ok(fetch::resolve(base, target)?),
+            Source::Data => match url::Url::parse(target) {
+                Err(url::ParseError::RelativeUrlWithoutBase) => Err(anyhow!(
+                    "Cannot follow relative link {} from a data: URL",
+                    target)),
+                r => Ok(r?),
+            },
+        }
+    }
+}
+
+/// Parses an address typed by the user, which may leave out the scheme.
+/// Bare hosts (`example.com`), hosts with paths or ports, and scheme-relative
+/// addresses (`//example.com`) are treated as `gemini://` URLs, while
+/// absolute URLs are used as-is.
+pub fn parse_address(s: &str) -> Result<url::Url, url::ParseError> {
+    let s = s.trim();
+    let gemini = |s: &str| url::Url::parse(&format!("gemini://{}", s));
+    if let Some(rest) = s.strip_prefix("//") {
+        return gemini(rest);
+    }
+    match url::Url::parse(s) {
+        Err(url::ParseError::RelativeUrlWithoutBase) => gemini(s),
+        // `host:port` parses as a URL whose scheme is the host
+        Ok(url) if url.cannot_be_a_base() &&
+                   url.path().split('/').next()
+                       .is_some_and(|p| !p.is_empty() &&
+                                        p.bytes().all(|c| c.is_ascii_digit()))
+            => gemini(s),
+        r => r,
+    }
+}
+
+/// Expands a scheme alias (from `(alias, scheme)` pairs)
+pub fn coerce_url(url: url::Url, aliases: &[(String, String)])
+    -> Result<url::Url, url::ParseError>
+{
+    // Changing between some schemes isn't allowed by set_scheme(), so the
+    // URL is parsed again with the new one
+    Ok(match aliases.iter().find(|(a, _)| a == url.scheme()) {
+        Some((alias, scheme)) => url::Url::parse(
+            &format!("{}{}", scheme, &url.as_str()[alias.len()..]))?,
+        None => url,
+    })
+}
+
+/// Coerces an address typed by the user (see [`parse_address`]), which also
+/// gives a gemini URL without a port the default port.  Links and redirects
+/// only go through [`coerce_url`], so that they reach the port they name.
+/// The port is only written out if it's not the usual one, so that most
+/// URLs are left alone.
+pub fn coerce_typed(url: url::Url, aliases: &[(String, String)], port: u16)
+    -> Result<url::Url, url::ParseError>
+{
+    let mut url = coerce_url(url, aliases)?;
+    if url.scheme() == "gemini" && url.port().is_none() && port != 1965 &&
+       url.has_host()
+    {
+        let _ = url.set_port(Some(port));
+    }
+    Ok(url)
+}
+
+/// Rewrites a URL to go through a gateway, given a template in which `{url}`
+/// is replaced by the percent-encoded URL
+pub fn via_gateway(template: &str, url: &url::Url) -> Result<url::Url> {
+    if !template.contains("{url}") {
+        return Err(anyhow!("Gateway {} doesn't contain {{url}}", template));
+    }
+    let encoded = percent_encoding::utf8_percent_encode(
+        url.as_str(), percent_encoding::NON_ALPHANUMERIC).to_string();
+    Ok(url::Url::parse(&template.replace("{url}", &encoded))?)
+}
+
+/// Reads a `file://` URL, returning its source and a synthesized response
+pub fn read_file(url: &url::Url) -> Result<(Source, Vec<u8>)> {
+    let path = url.to_file_path()
+        .map_err(|_| anyhow!("Invalid file URL {}", url))?;
+    let data = std::fs::read(&path)?;
+
+    if data.starts_with(SNAPSHOT_TAG) {
+        let data = &data[SNAPSHOT_TAG.len()..];
+        let end = data.iter().position(|c| *c == b'\n')
+            .ok_or_else(|| anyhow!("Invalid snapshot {}", path.display()))?;
+        let base = std::str::from_utf8(&data[..end])?.trim_end();
+        let base = url::Url::parse(base)?;
+        Ok((Source::Snapshot(base), data[end + 1..].to_vec()))
+    } else {
+        let mut out = format!("20 {}\r\n", file_mime(&path)).into_bytes();
+        out.extend(data);
+        Ok((Source::File(url.clone()), out))
+    }
+}
+
+fn file_mime(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("gmi") | Some("gemini") => "text/gemini",
+        Some("md") | Some("markdown") => "text/markdown",
+        _ => "text/plain",
+    }
+}
+
+/// Decodes a `data:` URL into a synthesized response
+pub fn read_data(url: &url::Url) -> Result<Vec<u8>> {
+    let (mime, data) = url.path().split_once(',')
+        .ok_or_else(|| anyhow!("Invalid data URL {}", url))?;
+    let (mime, base64) = match mime.strip_suffix(";base64") {
+        Some(m) => (m, true),
+        None => (mime, false),
+    };
+    let data: Vec<u8> = percent_encoding::percent_decode_str(data).collect();
+    let data = if base64 { base64::decode(&data)? } else { data };
+
+    let mime = if mime.is_empty() { "text/plain" } else { mime };
+    let mut out = format!("20 {}\r\n", mime).into_bytes();
+    out.extend(data);
+    Ok(out)
+}
+
+#[test]
+fn test_network_relative() {
+    let url = url::Url::parse("gemini://example.com/a/b.gmi?x").unwrap();
+    let source = Source::Network(url);
+    let r = |t| source.resolve(t).unwrap().into_string();
+    assert_eq!(r("?search-term"), "gemini://example.com/a/b.gmi?search-term");
+    assert_eq!(r("//other.com/c"), "gemini://other.com/c");
+    assert_eq!(r("/abs/path"), "gemini://example.com/abs/path");
+}
+
+#[test]
+fn test_file_relative() {
+    let url = url::Url::parse("file:///home/user/docs/index.gmi").unwrap();
+    let source = Source::File(url);
+    assert_eq!(source.resolve("other.gmi").unwrap().as_str(),
+               "file:///home/user/docs/other.gmi");
+    assert_eq!(source.resolve("../up.gmi").unwrap().as_str(),
+               "file:///home/user/up.gmi");
+    assert_eq!(source.resolve("gemini://example.com/").unwrap().as_str(),
+               "gemini://example.com/");
+}
+
+#[test]
+fn test_snapshot_relative() {
+    let path = std::env::temp_dir()
+        .join(format!("titan-snapshot-test-{}", std::process::id()));
+    std::fs::write(&path, "titan-snapshot gemini://example.com/a/b.gmi\n\
+                           20 text/gemini\r\n=> c.gmi link\n").unwrap();
+
+    let url = url::Url::from_file_path(&path).unwrap();
+    let (source, data) = read_file(&url).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(data, b"20 text/gemini\r\n=> c.gmi link\n");
+    assert_eq!(source.resolve("c.gmi").unwrap().as_str(),
+               "gemini://example.com/a/c.gmi");
+}
+
+#[test]
+fn test_data_relative() {
+    let url = url::Url::parse("data:text/gemini,%23%20hi").unwrap();
+    assert_eq!(read_data(&url).unwrap(), b"20 text/gemini\r\n# hi");
+
+    let url = url::Url::parse("data:text/gemini;base64,IyBoaQ==").unwrap();
+    assert_eq!(read_data(&url).unwrap(), b"20 text/gemini\r\n# hi");
+
+    assert!(Source::Data.resolve("foo.gmi").is_err());
+    assert!(Source::Data.resolve("gemini://example.com").is_ok());
+}
+
+#[test]
+fn test_via_gateway() {
+    let url = url::Url::parse("https://example.com/a?b=c").unwrap();
+    let g = via_gateway("gemini://gateway.example/?url={url}", &url).unwrap();
+    assert_eq!(g.as_str(), "gemini://gateway.example/\
+                            ?url=https%3A%2F%2Fexample%2Ecom%2Fa%3Fb%3Dc");
+    assert!(via_gateway("gemini://gateway.example/", &url).is_err());
+}
+
+#[test]
+fn test_coerce_url() {
+    let aliases = vec![("g".to_owned(), "gemini".to_owned())];
+    let c = |s, port| coerce_typed(url::Url::parse(s).unwrap(), &aliases,
+                                   port)
+        .unwrap().into_string();
+    assert_eq!(c("g://example.com/a?b", 1965), "gemini://example.com/a?b");
+    assert_eq!(c("gemini://example.com/", 1965), "gemini://example.com/");
+    assert_eq!(c("https://example.com/", 1965), "https://example.com/");
+
+    // Only gemini URLs without a port get the default port
+    assert_eq!(c("g://localhost/", 1966), "gemini://localhost:1966/");
+    assert_eq!(c("gemini://localhost:1967/", 1966),
+               "gemini://localhost:1967/");
+    assert_eq!(c("https://example.com/", 1966), "https://example.com/");
+
+    // Typed addresses go through the same expansion
+    let u = coerce_typed(parse_address("g://host").unwrap(), &aliases, 1966);
+    assert_eq!(u.unwrap().as_str(), "gemini://host:1966");
+
+    // Links only have their aliases expanded
+    let u = coerce_url(url::Url::parse("g://host/").unwrap(), &aliases);
+    assert_eq!(u.unwrap().as_str(), "gemini://host/");
+}
+
+#[test]
+fn test_parse_address() {
+    let p = |s| parse_address(s).unwrap().into_string();
+    assert_eq!(p("example.com"), "gemini://example.com");
+    assert_eq!(p("example.com/x"), "gemini://example.com/x");
+    assert_eq!(p("gemini://example.com"), "gemini://example.com");
+    assert_eq!(p("//example.com"), "gemini://example.com");
+    assert_eq!(p("example.com:1966/x"), "gemini://example.com:1966/x");
+    assert_eq!(p("localhost:1965"), "gemini://localhost:1965");
+
+    // Other absolute URLs keep their schemes
+    assert_eq!(p("https://example.com/"), "https://example.com/");
+    assert_eq!(p("data:text/gemini,hi"), "data:text/gemini,hi");
+    assert_eq!(p("mailto:123@example.com"), "mailto:123@example.com");
+    assert!(parse_address("gemini://[invalid").is_err());
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use silo::document::Document;
+use silo::protocol::Line;
+
+use crate::source::Source;
+use crate::wrapped::sanitize;
+
+/// A page which is open in a tab.  The raw response is kept (rather than
+/// the parsed document, which borrows from it), so that switching back to
+/// the tab redraws it without fetching it again.
+#[derive(Default)]
+pub struct Tab {
+    /// Most recently displayed URL, to return to after a download
+    pub url: Option<url::Url>,
+    /// Where the page came from, and its raw response
+    pub page: Option<(Source, Rc<Vec<u8>>)>,
+    /// Scroll and cursor positions, restored when switching back
+    pub pos: (usize, usize),
+    /// Label shown in the tab bar
+    pub title: String,
+    /// Fragment of a freshly loaded URL, to jump to when first shown
+    pub fragment: Option<String>,
+    /// If the raw source is being shown instead of the page, the page's
+    /// scroll and cursor positions, to restore when switching back
+    pub raw: Option<(usize, usize)>,
+}
+
+/// Last scroll and cursor positions of each page shown during the session,
+/// so that returning to a page picks up where it was left.  Fragments are
+/// ignored, since they select part of the same page.
+#[derive(Default)]
+pub struct Positions(HashMap<String, (usize, usize)>);
+
+impl Positions {
+    /// Returns the position saved for a URL, or the top of the page
+    pub fn get(&self, url: &url::Url) -> (usize, usize) {
+        self.0.get(&key(url)).copied().unwrap_or_default()
+    }
+
+    pub fn set(&mut self, url: &url::Url, pos: (usize, usize)) {
+        self.0.insert(key(url), pos);
+    }
+}
+
+fn key(url: &url::Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    url.into_string()
+}
+
+/// Returns a tab's title, which is the page's first H1 or its URL.  The
+/// title is drawn in the tab bar and sent to the terminal, so control
+/// characters are sanitized and tabs become spaces.
+pub fn title(doc: &Document, url: Option<&url::Url>, ascii: bool) -> String {
+    doc.0.iter()
+        .find_map(|line| match line {
+            Line::H1(t) => Some(sanitize(t.trim().into(), ascii)
+                .replace('\t', " ")),
+            _ => None,
+        })
+        .or_else(|| url.map(|u| u.to_string()))
+        .unwrap_or_default()
+}
+
+/// Finds the line of a document which a URL fragment refers to, either as
+/// `line-N` (counting lines of the parsed document from 1) or as the text of
+/// a heading, ignoring case.
+pub fn anchor(doc: &Document, fragment: &str) -> Option<usize> {
+    let fragment = percent_encoding::percent_decode_str(fragment)
+        .decode_utf8_lossy();
+    if let Some(n) = fragment.strip_prefix("line-") {
+        return n.parse::<usize>().ok()
+            .filter(|n| (1..=doc.0.len()).contains(n))
+            .map(|n| n - 1);
+    }
+    doc.0.iter().position(|line| match line {
+        Line::H1(t) | Line::H2(t) | Line::H3(t) =>
+            t.trim().eq_ignore_ascii_case(fragment.trim()),
+        _ => false,
+    })
+}
+
+/// Builds the tab bar, e.g. ` 1:Home  2:Search `, where the active tab is
+/// wrapped in brackets.  Titles are shortened so that every tab fits in the
+/// given width, ending with an ellipsis (which is `...` if `ascii` is set).
+pub fn bar(titles: &[&str], active: usize, width: usize, ascii: bool)
+    -> String
+{
+    let n = titles.len().max(1);
+    // Each tab has a number, a colon, and a bracket or space on either side
+    let room = (width / n).saturating_sub(5).max(1);
+    let mut out = String::new();
+    for (i, t) in titles.iter().enumerate() {
+        let t: String = if t.chars().count() > room {
+            let ellipsis = if ascii { "..." } else { "…" };
+            let n = ellipsis.chars().count();
+            t.chars().take(room.saturating_sub(n))
+                .chain(ellipsis.chars())
+                .take(room)
+                .collect()
+        } else {
+            t.to_string()
+        };
+        let (l, r) = if i == active { ('[', ']') } else { (' ', ' ') };
+        out += &format!("{}{}:{}{}", l, i + 1, t, r);
+    }
+    out.chars().take(width).collect()
+}
+
+#[test]
+fn test_title() {
+    let url = url::Url::parse("gemini://example.com/a.gmi").unwrap();
+    let doc = Document::new(vec![
+        Line::Text("intro"),
+        Line::H1(" Welcome "),
+        Line::H1("Other"),
+    ]);
+    assert_eq!(title(&doc, Some(&url), false), "Welcome");
+
+    let doc = Document::new(vec![Line::H2("Not a title")]);
+    assert_eq!(title(&doc, Some(&url), false), "gemini://example.com/a.gmi");
+    assert_eq!(title(&doc, None, false), "");
+
+    // Escape sequences can't reach the terminal's title
+    let doc = Document::new(vec![Line::H1("Evil\x1b]0;pwned\x07\ttitle")]);
+    assert_eq!(title(&doc, None, false), "Evil\u{241b}]0;pwned\u{2407} title");
+    assert_eq!(title(&doc, None, true), "Evil?]0;pwned? title");
+}
+
+#[test]
+fn test_anchor() {
+    let doc = Document::new(vec![
+        Line::H1("Title"),
+        Line::Text("text"),
+        Line::H2("Getting Started"),
+        Line::Text("more"),
+    ]);
+    assert_eq!(anchor(&doc, "line-2"), Some(1));
+    assert_eq!(anchor(&doc, "line-4"), Some(3));
+    assert_eq!(anchor(&doc, "getting%20started"), Some(2));
+    assert_eq!(anchor(&doc, "title"), Some(0));
+
+    assert_eq!(anchor(&doc, "line-0"), None);
+    assert_eq!(anchor(&doc, "line-5"), None);
+    assert_eq!(anchor(&doc, "line-x"), None);
+    assert_eq!(anchor(&doc, "text"), None);
+}
+
+#[test]
+fn test_bar() {
+    assert_eq!(bar(&["Home", "Search"], 1, 80, false), " 1:Home [2:Search]");
+    assert_eq!(bar(&["A long title", "B"], 0, 20, false), "[1:A lo…] 2:B ");
+    assert_eq!(bar(&["A long title", "B"], 0, 20, true), "[1:A ...] 2:B ");
+}
+
+#[test]
+fn test_positions() {
+    let url = |s| url::Url::parse(s).unwrap();
+    let mut p = Positions::default();
+    assert_eq!(p.get(&url("gemini://example.com/a")), (0, 0));
+
+    p.set(&url("gemini://example.com/a#intro"), (10, 12));
+    assert_eq!(p.get(&url("gemini://example.com/a")), (10, 12));
+    assert_eq!(p.get(&url("gemini://example.com/a#other")), (10, 12));
+    assert_eq!(p.get(&url("gemini://example.com/b")), (0, 0));
+}
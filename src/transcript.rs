@@ -0,0 +1,155 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use silo::fetch::TlsInfo;
+use silo::protocol::Status;
+
+/// Record of every request made during a session.  Each entry is a
+/// tab-separated line of timestamp, URL, status, meta, elapsed time, body
+/// size, and TLS version, kept in memory and optionally appended to a
+/// transcript file.  Requests which fail without a response are recorded
+/// with `error` as their status and the error as their meta, and responses
+/// which were prefetched in the background have `prefetched` in place of
+/// the elapsed time and TLS version.
+#[derive(Default)]
+pub struct Transcript {
+    file: Option<File>,
+    entries: Vec<String>,
+}
+
+/// What came of a request
+pub enum Outcome<'a> {
+    /// A response, with the size of its body and the TLS session it came
+    /// over (which is None if it wasn't read from the network), and whether
+    /// it was prefetched rather than read for this request
+    Response { status: Status, meta: &'a str, bytes: usize,
+               tls: Option<TlsInfo>, prefetched: bool },
+    /// A request which failed without a response
+    Failed(&'a str),
+}
+
+impl Transcript {
+    /// Builds a transcript which also appends to the given file
+    pub fn to_file(path: &Path) -> Result<Transcript> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Transcript { file: Some(file), entries: Vec::new() })
+    }
+
+    /// Records a request.  If `redact` is set, the URL's query (which
+    /// holds the user's answer to a sensitive input prompt) is replaced.
+    ///
+    /// If the file can't be written (e.g. because the disk is full), the
+    /// error is returned and the file is closed, but the entry is still
+    /// kept in memory and later requests are recorded there.
+    pub fn record(&mut self, url: &url::Url, outcome: Outcome,
+                  elapsed: Duration, redact: bool) -> Result<()>
+    {
+        let mut url = url.clone();
+        if redact && url.query().is_some() {
+            url.set_query(Some("[redacted]"));
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let (status, meta, bytes, tls, prefetched) = match outcome {
+            Outcome::Response { status, meta, bytes, tls, prefetched } =>
+                (u32::from(status).to_string(), meta, bytes,
+                 tls.map(|t| format!("{:?}", t.version)), prefetched),
+            Outcome::Failed(err) => ("error".to_owned(), err, 0, None, false),
+        };
+        // Tabs would split fields and newlines would split entries, while
+        // other control characters could reach a terminal showing the file
+        let meta: String = meta.chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect();
+        let (elapsed, tls) = if prefetched {
+            ("prefetched".to_owned(), "prefetched".to_owned())
+        } else {
+            (format!("{}ms", elapsed.as_millis()),
+             tls.unwrap_or_else(|| "-".to_owned()))
+        };
+        let entry = format!("{}\t{}\t{}\t{}\t{}\t{}\t{}", now, url, status,
+                            meta, elapsed, bytes, tls);
+        let written = match self.file.as_mut() {
+            Some(f) => writeln!(f, "{}", entry),
+            None => Ok(()),
+        };
+        if written.is_err() {
+            self.file = None;
+        }
+        self.entries.push(entry);
+        Ok(written?)
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+}
+
+#[test]
+fn test_record() {
+    let path = std::env::temp_dir()
+        .join(format!("titan-transcript-test-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut t = Transcript::to_file(&path).unwrap();
+
+    let url = url::Url::parse("gemini://example.com/login?hunter2").unwrap();
+    let tls = TlsInfo {
+        version: rustls::ProtocolVersion::TLSv1_3,
+        suite: rustls::CipherSuite::TLS13_AES_128_GCM_SHA256,
+    };
+    t.record(&url, Outcome::Response { status: Status::Success,
+                                       meta: "text/gemini", bytes: 120,
+                                       tls: Some(tls), prefetched: false },
+             Duration::from_millis(12), true).unwrap();
+    let url = url::Url::parse("gemini://example.com/search?cats").unwrap();
+    t.record(&url, Outcome::Response { status: Status::NotFound,
+                                       meta: "Not found", bytes: 0,
+                                       tls: None, prefetched: false },
+             Duration::from_millis(3), false).unwrap();
+    t.record(&url, Outcome::Failed("Connection refused\n(os error 111)"),
+             Duration::from_millis(1), false).unwrap();
+    t.record(&url, Outcome::Response { status: Status::Success,
+                                       meta: "text/\x1b[2Jplain\x07",
+                                       bytes: 4, tls: None,
+                                       prefetched: true },
+             Duration::from_millis(0), false).unwrap();
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines, t.entries());
+    assert!(!text.contains("hunter2"));
+
+    let fields: Vec<&str> = lines[0].split('\t').collect();
+    assert_eq!(&fields[1..], &["gemini://example.com/login?[redacted]",
+                               "20", "text/gemini", "12ms", "120", "TLSv1_3"]);
+    let fields: Vec<&str> = lines[1].split('\t').collect();
+    assert_eq!(&fields[1..], &["gemini://example.com/search?cats",
+                               "51", "Not found", "3ms", "0", "-"]);
+    let fields: Vec<&str> = lines[2].split('\t').collect();
+    assert_eq!(&fields[2..], &["error", "Connection refused (os error 111)",
+                               "1ms", "0", "-"]);
+    let fields: Vec<&str> = lines[3].split('\t').collect();
+    assert_eq!(&fields[2..], &["20", "text/ [2Jplain ", "prefetched", "4",
+                               "prefetched"]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_record_write_failure() {
+    // Every write to /dev/full fails, as if the disk were full
+    let mut t = Transcript::to_file(Path::new("/dev/full")).unwrap();
+    let url = url::Url::parse("gemini://example.com/").unwrap();
+    let outcome = || Outcome::Failed("timed out");
+    let d = Duration::from_millis(1);
+    assert!(t.record(&url, outcome(), d, false).is_err());
+
+    // Recording carries on in memory, without reporting the error again
+    t.record(&url, outcome(), d, false).unwrap();
+    assert_eq!(t.entries().len(), 2);
+}
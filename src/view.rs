@@ -1,25 +1,41 @@
+use std::collections::HashSet;
 use std::convert::TryInto;
+use std::ops::Range;
 use std::io::{Write};
 
 use silo::document::Document;
+use silo::parser::find_urls;
+use silo::render;
 use silo::protocol::Line;
+use silo::theme::Theme;
 
-use crate::wrapped::WrappedDocument;
+use crate::wrapped::{self, WrapOptions, WrappedDocument, WrappedLine};
 use crate::command::Command;
+use crate::config::{Config, LineNumbers};
 
 use anyhow::Result;
 
 use crossterm::{
     cursor,
-    event,
-    execute,
-    terminal,
-    event::{Event, KeyCode, KeyEvent, MouseEvent},
+    event::{Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent},
     terminal::{Clear, ClearType},
     style::{style, Color, ContentStyle, Print, PrintStyledContent},
     queue,
 };
 
+/// Narrowest column which `[` will shrink the text to
+const MIN_WRAP_WIDTH: u16 = 20;
+
+/// Columns by which `[` and `]` change the width of the text
+const WRAP_STEP: u16 = 4;
+
+/// Labels drawn beside the links which are on screen, while waiting for
+/// one of them to be typed
+struct Hints {
+    labels: Vec<(usize, String)>, // Wrapped line index and its label
+    typed: String,
+}
+
 pub struct View<'a> {
     source: &'a Document<'a>,
     doc: WrappedDocument<'a>,
@@ -28,119 +44,354 @@ pub struct View<'a> {
 
     yscroll: usize, // Y scoll position in the doc
     ycursor: usize, // Y cursor position in the doc
-}
 
-impl Drop for View<'_> {
-    fn drop(&mut self) {
-        execute!(std::io::stdout(),
-            cursor::Show,
-            event::DisableMouseCapture,
-            terminal::Clear(ClearType::All),
-        ).expect("Could not renable cursor");
-        terminal::disable_raw_mode()
-            .expect("Could not disable raw mode");
-    }
-}
+    wrap_cursor: bool, // Wrap from the bottom to the top and vice versa
+    wrap_options: WrapOptions,
+    theme: Theme,
+    decode_urls: bool, // Draw bare links' URLs percent-decoded
+    visited: HashSet<&'a str>, // Targets of links which were followed
+    scrollbar: bool, // Draw a scroll bar at the right edge
+    dim: bool, // Draw everything in a single muted color
+    ascii: bool, // Draw decoration without non-ASCII glyphs
+    pending_y: bool, // Was the last key 'y', starting a 'yy'?
+    hints: Option<Hints>, // Link labels, if we're in hint mode
 
-impl View<'_> {
-    pub fn new<'a>(source: &'a Document) -> View<'a> {
-        let size = terminal::size()
-            .expect("Could not get terminal size");
+    line_numbers: LineNumbers,
+    gutter: u16, // Width of the line number gutter, including its padding
 
+    wrap_width: u16, // Widest column of text, or 0 for no limit
+    wrap_centered: bool, // Center the column, rather than aligning it left
+    reader: bool, // Center the text in a column of at most reader_width
+    reader_width: u16,
+    margin: u16, // Extra columns to the left of the text, for centering
+    term: (u16, u16), // Terminal size, from which everything else is sized
+}
+
+impl<'a> View<'a> {
+    /// Builds a view for a terminal of the given size.  This doesn't touch
+    /// the terminal; call draw() to display it.
+    pub fn new(source: &'a Document, size: (u16, u16), options: &Config)
+        -> View<'a>
+    {
         let doc = crate::wrapped::dummy_wrap(source);
 
         let mut v = View { doc, source,
             ycursor: 0,
             yscroll: 0,
             size: (0, 0),
+            wrap_cursor: options.cursor_wrap,
+            wrap_options: WrapOptions {
+                truncate_pre: options.truncate_pre,
+                underline_headings: options.underline_headings,
+                collapse_blank_lines: options.collapse_blank_lines,
+            },
+            theme: options.theme.clone(),
+            decode_urls: options.decode_urls,
+            visited: HashSet::new(),
+            scrollbar: options.scrollbar,
+            dim: false,
+            ascii: options.ascii,
+            pending_y: false,
+            hints: None,
+            line_numbers: options.line_numbers,
+            gutter: 0,
+            wrap_width: options.wrap_width,
+            wrap_centered: options.wrap_centered,
+            reader: options.reader_mode,
+            reader_width: options.reader_width.max(1),
+            margin: 0,
+            term: size,
         };
-        terminal::enable_raw_mode()
-            .expect("Could not enable raw mode");
-        execute!(std::io::stdout(), cursor::Hide, event::EnableMouseCapture)
-            .expect("Could not hide cursor");
-        v.resize(size);
-        v.draw();
+        v.rewrap(size);
         v
     }
 
-    fn resize(&mut self, size: (u16, u16)) {
+    /// Returns the scroll and cursor positions
+    pub fn position(&self) -> (usize, usize) {
+        (self.yscroll, self.ycursor)
+    }
+
+    /// Restores scroll and cursor positions from position(), clamping them
+    /// in case the document was wrapped differently since
+    pub fn set_position(&mut self, (yscroll, ycursor): (usize, usize)) {
+        self.ycursor = ycursor.min(self.doc.0.len().saturating_sub(1));
+        self.yscroll = yscroll.min(self.ycursor)
+            .max((self.ycursor + 1).saturating_sub(self.size.1 as usize));
+    }
+
+    /// Moves the cursor to the first wrapped line of the given document line,
+    /// scrolling so that it's at the top of the screen (if possible)
+    pub fn goto_line(&mut self, line: usize) {
+        let i = self.doc.0.iter()
+            .position(|w| w.block >= line)
+            .unwrap_or(0);
+        let max_scroll = self.doc.0.len().saturating_sub(self.size.1 as usize);
+        self.set_position((i.min(max_scroll), i));
+    }
+
+    /// Moves the cursor to the first line containing the given text
+    /// (ignoring case), scrolling it to the top of the screen if possible.
+    /// Returns false if there's no such line.
+    pub fn find(&mut self, term: &str) -> bool {
+        let term = term.to_lowercase();
+        let i = self.doc.0.iter()
+            .position(|w| wrapped::text(w).to_lowercase().contains(&term));
+        if let Some(i) = i {
+            let max_scroll =
+                self.doc.0.len().saturating_sub(self.size.1 as usize);
+            self.set_position((i.min(max_scroll), i));
+        }
+        i.is_some()
+    }
+
+    /// Marks links with the given targets as visited
+    pub fn set_visited(&mut self, visited: HashSet<&'a str>) {
+        self.visited = visited;
+    }
+
+    fn line_style(&self, line: &Line) -> ContentStyle {
+        match line {
+            _ if self.dim => ContentStyle::new().foreground(Color::DarkGrey),
+            Line::NamedLink { url, .. } | Line::BareLink(url)
+                if self.visited.contains(url) => self.theme.visited.clone(),
+            _ => self.theme.style(line),
+        }
+    }
+
+    /// Dims or restores the page, redrawing it
+    pub fn set_dim(&mut self, dim: bool) {
+        self.dim = dim;
+        self.draw();
+    }
+
+    /// Changes which line numbers are shown, rewrapping and redrawing
+    pub fn set_line_numbers(&mut self, n: LineNumbers) {
+        self.line_numbers = n;
+        self.rewrap(self.term);
+        self.draw();
+    }
+
+    /// Enters or leaves reader mode, rewrapping and redrawing
+    pub fn set_reader(&mut self, reader: bool) {
+        self.reader = reader;
+        self.rewrap(self.term);
+        self.draw();
+    }
+
+    /// Changes the widest column of text (0 for no limit), rewrapping and
+    /// redrawing
+    pub fn set_wrap_width(&mut self, width: u16) {
+        self.wrap_width = width;
+        self.rewrap(self.term);
+        self.draw();
+    }
+
+    /// Returns the wrap width which is one step wider or narrower than the
+    /// current column, between MIN_WRAP_WIDTH and the terminal's width.
+    /// Widening the column to fill the terminal removes the limit (0).
+    fn step_wrap_width(&self, wider: bool) -> u16 {
+        let full = self.term.0.saturating_sub(4 + self.gutter);
+        let w = if wider {
+            self.size.0.saturating_add(WRAP_STEP)
+        } else {
+            self.size.0.saturating_sub(WRAP_STEP).max(MIN_WRAP_WIDTH)
+        };
+        if w >= full { 0 } else { w }
+    }
+
+    /// Returns the number drawn in the gutter beside a wrapped line, if any
+    fn line_number(&self, i: usize) -> Option<usize> {
+        match self.line_numbers {
+            LineNumbers::Off => None,
+            LineNumbers::Wrapped => Some(i + 1),
+            LineNumbers::Source => Some(self.doc.0[i].block + 1)
+                .filter(|_| self.doc.0[i].first && !self.source.0.is_empty()),
+        }
+    }
+
+    /// Returns the rows covered by the scroll bar's thumb, or None if
+    /// there's no scroll bar
+    fn thumb(&self) -> Option<Range<usize>> {
+        if !self.scrollbar {
+            return None;
+        }
+        thumb(self.doc.0.len(), self.size.1 as usize, self.yscroll)
+    }
+
+    /// Rewraps the view for a terminal of the given size, and redraws it
+    pub fn resize(&mut self, size: (u16, u16)) {
+        self.rewrap(size);
+        self.draw()
+    }
+
+    fn rewrap(&mut self, size: (u16, u16)) {
+        // Hints refer to wrapped lines, so they're dropped
+        self.hints = None;
+
         // Attempt to maintain roughly the same scroll and cursor position
         // after resizing is complete
-        let yscroll_frac = self.yscroll as f32 / self.doc.0.len() as f32;
-        let ycursor_frac = self.ycursor as f32 / self.doc.0.len() as f32;
+        let len = self.doc.0.len().max(1) as f32;
+        let yscroll_frac = self.yscroll as f32 / len;
+        let ycursor_frac = self.ycursor as f32 / len;
 
-        self.doc = crate::wrapped::word_wrap(self.source, (size.0 - 4).into());
+        // The gutter holds the widest line number and a space.  Wrapped
+        // line numbers depend on the wrapping, which depends on the gutter,
+        // so we guess from the source's length and rewrap if it was wrong.
+        let digits = |n: usize| n.to_string().len() as u16 + 1;
+        self.gutter = match self.line_numbers {
+            LineNumbers::Off => 0,
+            _ => digits(self.source.0.len()),
+        };
+        // The wrap width narrows the text to a column, which reader mode
+        // narrows further (and always centers)
+        let mut column = match self.wrap_width {
+            0 => u16::MAX,
+            w => w,
+        };
+        if self.reader {
+            column = column.min(self.reader_width);
+        }
+        // Tiny terminals still get a column of text, even if it's offscreen
+        let width = |gutter: u16| size.0.saturating_sub(4 + gutter)
+            .min(column)
+            .max(1);
+        loop {
+            self.doc = wrapped::word_wrap(self.source,
+                                          width(self.gutter).into(),
+                                          self.wrap_options);
+            if self.line_numbers != LineNumbers::Wrapped ||
+               digits(self.doc.0.len()) <= self.gutter
+            {
+                break;
+            }
+            self.gutter = digits(self.doc.0.len());
+        }
 
-        // Add two characters of padding on either side, and a status
-        // and command bar at the bottom
-        // Add a status and command bar at the bottom
-        self.size = (size.0 - 4, size.1 - 2);
+        // Add two characters of padding on either side (plus the gutter and
+        // any margin), and a status and command bar at the bottom
+        self.term = size;
+        self.size = (width(self.gutter), size.1.saturating_sub(2));
+        self.margin = if self.reader || self.wrap_centered {
+            size.0.saturating_sub(4 + self.gutter + self.size.0) / 2
+        } else {
+            0
+        };
 
         let dl = self.doc.0.len();
         self.ycursor = ((ycursor_frac * dl as f32) as usize)
-            .max(0)
-            .min(dl)
+            .min(dl.saturating_sub(1))
             .min((self.yscroll + self.size.1 as usize).saturating_sub(1));
-        self.yscroll = ((yscroll_frac * dl as f32) as usize).max(0)
+        self.yscroll = ((yscroll_frac * dl as f32) as usize)
             .min(dl);
-
-        self.draw()
     }
 
     fn draw_line<W: Write>(&self, out: &mut W, i: usize) {
         // We trust that the line-wrapping has wrapped things like quotes and
         // links so that there's room for their prefixes here.
 
-        use Line::*;
-        let c = ContentStyle::new();
-        let (line, first) = self.doc.0[i];
-
-        // Prefix selector function
-        let p = |a, b| if first { a } else { b };
-
-        let (text, prefix, c) = match line {
-            Text(t) => (t, "", c),
-            H1(t) => (t, p("# ", "  "), c.foreground(Color::DarkRed)),
-            H2(t) => (t, p("## ", "   "), c.foreground(Color::DarkYellow)),
-            H3(t) => (t, p("### ", "    "), c.foreground(Color::DarkCyan)),
-            List(t) => (t, p("• ", "  "), c),
-            Quote(t) => (t, "> ", c.foreground(Color::White)),
-            NamedLink { name, .. } => (name, p("→ ", "  "),
-                                       c.foreground(Color::Magenta)),
-
-            // TODO: handle overly long Pre and BareLink lines
-            BareLink(url) => (url, "→ ", c.foreground(Color::Magenta)),
-            Pre { text, .. } => (text, "", c.foreground(Color::Red)),
+        let w = self.doc.0[i];
+        let WrappedLine { line, truncated, .. } = w;
+
+        // Only the displayed text of a bare link is decoded; its target is
+        // still the URL as written
+        let text = match line {
+            Line::BareLink(url) if self.decode_urls => wrapped::decode_url(url),
+            _ => wrapped::text(&w),
+        };
+        let text = wrapped::sanitize(text, self.ascii);
+        let prefix = if self.ascii {
+            wrapped::ascii_prefix(&w)
+        } else {
+            wrapped::prefix(&w)
+        };
+        // Bare links aren't wrapped, so long ones are shortened to fit.
+        // The line keeps the whole URL, which is what gets followed.
+        let text = match line {
+            Line::BareLink(_) => {
+                let width = (self.size.0 as usize)
+                    .saturating_sub(prefix.chars().count());
+                wrapped::shorten(text, width, self.ascii)
+            },
+            _ => text,
+        };
+        let c = self.line_style(&line);
+
+        let marker = match (truncated, self.ascii) {
+            (false, _) => "",
+            (true, false) => "›",
+            (true, true) => ">",
         };
 
         let sy = (i - self.yscroll).try_into().unwrap();
         assert!(sy < self.size.1);
 
+        // Text starts after the margin, padding, and gutter
+        let x = self.margin + 2 + self.gutter;
         if i == self.ycursor {
             let c = c.background(Color::Black);
-            let fill = " ".repeat((self.size.0 + 1).into());
+            let fill = " ".repeat((self.size.0 + self.gutter + 1).into());
             queue!(out,
-                cursor::MoveTo(0, sy),
+                cursor::MoveTo(self.margin, sy),
                 PrintStyledContent(style(fill).on(Color::Black)),
-                cursor::MoveTo(2, sy),
+                cursor::MoveTo(x, sy),
                 PrintStyledContent(style(prefix).on(Color::Black)),
-                PrintStyledContent(c.apply(text)),
+                PrintStyledContent(c.clone().apply(text)),
+                PrintStyledContent(c.apply(marker)),
             )
         } else {
             queue!(out,
-                cursor::MoveTo(2, sy),
+                cursor::MoveTo(x, sy),
                 Print(prefix),
-                PrintStyledContent(c.apply(text)),
+                PrintStyledContent(c.clone().apply(text)),
+                PrintStyledContent(c.apply(marker)),
             )
         }.expect("Could not queue line");
+
+        if let Some(n) = self.line_number(i) {
+            let n = format!("{:>w$}", n, w = self.gutter as usize - 1);
+            let n = style(n).with(Color::DarkGrey);
+            let n = if i == self.ycursor { n.on(Color::Black) } else { n };
+            queue!(out,
+                cursor::MoveTo(self.margin + 2, sy),
+                PrintStyledContent(n),
+            ).expect("Could not queue line number");
+        }
+
+        // The scroll bar is drawn a row at a time, so that repainting a
+        // single line doesn't erase its part of the bar
+        if let Some(thumb) = self.thumb() {
+            let c = match (thumb.contains(&(sy as usize)), self.ascii) {
+                (true, false) => "█",
+                (false, false) => "░",
+                (true, true) => "#",
+                (false, true) => "|",
+            };
+            queue!(out,
+                cursor::MoveTo(self.term.0 - 1, sy),
+                PrintStyledContent(style(c).with(Color::DarkGrey)),
+            ).expect("Could not queue scroll bar");
+        }
+
+        // Labels which still match what's been typed are drawn in the
+        // padding to the left of their link
+        let label = self.hints.as_ref()
+            .and_then(|h| h.labels.iter()
+                .find(|(j, t)| *j == i && t.starts_with(&h.typed)));
+        if let Some((_, label)) = label {
+            queue!(out,
+                cursor::MoveTo(self.margin, sy),
+                PrintStyledContent(style(label).with(Color::Black)
+                                               .on(Color::Yellow)),
+            ).expect("Could not queue hint");
+        }
     }
 
-    fn draw(&self) {
+    pub fn draw(&self) {
         let stdout = std::io::stdout();
         let mut out = stdout.lock();
 
         queue!(out,
-            cursor::MoveTo(self.size.0 + 4, self.size.1 - 1),
+            cursor::MoveTo(self.term.0, self.size.1.saturating_sub(1)),
             Clear(ClearType::FromCursorUp),
         ).expect("Could not queue clear");
 
@@ -151,12 +402,22 @@ impl View<'_> {
             self.draw_line(&mut out, i);
         }
 
+        // An empty document is wrapped to a single blank line, on which we
+        // draw a placeholder
+        if self.source.0.is_empty() {
+            queue!(out,
+                cursor::MoveTo(self.margin + 2 + self.gutter, 0),
+                PrintStyledContent(style("(empty)").with(Color::DarkGrey)
+                                                   .on(Color::Black)),
+            ).expect("Could not queue placeholder");
+        }
+
         out.flush().expect("Could not flush stdout");
     }
 
     // Safely increments a line index
     fn increment_index(&self, index: usize) -> usize {
-        (index + 1).min(self.doc.0.len() - 1)
+        (index + 1).min(self.doc.0.len().saturating_sub(1))
     }
 
     // Selectively repaints based on whether scroll or cursor position has
@@ -186,6 +447,16 @@ impl View<'_> {
     fn down(&mut self) {
         let prev_cursor = self.ycursor;
         let prev_scroll = self.yscroll;
+        self.cursor_down();
+        self.repaint(prev_cursor, prev_scroll);
+    }
+
+    fn cursor_down(&mut self) {
+        if self.wrap_cursor && self.ycursor + 1 >= self.doc.0.len() {
+            self.ycursor = 0;
+            self.yscroll = 0;
+            return;
+        }
         self.ycursor = self.increment_index(self.ycursor);
 
         // If we've scrolled off the bottom of the screen, then adjust the
@@ -193,7 +464,6 @@ impl View<'_> {
         if self.ycursor >= self.yscroll + self.size.1 as usize {
             self.yscroll = self.increment_index(self.yscroll);
         }
-        self.repaint(prev_cursor, prev_scroll);
     }
 
     fn decrement_index(&self, index: usize) -> usize {
@@ -203,24 +473,217 @@ impl View<'_> {
     fn up(&mut self) {
         let prev_cursor = self.ycursor;
         let prev_scroll = self.yscroll;
+        self.cursor_up();
+        self.repaint(prev_cursor, prev_scroll)
+    }
+
+    fn cursor_up(&mut self) {
+        if self.wrap_cursor && self.ycursor == 0 {
+            self.ycursor = self.doc.0.len().saturating_sub(1);
+            self.yscroll = self.doc.0.len()
+                .saturating_sub(self.size.1 as usize);
+            return;
+        }
         self.ycursor = self.decrement_index(self.ycursor);
         if self.ycursor < self.yscroll {
             self.yscroll = self.decrement_index(self.yscroll);
         }
-        self.repaint(prev_cursor, prev_scroll)
+    }
+
+    /// Moves the cursor and scroll position by a screenful
+    fn page_down(&mut self) {
+        let prev = self.position();
+        let h = self.size.1 as usize;
+        let max_scroll = self.doc.0.len().saturating_sub(h);
+        self.set_position(((self.yscroll + h).min(max_scroll),
+                           self.ycursor + h));
+        self.repaint(prev.1, prev.0);
+    }
+
+    fn page_up(&mut self) {
+        let prev = self.position();
+        let h = self.size.1 as usize;
+        self.set_position((self.yscroll.saturating_sub(h),
+                           self.ycursor.saturating_sub(h)));
+        self.repaint(prev.1, prev.0);
+    }
+
+    /// Moves the cursor to the first or last line of the document
+    fn jump(&mut self, end: bool) {
+        let prev = self.position();
+        if end {
+            let len = self.doc.0.len();
+            self.set_position((len.saturating_sub(self.size.1 as usize),
+                               len));
+        } else {
+            self.set_position((0, 0));
+        }
+        self.repaint(prev.1, prev.0);
+    }
+
+    /// Moves the cursor to the start of the next (or previous) link,
+    /// wrapping around at the ends of the document and scrolling to keep it
+    /// on screen.  Does nothing if there are no links.
+    fn next_link(&mut self, forward: bool) {
+        let n = self.doc.0.len();
+        let i = (1..=n)
+            .map(|d| if forward { (self.ycursor + d) % n }
+                     else { (self.ycursor + n - d) % n })
+            .find(|&i| {
+                let w = &self.doc.0[i];
+                w.first && matches!(w.line, Line::NamedLink { .. } |
+                                            Line::BareLink(..))
+            });
+        if let Some(i) = i {
+            let prev = self.position();
+            self.set_position((self.yscroll, i));
+            self.repaint(prev.1, prev.0);
+        }
+    }
+
+    /// Moves the cursor to the given screen row, returning false if there's
+    /// no document line there (e.g. the status and command rows)
+    fn cursor_to_row(&mut self, row: u16) -> bool {
+        let i = self.yscroll + row as usize;
+        if row >= self.size.1 || i >= self.doc.0.len() {
+            return false;
+        }
+        self.ycursor = i;
+        true
+    }
+
+    /// Moves the cursor to a clicked row, following the link there (if any)
+    fn click(&mut self, row: u16) -> Option<Result<Command>> {
+        let prev_cursor = self.ycursor;
+        if !self.cursor_to_row(row) {
+            return None;
+        }
+        self.repaint(prev_cursor, self.yscroll);
+        self.cursor_link()
+            .map(|url| Ok(Command::TryLoad(url.to_string())))
+    }
+
+    /// Returns the target of the link under the cursor, if there is one
+    pub fn cursor_link(&self) -> Option<&str> {
+        match self.doc.0.get(self.ycursor)?.line {
+            Line::NamedLink { url, .. } |
+            Line::BareLink(url) => Some(url),
+            _ => None
+        }
+    }
+
+    /// Returns the document line (e.g. the whole preformatted block) which
+    /// contains the wrapped line under the cursor
+    fn cursor_block(&self) -> Option<&Line<'_>> {
+        self.source.0.get(self.doc.0.get(self.ycursor)?.block)
+    }
+
+    /// Checks whether we're in hint mode, where every key goes to the view
+    pub fn hinting(&self) -> bool {
+        self.hints.is_some()
+    }
+
+    /// Enters hint mode, labelling each link which is on screen
+    fn start_hints(&mut self) {
+        let end = (self.yscroll + self.size.1 as usize).min(self.doc.0.len());
+        let links: Vec<usize> = (self.yscroll..end)
+            .filter(|i| self.doc.0[*i].first)
+            .filter(|i| matches!(self.doc.0[*i].line,
+                                 Line::NamedLink { .. } | Line::BareLink(_)))
+            .collect();
+        if links.is_empty() {
+            return;
+        }
+        let labels = hint_labels(links.len());
+        self.hints = Some(Hints {
+            labels: links.into_iter().zip(labels).collect(),
+            typed: String::new(),
+        });
+        self.draw();
+    }
+
+    /// Handles a key in hint mode, following a link once its whole label
+    /// has been typed.  Esc (or a key which doesn't match any label) leaves
+    /// hint mode.
+    fn hint_key(&mut self, k: KeyEvent) -> Option<Result<Command>> {
+        let hints = self.hints.as_mut()?;
+        let target = match k.code {
+            // Control keys (e.g. Ctrl-C) cancel, rather than being typed
+            KeyCode::Char(c)
+                if !k.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                hints.typed.push(c);
+                let typed = &hints.typed;
+                let found = hints.labels.iter()
+                    .find(|(_, t)| t == typed)
+                    .map(|(i, _)| *i);
+                if found.is_none() &&
+                   hints.labels.iter().any(|(_, t)| t.starts_with(typed))
+                {
+                    self.draw();
+                    return None;
+                }
+                found
+            },
+            _ => None,
+        };
+        self.hints = None;
+        self.draw();
+        match self.doc.0[target?].line {
+            Line::NamedLink { url, .. } | Line::BareLink(url) =>
+                Some(Ok(Command::TryLoad(url.to_string()))),
+            _ => None,
+        }
     }
 
     fn key(&mut self, k: KeyEvent) -> Option<Result<Command>> {
+        if self.hints.is_some() {
+            return self.hint_key(k);
+        }
+        let yy = std::mem::take(&mut self.pending_y) &&
+                 k.code == KeyCode::Char('y');
+        let ctrl = k.modifiers.contains(KeyModifiers::CONTROL);
         match k.code {
-            KeyCode::Char('j') => { self.down(); None }
-            KeyCode::Char('k') => { self.up(); None }
-            KeyCode::Enter => {
-                match self.doc.0[self.ycursor].0 {
-                    Line::NamedLink { url, .. } |
-                    Line::BareLink(url) =>
-                        Some(Ok(Command::TryLoad(url.to_string()))),
-                    _ => None
-                }
+            KeyCode::Char('g') if ctrl =>
+                Some(Ok(Command::LineNumbers(self.line_numbers.next()))),
+            KeyCode::Char('u') if ctrl => Some(Ok(Command::ViewSource)),
+            // 'yy' copies the page's URL, and 'y' on a link copies its target
+            KeyCode::Char('y') if yy => Some(Ok(Command::Yank(None))),
+            KeyCode::Char('y') => {
+                self.pending_y = true;
+                self.cursor_link()
+                    .map(|url| Ok(Command::Yank(Some(url.to_string()))))
+            },
+            KeyCode::Char('f') => { self.start_hints(); None }
+            KeyCode::Char('R') => Some(Ok(Command::Reader(!self.reader))),
+            KeyCode::Char('[') =>
+                Some(Ok(Command::WrapWidth(self.step_wrap_width(false)))),
+            KeyCode::Char(']') =>
+                Some(Ok(Command::WrapWidth(self.step_wrap_width(true)))),
+            KeyCode::Char('j') | KeyCode::Down => { self.down(); None }
+            KeyCode::Char('k') | KeyCode::Up => { self.up(); None }
+            KeyCode::PageDown => { self.page_down(); None }
+            KeyCode::PageUp => { self.page_up(); None }
+            KeyCode::Home => { self.jump(false); None }
+            KeyCode::End => { self.jump(true); None }
+            KeyCode::Tab => { self.next_link(true); None }
+            KeyCode::BackTab => { self.next_link(false); None }
+            KeyCode::Enter => self.cursor_link()
+                .map(|url| Ok(Command::TryLoad(url.to_string()))),
+            KeyCode::Char('t') => self.cursor_link()
+                .map(|url| Ok(Command::TryLoadTab(url.to_string()))),
+            // 'o' follows the first URL written in the text of a line
+            KeyCode::Char('o') => self.cursor_block()
+                .and_then(|b| find_urls(render::text(b)).first().copied())
+                .map(|url| Ok(Command::TryLoad(url.to_string()))),
+            KeyCode::Char('s') => self.cursor_link()
+                .map(|url| Ok(Command::Download(url.to_string()))),
+            // 'p' pages a preformatted block, or previews a link's target
+            KeyCode::Char('p') => match self.cursor_block()? {
+                Line::Pre { text, .. } =>
+                    Some(Ok(Command::Pager(text.to_string()))),
+                _ => self.cursor_link()
+                    .map(|url| Ok(Command::Peek(url.to_string()))),
             },
             _ => None,
         }
@@ -229,13 +692,12 @@ impl View<'_> {
     pub fn event(&mut self, evt: Event) -> Option<Result<Command>> {
         match evt {
             Event::Key(event) => self.key(event),
-            Event::Mouse(event) => {
-                match event {
-                    MouseEvent::ScrollUp(..) => self.up(),
-                    MouseEvent::ScrollDown(..) => self.down(),
-                    _ => (),
-                };
-                None
+            Event::Mouse(event) => match event {
+                MouseEvent::ScrollUp(..) => { self.up(); None },
+                MouseEvent::ScrollDown(..) => { self.down(); None },
+                MouseEvent::Down(MouseButton::Left, _, row, _) =>
+                    self.click(row),
+                _ => None,
             },
             Event::Resize(w, h) => {
                 self.resize((w, h));
@@ -244,3 +706,548 @@ impl View<'_> {
         }
     }
 }
+
+/// Finds the rows of a scroll bar's thumb, which is as tall (relative to the
+/// bar) as the screen is (relative to the document), and reaches the bottom
+/// when the end of the document is on screen.  Returns None if the whole
+/// document fits on screen.
+fn thumb(len: usize, height: usize, scroll: usize) -> Option<Range<usize>> {
+    if len <= height || height == 0 {
+        return None;
+    }
+    let size = (height * height / len).max(1);
+    let start = if scroll + height >= len {
+        height - size
+    } else {
+        (scroll * height / len).min(height - size)
+    };
+    Some(start..start + size)
+}
+
+/// Builds labels for hint mode: single letters if there are few enough
+/// links, otherwise strings of equal length, so that no label is a prefix
+/// of another
+fn hint_labels(n: usize) -> Vec<String> {
+    let mut len = 1;
+    while 26usize.pow(len) < n {
+        len += 1;
+    }
+    (0..n).map(|mut i| {
+        let mut s = vec![b'a'; len as usize];
+        for c in s.iter_mut().rev() {
+            *c += (i % 26) as u8;
+            i /= 26;
+        }
+        String::from_utf8(s).unwrap()
+    }).collect()
+}
+
+#[test]
+fn test_cursor_wrap() {
+    let lines = (0..30).map(|_| Line::Text("line")).collect();
+    let doc = Document::new(lines);
+
+    let mut options = Config::default();
+    let mut v = View::new(&doc, (80, 12), &options);
+    v.cursor_up();
+    assert_eq!((v.ycursor, v.yscroll), (0, 0));
+    for _ in 0..40 {
+        v.cursor_down();
+    }
+    assert_eq!((v.ycursor, v.yscroll), (29, 20));
+
+    options.cursor_wrap = true;
+    let mut v = View::new(&doc, (80, 12), &options);
+    v.cursor_up();
+    assert_eq!((v.ycursor, v.yscroll), (29, 20));
+    v.cursor_down();
+    assert_eq!((v.ycursor, v.yscroll), (0, 0));
+}
+
+#[test]
+fn test_download_key() {
+    let doc = Document::new(vec![
+        Line::Text("text"),
+        Line::NamedLink { url: "image.png", name: "An image" },
+    ]);
+    let mut v = View::new(&doc, (80, 12), &Config::default());
+    let s = KeyEvent::from(KeyCode::Char('s'));
+    assert!(v.key(s).is_none());
+
+    v.cursor_down();
+    assert_eq!(v.key(s).unwrap().unwrap(),
+               Command::Download("image.png".to_owned()));
+    assert_eq!(v.key(KeyEvent::from(KeyCode::Enter)).unwrap().unwrap(),
+               Command::TryLoad("image.png".to_owned()));
+    assert_eq!(v.key(KeyEvent::from(KeyCode::Char('t'))).unwrap().unwrap(),
+               Command::TryLoadTab("image.png".to_owned()));
+    assert_eq!(v.key(KeyEvent::from(KeyCode::Char('p'))).unwrap().unwrap(),
+               Command::Peek("image.png".to_owned()));
+}
+
+#[test]
+fn test_restore_position() {
+    let text = "word ".repeat(30);
+    let doc = Document::new(vec![Line::Text(&text); 20]);
+    let options = Config::default();
+    let mut v = View::new(&doc, (40, 12), &options);
+    let len = v.doc.0.len();
+    v.set_position((len - 10, len - 1));
+    let pos = v.position();
+
+    // A saved position is clamped when the page wraps to fewer lines
+    let mut v = View::new(&doc, (200, 12), &options);
+    v.set_position(pos);
+    let (yscroll, ycursor) = v.position();
+    assert_eq!(ycursor, v.doc.0.len() - 1);
+    assert!(yscroll <= ycursor && ycursor < yscroll + v.size.1 as usize);
+}
+
+#[test]
+fn test_view_source_key() {
+    let doc = Document::new(vec![Line::Text("text")]);
+    let mut v = View::new(&doc, (80, 12), &Config::default());
+    let k = KeyEvent::new(KeyCode::Char('u'), KeyModifiers::CONTROL);
+    assert_eq!(v.key(k).unwrap().unwrap(), Command::ViewSource);
+    assert!(v.key(KeyEvent::from(KeyCode::Char('u'))).is_none());
+}
+
+#[test]
+fn test_pager_key() {
+    let doc = Document::new(vec![
+        Line::Text("some text which is long enough to wrap"),
+        Line::Pre { alt: None, text: "first\nblock which is very long" },
+        Line::Pre { alt: None, text: "second" },
+    ]);
+    let options = Config { truncate_pre: true, ..Default::default() };
+    let mut v = View::new(&doc, (14, 12), &options);
+    let p = KeyEvent::from(KeyCode::Char('p'));
+    assert!(v.key(p).is_none());
+
+    // The whole block is extracted from any of its lines, even if they
+    // were truncated on screen
+    while !v.doc.0[v.ycursor].truncated {
+        v.cursor_down();
+    }
+    assert_eq!(v.key(p).unwrap().unwrap(),
+               Command::Pager("first\nblock which is very long".to_owned()));
+
+    v.cursor_down();
+    assert_eq!(v.key(p).unwrap().unwrap(),
+               Command::Pager("second".to_owned()));
+}
+
+#[test]
+fn test_set_position() {
+    let lines = (0..30).map(|_| Line::Text("line")).collect();
+    let doc = Document::new(lines);
+    let mut v = View::new(&doc, (80, 12), &Config::default());
+    for _ in 0..15 {
+        v.cursor_down();
+    }
+    let pos = v.position();
+    assert_eq!(pos, (6, 15));
+
+    let mut v = View::new(&doc, (80, 12), &Config::default());
+    v.set_position(pos);
+    assert_eq!(v.position(), pos);
+
+    // Out-of-range positions are clamped, keeping the cursor on screen
+    v.set_position((0, 100));
+    assert_eq!(v.position(), (20, 29));
+}
+
+#[test]
+fn test_cursor_to_row() {
+    let mut lines = vec![Line::Text("text"); 20];
+    lines[12] = Line::BareLink("gemini://example.com");
+    let doc = Document::new(lines);
+    let mut v = View::new(&doc, (80, 12), &Config::default());
+    for _ in 0..12 {
+        v.cursor_down();
+    }
+    assert_eq!(v.position(), (3, 12));
+
+    // Rows are relative to the scroll position
+    assert!(v.cursor_to_row(9));
+    assert!(v.cursor_link().is_some());
+    assert!(v.cursor_to_row(0));
+    assert_eq!(v.position(), (3, 3));
+    assert!(v.cursor_link().is_none());
+
+    // The status and command rows aren't part of the document
+    assert!(!v.cursor_to_row(10));
+    assert!(!v.cursor_to_row(11));
+    assert_eq!(v.position(), (3, 3));
+}
+
+#[test]
+fn test_goto_line() {
+    let mut lines = vec![Line::Text("some text which is long enough to wrap")];
+    lines.extend((0..30).map(|_| Line::Text("line")));
+    let doc = Document::new(lines);
+    let mut v = View::new(&doc, (24, 12), &Config::default());
+    let w = v.doc.0.len() - 30;
+    assert!(w > 1);
+
+    v.goto_line(1);
+    assert_eq!(v.position(), (w, w));
+
+    // Near the end of the page, we scroll as far as we can
+    v.goto_line(29);
+    assert_eq!(v.position(), (v.doc.0.len() - 10, w + 28));
+}
+
+#[test]
+fn test_visited_style() {
+    let doc = Document::new(vec![
+        Line::NamedLink { url: "a.gmi", name: "A" },
+        Line::NamedLink { url: "b.gmi", name: "B" },
+    ]);
+    let mut v = View::new(&doc, (80, 12), &Config::default());
+    v.set_visited(std::iter::once("a.gmi").collect());
+    let theme = Theme::default();
+    assert_eq!(v.line_style(&doc.0[0]).foreground_color,
+               theme.visited.foreground_color);
+    assert_eq!(v.line_style(&doc.0[1]).foreground_color,
+               theme.link.foreground_color);
+}
+
+#[test]
+fn test_empty_document() {
+    let doc = Document::new(vec![]);
+    for &cursor_wrap in &[false, true] {
+        let options = Config { cursor_wrap, ..Default::default() };
+        let mut v = View::new(&doc, (80, 12), &options);
+        v.down();
+        v.up();
+        v.rewrap((40, 6));
+        assert_eq!(v.position(), (0, 0));
+        assert!(v.cursor_link().is_none());
+        assert!(v.key(KeyEvent::from(KeyCode::Char('p'))).is_none());
+        assert!(v.cursor_to_row(0));
+        assert!(!v.cursor_to_row(1));
+        v.goto_line(3);
+        assert_eq!(v.position(), (0, 0));
+    }
+}
+
+#[test]
+fn test_find() {
+    let mut lines = vec![Line::Text("line"); 30];
+    lines[5] = Line::NamedLink { url: "/search", name: "Search Engine" };
+    lines[25] = Line::Text("the end");
+    let doc = Document::new(lines);
+    let mut v = View::new(&doc, (80, 12), &Config::default());
+
+    assert!(v.find("search"));
+    assert_eq!(v.position(), (5, 5));
+    assert!(v.find("END"));
+    assert_eq!(v.position(), (20, 25));
+    assert!(!v.find("missing"));
+    assert_eq!(v.position(), (20, 25));
+}
+
+#[test]
+fn test_yank_key() {
+    let doc = Document::new(vec![
+        Line::Text("text"),
+        Line::NamedLink { url: "a.gmi", name: "A" },
+    ]);
+    let mut v = View::new(&doc, (80, 12), &Config::default());
+    let y = KeyEvent::from(KeyCode::Char('y'));
+    let j = KeyEvent::from(KeyCode::Char('j'));
+
+    // Off a link, a single 'y' waits for a second one
+    assert!(v.key(y).is_none());
+    assert_eq!(v.key(y).unwrap().unwrap(), Command::Yank(None));
+
+    // Another key in between cancels the 'yy'
+    assert!(v.key(y).is_none());
+    assert!(v.key(j).is_none());
+    assert_eq!(v.key(y).unwrap().unwrap(),
+               Command::Yank(Some("a.gmi".to_owned())));
+    assert_eq!(v.key(y).unwrap().unwrap(), Command::Yank(None));
+}
+
+#[test]
+fn test_hint_labels() {
+    assert_eq!(hint_labels(3), vec!["a", "b", "c"]);
+    let labels = hint_labels(28);
+    assert_eq!(&labels[..3], &["aa", "ab", "ac"]);
+    assert_eq!(&labels[26..], &["ba", "bb"]);
+}
+
+#[test]
+fn test_hints() {
+    let mut lines = vec![Line::Text("text"); 30];
+    lines[1] = Line::NamedLink { url: "a.gmi", name: "A" };
+    lines[3] = Line::BareLink("b.gmi");
+    lines[20] = Line::NamedLink { url: "offscreen.gmi", name: "C" };
+    let doc = Document::new(lines);
+    let mut v = View::new(&doc, (80, 12), &Config::default());
+    let key = |c| KeyEvent::from(KeyCode::Char(c));
+
+    // Only links on screen are labelled
+    v.key(key('f'));
+    let labels = &v.hints.as_ref().unwrap().labels;
+    assert_eq!(labels, &[(1, "a".to_owned()), (3, "b".to_owned())]);
+    assert_eq!(v.key(key('b')).unwrap().unwrap(),
+               Command::TryLoad("b.gmi".to_owned()));
+    assert!(v.hints.is_none());
+
+    // Escape or a key without a label cancels
+    v.key(key('f'));
+    assert!(v.key(KeyEvent::from(KeyCode::Esc)).is_none());
+    assert!(v.hints.is_none());
+    v.key(key('f'));
+    assert!(v.key(key('z')).is_none());
+    assert!(v.hints.is_none());
+}
+
+#[test]
+fn test_line_numbers() {
+    let mut lines = vec![Line::Text("short"); 8];
+    lines[0] = Line::Text("a line which is long enough to wrap");
+    let doc = Document::new(lines);
+    let mut v = View::new(&doc, (24, 12), &Config::default());
+    assert_eq!(v.gutter, 0);
+    assert_eq!(v.line_number(0), None);
+    let rows = v.doc.0.len();
+
+    // The gutter is as wide as the largest number plus a space, and takes
+    // columns away from the text (which wraps onto more rows here)
+    v.set_line_numbers(LineNumbers::Wrapped);
+    assert!(v.doc.0.len() > 9);
+    assert_eq!(v.gutter, 3);
+    assert_eq!(v.size.0, 24 - 4 - 3);
+    assert_eq!(v.term, (24, 12));
+    assert_eq!(v.line_number(1), Some(2));
+
+    // Source numbers are only drawn on the first row of each line
+    v.set_line_numbers(LineNumbers::Source);
+    assert_eq!(v.gutter, 2);
+    assert_eq!(v.line_number(0), Some(1));
+    assert_eq!(v.line_number(1), None);
+    assert_eq!(v.line_number(v.doc.0.len() - 1), Some(8));
+
+    v.set_line_numbers(LineNumbers::Off);
+    assert_eq!((v.gutter, v.doc.0.len()), (0, rows));
+}
+
+#[test]
+fn test_tiny_terminal() {
+    let doc = Document::new(vec![
+        Line::H1("Title"),
+        Line::NamedLink { url: "a.gmi", name: "a link" },
+        Line::Quote("quoted"),
+        Line::Text("text"),
+    ]);
+    for size in &[(0, 0), (3, 1), (5, 2), (8, 3)] {
+        for n in &[LineNumbers::Off, LineNumbers::Wrapped] {
+            let options = Config { line_numbers: *n, reader_mode: true,
+                                   ..Default::default() };
+            let v = View::new(&doc, *size, &options);
+            assert!(v.size.0 >= 1);
+            assert_eq!(v.size.1, size.1.saturating_sub(2));
+            assert_eq!(v.margin, 0);
+        }
+    }
+}
+
+#[test]
+fn test_reader_mode() {
+    let doc = Document::new(vec![Line::Text("text"); 20]);
+    let options = Config { reader_mode: true, reader_width: 60,
+                           ..Default::default() };
+    let mut v = View::new(&doc, (120, 12), &options);
+    assert_eq!((v.size, v.margin), ((60, 10), 28));
+    assert_eq!(v.key(KeyEvent::from(KeyCode::Char('R'))).unwrap().unwrap(),
+               Command::Reader(false));
+
+    // Leaving reader mode uses the whole width again
+    v.reader = false;
+    v.rewrap(v.term);
+    assert_eq!((v.size, v.margin), ((116, 10), 0));
+
+    // Terminals narrower than the column aren't given a margin
+    let mut v = View::new(&doc, (40, 12), &options);
+    assert_eq!((v.size, v.margin), ((36, 10), 0));
+
+    // With a gutter, the text and its line numbers are centered together
+    let options = Config { line_numbers: LineNumbers::Wrapped, ..options };
+    v = View::new(&doc, (120, 12), &options);
+    assert_eq!((v.size, v.gutter, v.margin), ((60, 10), 3, 26));
+}
+
+#[test]
+fn test_wrap_width() {
+    let (text, heading) = ("word ".repeat(40), "heading ".repeat(20));
+    let doc = Document::new(vec![Line::Text(&text), Line::H1(&heading)]);
+    let options = Config { wrap_width: 72, ..Default::default() };
+    let mut v = View::new(&doc, (200, 12), &options);
+    assert_eq!((v.size, v.margin), ((72, 10), 0));
+    assert!(v.doc.0.len() > 2);
+    assert!(v.doc.0.iter()
+        .all(|w| render::text(&w.line).trim_end().chars().count() <= 72));
+
+    // Changing it at runtime rewraps, and 0 removes the limit
+    v.set_wrap_width(0);
+    assert_eq!(v.size, (196, 10));
+    v.set_wrap_width(100);
+    assert_eq!(v.size, (100, 10));
+
+    // The column can be centered, and reader mode can narrow it further
+    let options = Config { wrap_centered: true, ..options };
+    let v = View::new(&doc, (200, 12), &options);
+    assert_eq!((v.size, v.margin), ((72, 10), 62));
+    let options = Config { reader_mode: true, reader_width: 60,
+                           wrap_centered: false, ..options };
+    let v = View::new(&doc, (200, 12), &options);
+    assert_eq!((v.size, v.margin), ((60, 10), 68));
+
+    // '[' and ']' step the width, stopping at the minimum and the terminal
+    let mut v = View::new(&doc, (80, 12), &Config::default());
+    let key = |v: &mut View, c| match v.key(KeyEvent::from(KeyCode::Char(c))) {
+        Some(Ok(Command::WrapWidth(n))) => { v.set_wrap_width(n); n },
+        r => panic!("Unexpected result {:?}", r),
+    };
+    assert_eq!(key(&mut v, '['), 72);
+    assert_eq!(v.size.0, 72);
+    assert_eq!(key(&mut v, ']'), 0);
+    assert_eq!(v.size.0, 76);
+    v.set_wrap_width(22);
+    assert_eq!(key(&mut v, '['), MIN_WRAP_WIDTH);
+    assert_eq!(key(&mut v, '['), MIN_WRAP_WIDTH);
+    assert_eq!(key(&mut v, ']'), 24);
+}
+
+#[test]
+fn test_scrollbar() {
+    assert_eq!(thumb(10, 10, 0), None);
+    assert_eq!(thumb(40, 10, 0), Some(0..2));
+    assert_eq!(thumb(40, 10, 12), Some(3..5));
+    assert_eq!(thumb(40, 10, 30), Some(8..10));
+
+    // The thumb is always at least one row tall
+    assert_eq!(thumb(1000, 10, 0), Some(0..1));
+    assert_eq!(thumb(1000, 10, 500), Some(5..6));
+    assert_eq!(thumb(1000, 10, 990), Some(9..10));
+
+    let doc = Document::new(vec![Line::Text("text"); 40]);
+    let mut v = View::new(&doc, (80, 12), &Config::default());
+    assert_eq!(v.thumb(), Some(0..2));
+    v.set_position((30, 30));
+    assert_eq!(v.thumb(), Some(8..10));
+    v.rewrap((80, 42));
+    assert_eq!(v.thumb(), None);
+
+    let options = Config { scrollbar: false, ..Default::default() };
+    assert_eq!(View::new(&doc, (80, 12), &options).thumb(), None);
+}
+
+#[test]
+fn test_ascii() {
+    let doc = Document::new(vec![
+        Line::List("item"),
+        Line::NamedLink { url: "a.gmi", name: "a link" },
+        Line::BareLink("gemini://example.com/"),
+    ]);
+    let draw = |options: &Config| {
+        let v = View::new(&doc, (80, 12), options);
+        let mut out = Vec::new();
+        for i in 0..3 {
+            v.draw_line(&mut out, i);
+        }
+        String::from_utf8(out).unwrap()
+    };
+
+    let s = draw(&Config::default());
+    assert!(s.contains("• ") && s.contains("→ "));
+
+    let s = draw(&Config { ascii: true, ..Default::default() });
+    assert!(s.contains("* ") && s.contains("-> "));
+    assert!(s.is_ascii());
+}
+
+#[test]
+fn test_navigation_keys() {
+    let doc = Document::new(vec![Line::Text("line"); 30]);
+    let mut v = View::new(&doc, (80, 12), &Config::default());
+    let mut key = |c| { v.key(KeyEvent::from(c)); v.position() };
+    assert_eq!(key(KeyCode::Down), (0, 1));
+    assert_eq!(key(KeyCode::PageDown), (10, 11));
+    assert_eq!(key(KeyCode::PageDown), (20, 21));
+    assert_eq!(key(KeyCode::PageDown), (20, 29));
+    assert_eq!(key(KeyCode::Up), (20, 28));
+    assert_eq!(key(KeyCode::PageUp), (10, 18));
+    assert_eq!(key(KeyCode::Home), (0, 0));
+    assert_eq!(key(KeyCode::PageUp), (0, 0));
+    assert_eq!(key(KeyCode::End), (20, 29));
+}
+
+#[test]
+fn test_next_link() {
+    let mut lines = vec![Line::Text("text"); 30];
+    lines[3] = Line::NamedLink { url: "a.gmi",
+                                 name: "a link which wraps onto more rows" };
+    lines[25] = Line::BareLink("gemini://example.com/b.gmi");
+    let doc = Document::new(lines);
+    let mut v = View::new(&doc, (24, 12), &Config::default());
+    let mut key = |c| { v.key(KeyEvent::from(c)); v.position() };
+
+    // Wrapped rows of the same link are skipped, and the cursor is scrolled
+    // into view
+    assert_eq!(key(KeyCode::Tab), (0, 3));
+    let pos = key(KeyCode::Tab);
+    assert_eq!(v.cursor_link(), Some("gemini://example.com/b.gmi"));
+    assert!(pos.0 <= pos.1 && pos.1 < pos.0 + 10);
+    let mut key = |c| { v.key(KeyEvent::from(c)); v.position() };
+    assert_eq!(key(KeyCode::Tab).1, 3);
+    assert_eq!(key(KeyCode::BackTab), pos);
+
+    // Documents without links leave the cursor alone
+    let doc = Document::new(vec![Line::Text("text"); 3]);
+    let mut v = View::new(&doc, (24, 12), &Config::default());
+    v.key(KeyEvent::from(KeyCode::Tab));
+    assert_eq!(v.position(), (0, 0));
+}
+
+#[test]
+fn test_open_inline_url() {
+    let doc = Document::new(vec![
+        Line::Text("plain text"),
+        Line::Text("see gemini://example.com/a.gmi or https://example.com."),
+    ]);
+    let mut v = View::new(&doc, (24, 12), &Config::default());
+    let o = KeyEvent::from(KeyCode::Char('o'));
+    assert!(v.key(o).is_none());
+
+    // Any wrapped row of the line finds the URL
+    v.goto_line(1);
+    v.cursor_down();
+    assert_eq!(v.doc.0[v.ycursor].block, 1);
+    assert_eq!(v.key(o).unwrap().unwrap(),
+               Command::TryLoad("gemini://example.com/a.gmi".to_owned()));
+}
+
+#[test]
+fn test_long_bare_link() {
+    let url = format!("gemini://example.com/{}end.gmi", "x/".repeat(60));
+    let doc = Document::new(vec![Line::BareLink(&url)]);
+    let mut v = View::new(&doc, (44, 12), &Config::default());
+    assert_eq!(v.size.0, 40);
+
+    let mut out = Vec::new();
+    v.draw_line(&mut out, 0);
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("gemini://example.co…/x/"), "{}", out);
+    assert!(out.contains("x/x/end.gmi"), "{}", out);
+    assert!(!out.contains(&url));
+
+    // Following the link still uses the whole URL
+    match v.key(KeyEvent::from(KeyCode::Enter)) {
+        Some(Ok(Command::TryLoad(s))) => assert_eq!(s, url),
+        r => panic!("Unexpected result {:?}", r),
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use silo::document::Document;
+use silo::protocol::Line;
+
+use crate::source::Source;
+
+/// Set of URLs which have been loaded, used to mark links which have
+/// already been followed.  It's optionally persisted in the database, so
+/// that it's remembered across sessions.
+pub struct Visited {
+    urls: HashSet<String>,
+    db: Option<sled::Tree>,
+}
+
+impl Visited {
+    /// Builds an empty set, or loads it from the database if one is given
+    pub fn new(db: Option<&sled::Db>) -> Result<Visited> {
+        let db = db.map(|db| db.open_tree("visited")).transpose()?;
+        let mut urls = HashSet::new();
+        if let Some(db) = &db {
+            for k in db.iter().keys() {
+                urls.insert(String::from_utf8_lossy(&k?).into_owned());
+            }
+        }
+        Ok(Visited { urls, db })
+    }
+
+    pub fn insert(&mut self, url: &url::Url) -> Result<()> {
+        let k = key(url);
+        if let Some(db) = &self.db {
+            db.insert(k.as_bytes(), &[])?;
+        }
+        self.urls.insert(k);
+        Ok(())
+    }
+
+    pub fn contains(&self, url: &url::Url) -> bool {
+        self.urls.contains(&key(url))
+    }
+
+    /// Returns the link targets in a document which lead to visited URLs.
+    /// They're resolved the same way as when following the link, so that
+    /// relative links match.
+    pub fn targets<'a>(&self, doc: &Document<'a>, source: &Source)
+        -> HashSet<&'a str>
+    {
+        doc.0.iter()
+            .filter_map(|line| match *line {
+                Line::NamedLink { url, .. } | Line::BareLink(url) => Some(url),
+                _ => None,
+            })
+            .filter(|t| source.resolve(t).map(|u| self.contains(&u))
+                                         .unwrap_or(false))
+            .collect()
+    }
+}
+
+/// Fragments select part of a page, so they're ignored when comparing URLs
+fn key(url: &url::Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    url.into_string()
+}
+
+#[test]
+fn test_targets() {
+    let doc = Document::new(vec![
+        Line::NamedLink { url: "b.gmi", name: "relative" },
+        Line::BareLink("gemini://example.com/a/c.gmi#section"),
+        Line::NamedLink { url: "d.gmi", name: "unvisited" },
+        Line::Text("b.gmi"),
+    ]);
+    let base = url::Url::parse("gemini://example.com/a/index.gmi").unwrap();
+    let source = Source::Network(base.clone());
+
+    let mut v = Visited::new(None).unwrap();
+    assert!(v.targets(&doc, &source).is_empty());
+
+    // Following a link and coming back marks it as visited
+    v.insert(&source.resolve("b.gmi").unwrap()).unwrap();
+    v.insert(&base.join("c.gmi").unwrap()).unwrap();
+    let t = v.targets(&doc, &source);
+    assert_eq!(t.len(), 2);
+    assert!(t.contains("b.gmi"));
+    assert!(t.contains("gemini://example.com/a/c.gmi#section"));
+
+    // Relative links are resolved against the page they're on
+    let other = url::Url::parse("gemini://example.com/other/").unwrap();
+    assert!(v.targets(&doc, &Source::Network(other)).len() == 1);
+}
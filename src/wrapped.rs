@@ -1,15 +1,54 @@
 use std::borrow::Cow;
 use silo::protocol::Line;
 use silo::document::Document;
+use silo::render;
 
 // WrappedDocument encodes a set of screen-wrapped lines, each with a flag
 // indicating whether it's the first line in its block.  This matters for
 // rendering, e.g. a list shows "• " on the first line of each item.
 #[derive(Debug, Eq, PartialEq)]
-pub struct WrappedDocument<'a>(pub Vec<(Line<'a>, bool)>);
+pub struct WrappedDocument<'a>(pub Vec<WrappedLine<'a>>);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct WrappedLine<'a> {
+    pub line: Line<'a>,
+    pub first: bool, // Is this the first line in its block?
+    pub truncated: bool, // Was the line cut short to fit the width?
+    pub indent: usize, // Extra indentation drawn after the prefix
+    pub rule: usize, // Width of a heading underline drawn instead of text
+    pub block: usize, // Index of the document line which this came from
+}
+
+impl<'a> WrappedLine<'a> {
+    fn new(line: Line<'a>, first: bool) -> Self {
+        WrappedLine { line, first, truncated: false, indent: 0, rule: 0,
+                      block: 0 }
+    }
+}
+
+/// Options which change how a document is wrapped
+#[derive(Copy, Clone, Debug, Default)]
+pub struct WrapOptions {
+    /// Cut preformatted lines which are too long at the wrap width, rather
+    /// than letting them overflow (they're never word-wrapped)
+    pub truncate_pre: bool,
+
+    /// Number of heading levels (starting from H1) which are followed by an
+    /// underline row, drawn with '=' for H1 and '-' for H2
+    pub underline_headings: u8,
+
+    /// Draw a run of consecutive blank text lines as a single blank line.
+    /// The skipped lines produce no wrapped lines at all.
+    pub collapse_blank_lines: bool,
+}
+
+/// Iterator of flags which are only set for the first line of a block
+fn firsts() -> impl Iterator<Item=bool> {
+    std::iter::once(true).chain(std::iter::repeat(false))
+}
 
 fn wrap<'a, F>(s: &'a str, width: usize, mut f: F)
-    -> Box<dyn Iterator<Item=(Line<'a>, bool)> + 'a>
+    -> Box<dyn Iterator<Item=WrappedLine<'a>> + 'a>
     where F: 'a + FnMut(&'a str) -> Line<'a>
 {
     let default = f("");
@@ -23,46 +62,375 @@ fn wrap<'a, F>(s: &'a str, width: usize, mut f: F)
                 panic!("Got unexpected owned Pre line");
             })
         .map(f)
-        .zip(std::iter::once(true).chain(std::iter::repeat(false)))
+        .zip(firsts())
+        .map(|(line, first)| WrappedLine::new(line, first))
         .peekable();
 
     if t.peek().is_some() {
         Box::new(t)
     } else {
-        Box::new(std::iter::once((default, true)))
+        Box::new(std::iter::once(WrappedLine::new(default, true)))
     }
 }
 
-fn line_wrap<'a>(line: &'a Line, width: usize)
-    -> Box<dyn Iterator<Item=(Line<'a>, bool)> + 'a>
+/// If a string is too long for the given width, returns the prefix which
+/// fits alongside a one-character continuation marker
+fn truncate(s: &str, width: usize) -> Option<&str> {
+    s.char_indices()
+        .nth(width)
+        .map(|_| &s[..s.char_indices().nth(width.saturating_sub(1))
+                          .unwrap().0])
+}
+
+fn line_wrap<'a>(line: &'a Line, width: usize, opts: WrapOptions)
+    -> Box<dyn Iterator<Item=WrappedLine<'a>> + 'a>
 {
     use Line::*;
+    // Width left over after a prefix, which is at least one column
+    let inner = |prefix: usize| width.saturating_sub(prefix).max(1);
     match line {
         Text(t) => wrap(t, width, Text),
-        BareLink(url) => Box::new(std::iter::once((BareLink(url), true))),
-        NamedLink { name, url } => wrap(name, width - 3, move |s|
+        BareLink(url) =>
+            Box::new(std::iter::once(WrappedLine::new(BareLink(url), true))),
+        // A link whose name just repeats its URL is drawn as a bare link,
+        // rather than as a name which happens to look like a URL
+        NamedLink { name, url } if name.trim() == *url =>
+            Box::new(std::iter::once(WrappedLine::new(BareLink(url), true))),
+        NamedLink { name, url } => wrap(name, inner(3), move |s|
             NamedLink { url, name: s }),
+        // Blocks from CRLF documents keep their '\r's, which aren't drawn
         Pre { text, alt } => Box::new(text.split('\n')
-            .map(move |s| Pre { text: s, alt: *alt })
-            .zip(std::iter::once(true).chain(std::iter::repeat(false)))),
-        H1(t) => wrap(t, width - 2, H1), // "# "
-        H2(t) => wrap(t, width - 3, H2), // "## "
-        H3(t) => wrap(t, width - 4, H3), // "### "
-        List(t) => wrap(t, width - 2, List), // "* "
-        Quote(t) => wrap(t, width - 2, Quote), // "> "
+            .map(|s| s.strip_suffix('\r').unwrap_or(s))
+            .zip(firsts())
+            .map(move |(s, first)| {
+                match truncate(s, width).filter(|_| opts.truncate_pre) {
+                    Some(t) => WrappedLine { truncated: true,
+                        ..WrappedLine::new(Pre { text: t, alt: *alt }, first)
+                    },
+                    None => WrappedLine::new(Pre { text: s, alt: *alt },
+                                             first),
+                }
+            })),
+        H1(t) => underline(wrap(t, inner(2), H1), // "# "
+                           opts.underline_headings >= 1),
+        H2(t) => underline(wrap(t, inner(3), H2), // "## "
+                           opts.underline_headings >= 2),
+        H3(t) => wrap(t, inner(4), H3), // "### "
+        List(t) => {
+            // Indentation after the bullet is drawn on every wrapped line,
+            // so that nested items stay nested when they wrap
+            let body = t.trim_start();
+            let indent = t.len() - body.len();
+            Box::new(wrap(body, width.saturating_sub(2 + indent).max(1), List)
+                .map(move |w| WrappedLine { indent, ..w }))
+        },
+        Quote(t) => wrap(t, inner(2), Quote), // "> "
+    }
+}
+
+/// Optionally follows a wrapped heading with an underline row which is as
+/// wide as its longest line (including the prefix)
+fn underline<'a>(rows: Box<dyn Iterator<Item=WrappedLine<'a>> + 'a>,
+                 enabled: bool)
+    -> Box<dyn Iterator<Item=WrappedLine<'a>> + 'a>
+{
+    if !enabled {
+        return rows;
+    }
+    let mut rows: Vec<_> = rows.collect();
+    let rule = rows.iter()
+        .map(|w| prefix(w).chars().count() + text(w).chars().count())
+        .max()
+        .unwrap_or(0);
+    let line = rows[0].line;
+    rows.push(WrappedLine { rule, ..WrappedLine::new(line, false) });
+    Box::new(rows.into_iter())
+}
+
+/// Returns the decoration drawn before a wrapped line.  Most prefixes are
+/// only drawn on the first line of a block, with continuation lines indented
+/// to match.
+pub fn prefix(w: &WrappedLine) -> Cow<'static, str> {
+    indented(w, render::prefix(&w.line, w.first))
+}
+
+/// Returns the decoration drawn before a wrapped line using only ASCII, for
+/// terminals (or fonts) which can't draw the bullet and arrow.  The arrow
+/// becomes `-> `, which still fits in the room that wrapping leaves for a
+/// link's prefix.
+pub fn ascii_prefix(w: &WrappedLine) -> Cow<'static, str> {
+    let p = match (w.line, w.first) {
+        (Line::List(_), true) => "* ",
+        (Line::NamedLink { .. }, true) | (Line::BareLink(_), _) => "-> ",
+        (Line::NamedLink { .. }, false) => "   ",
+        (line, first) => render::prefix(&line, first),
+    };
+    indented(w, p)
+}
+
+fn indented(w: &WrappedLine, p: &'static str) -> Cow<'static, str> {
+    // Underlines start at the left edge, beneath the heading's prefix
+    if w.rule > 0 {
+        return Cow::Borrowed("");
+    }
+    if w.indent == 0 {
+        Cow::Borrowed(p)
+    } else {
+        Cow::Owned(format!("{}{}", p, " ".repeat(w.indent)))
+    }
+}
+
+/// Returns the text displayed for a line, i.e. everything but the prefix
+pub fn text<'a>(w: &WrappedLine<'a>) -> Cow<'a, str> {
+    if w.rule > 0 {
+        let c = if let Line::H1(_) = w.line { "=" } else { "-" };
+        Cow::Owned(c.repeat(w.rule))
+    } else {
+        Cow::Borrowed(render::text(&w.line))
+    }
+}
+
+/// Percent-decodes a URL for display.  The URL is returned as-is if it
+/// doesn't decode to valid UTF-8, or if it would decode to control
+/// characters (which could be interpreted by the terminal).
+pub fn decode_url(url: &str) -> Cow<'_, str> {
+    match percent_encoding::percent_decode_str(url).decode_utf8() {
+        Ok(s) if !s.chars().any(char::is_control) => s,
+        _ => Cow::Borrowed(url),
+    }
+}
+
+/// Shortens text which is wider than `width` by replacing its middle with
+/// an ellipsis (`...` in ASCII mode).  This is used for bare links, which
+/// aren't wrapped, so that both the host and the end of the path are still
+/// visible.
+pub fn shorten(s: Cow<'_, str>, width: usize, ascii: bool) -> Cow<'_, str> {
+    let len = s.chars().count();
+    if len <= width {
+        return s;
     }
+    let ellipsis = if ascii { "..." } else { "…" };
+    let keep = match width.checked_sub(ellipsis.chars().count()) {
+        Some(k) => k,
+        None => return Cow::Owned(s.chars().take(width).collect()),
+    };
+    let head: String = s.chars().take(keep - keep / 2).collect();
+    let tail: String = s.chars().skip(len - keep / 2).collect();
+    Cow::Owned(head + ellipsis + &tail)
+}
+
+/// Replaces control characters (other than tabs) with visible stand-ins, so
+/// that text from a page can't send escape sequences to the terminal.  C0
+/// controls and DEL are drawn as their Unicode control pictures (e.g. `␛`),
+/// and other controls as U+FFFD; in ASCII mode, all of them become `?`.
+pub fn sanitize(s: Cow<'_, str>, ascii: bool) -> Cow<'_, str> {
+    if !s.chars().any(|c| c.is_control() && c != '\t') {
+        return s;
+    }
+    Cow::Owned(s.chars()
+        .map(|c| match c {
+            '\t' => c,
+            c if !c.is_control() => c,
+            _ if ascii => '?',
+            '\0'..='\x1f' => char::from_u32(0x2400 + c as u32).unwrap(),
+            '\x7f' => '\u{2421}',
+            _ => '\u{FFFD}',
+        })
+        .collect())
 }
 
-pub fn word_wrap<'a>(d: &'a Document, width: usize) -> WrappedDocument<'a> {
-    WrappedDocument(d.0.iter()
-        .map(|line| line_wrap(line, width))
-        .flatten()
+pub fn word_wrap<'a>(d: &'a Document, width: usize, opts: WrapOptions)
+    -> WrappedDocument<'a>
+{
+    let mut prev_blank = false;
+    with_blank(d.0.iter()
+        .enumerate()
+        .filter(|(_, line)| {
+            let blank = matches!(line, Line::Text(t) if t.trim().is_empty());
+            let skip = blank && prev_blank && opts.collapse_blank_lines;
+            prev_blank = blank;
+            !skip
+        })
+        .flat_map(|(block, line)| line_wrap(line, width, opts)
+            .map(move |w| WrappedLine { block, ..w }))
         .collect()
     )
 }
 
 pub fn dummy_wrap<'a>(d: &'a Document) -> WrappedDocument<'a> {
-    WrappedDocument(d.0.iter()
-        .map(|line| (*line, true))
+    with_blank(d.0.iter()
+        .enumerate()
+        .map(|(block, line)| WrappedLine { block,
+                                           ..WrappedLine::new(*line, true) })
         .collect())
 }
+
+/// Builds a wrapped document, adding a blank line if it would otherwise be
+/// empty, so that there's always a line for the cursor to be on
+fn with_blank(mut lines: Vec<WrappedLine>) -> WrappedDocument {
+    if lines.is_empty() {
+        lines.push(WrappedLine::new(Line::Text(""), true));
+    }
+    WrappedDocument(lines)
+}
+
+#[test]
+fn test_truncate_pre() {
+    let doc = Document::new(vec![
+        Line::Pre { alt: None, text: "short\n0123456789abc\n0123456789" },
+        Line::Text("some text which is long enough to wrap"),
+    ]);
+
+    let w = word_wrap(&doc, 10, WrapOptions::default());
+    assert_eq!(w.0[1].line, Line::Pre { alt: None, text: "0123456789abc" });
+    assert!(w.0.iter().all(|w| !w.truncated));
+
+    let opts = WrapOptions { truncate_pre: true, ..Default::default() };
+    let w = word_wrap(&doc, 10, opts);
+    let pre: Vec<_> = w.0.iter()
+        .filter_map(|w| match w.line {
+            Line::Pre { text, .. } => Some((text, w.truncated)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(pre, vec![("short", false), ("012345678", true),
+                         ("0123456789", false)]);
+
+    // Text is still word-wrapped as usual
+    assert_eq!(w.0[3].line, Line::Text("some text"));
+
+    let doc = Document::new(vec![Line::Pre { alt: None, text: "a\r\nb" }]);
+    let w = word_wrap(&doc, 10, WrapOptions::default());
+    assert_eq!(w.0[0].line, Line::Pre { alt: None, text: "a" });
+    assert_eq!(w.0[1].line, Line::Pre { alt: None, text: "b" });
+}
+
+#[test]
+fn test_list_indent() {
+    let doc = Document::new(vec![
+        Line::List("item"),
+        Line::List("  indented item which wraps"),
+    ]);
+    let w = word_wrap(&doc, 20, WrapOptions::default());
+    let lines: Vec<String> = w.0.iter()
+        .map(|w| format!("{}{}", prefix(w), text(w)))
+        .collect();
+    assert_eq!(lines, vec!["• item", "•   indented item", "    which wraps"]);
+}
+
+#[test]
+fn test_underline_headings() {
+    let doc = Document::new(vec![
+        Line::H1("Title"),
+        Line::H2("A subtitle which wraps"),
+        Line::H3("Small"),
+    ]);
+    let render = |levels| {
+        let opts = WrapOptions { underline_headings: levels,
+                                 ..Default::default() };
+        word_wrap(&doc, 16, opts).0.iter()
+            .map(|w| format!("{}{}", prefix(w), text(w)))
+            .collect::<Vec<String>>()
+    };
+    assert_eq!(render(0), vec!["# Title", "## A subtitle", "   which wraps",
+                               "### Small"]);
+    assert_eq!(render(1), vec!["# Title", "=======", "## A subtitle",
+                               "   which wraps", "### Small"]);
+    assert_eq!(render(2), vec!["# Title", "=======", "## A subtitle",
+                               "   which wraps", "--------------",
+                               "### Small"]);
+}
+
+#[test]
+fn test_collapse_blank_lines() {
+    let doc = Document::new(vec![
+        Line::Text("a"),
+        Line::Text(""),
+        Line::Text("  "),
+        Line::Text(""),
+        Line::Pre { alt: None, text: "" },
+        Line::Text(""),
+        Line::Text("b"),
+    ]);
+    let w = word_wrap(&doc, 20, WrapOptions::default());
+    assert_eq!(w.0.len(), 7);
+
+    let opts = WrapOptions { collapse_blank_lines: true, ..Default::default() };
+    let w = word_wrap(&doc, 20, opts);
+    let blocks: Vec<usize> = w.0.iter().map(|w| w.block).collect();
+    assert_eq!(blocks, vec![0, 1, 4, 5, 6]);
+}
+
+#[test]
+fn test_link_named_by_url() {
+    let url = "gemini://example.com/a/long/path";
+    let doc = Document::new(vec![
+        Line::NamedLink { url, name: url },
+        Line::NamedLink { url, name: "Example" },
+    ]);
+    let w = word_wrap(&doc, 20, WrapOptions::default());
+    assert_eq!(w.0.len(), 2);
+    assert_eq!(w.0[0].line, Line::BareLink(url));
+    assert_eq!(format!("{}{}", prefix(&w.0[0]), text(&w.0[0])),
+               format!("→ {}", url));
+    assert_eq!(w.0[1].line, Line::NamedLink { url, name: "Example" });
+}
+
+#[test]
+fn test_wrap_empty() {
+    let doc = Document::new(vec![]);
+    let blank = vec![WrappedLine::new(Line::Text(""), true)];
+    assert_eq!(word_wrap(&doc, 20, WrapOptions::default()).0, blank);
+    assert_eq!(dummy_wrap(&doc).0, blank);
+}
+
+#[test]
+fn test_shorten() {
+    let url = "gemini://example.com/a/very/long/path/to/a/page.gmi";
+    let s = |width, ascii| shorten(Cow::Borrowed(url), width, ascii);
+    assert_eq!(s(80, false), url);
+    assert_eq!(s(url.len(), false), url);
+    assert_eq!(s(21, false), "gemini://e…a/page.gmi");
+    assert_eq!(s(21, true), "gemini://.../page.gmi");
+    assert_eq!(s(2, true), "ge");
+}
+
+#[test]
+fn test_sanitize() {
+    let s = |t: &'static str, ascii| sanitize(Cow::Borrowed(t), ascii);
+    assert!(matches!(s("plain\ttext", false), Cow::Borrowed("plain\ttext")));
+    assert_eq!(s("a\0b\x1b[2Jc\x7f\u{9b}", false), "a␀b␛[2Jc␡\u{FFFD}");
+    assert_eq!(s("a\0b\x1b[2J\r", true), "a?b?[2J?");
+}
+
+#[test]
+fn test_decode_url() {
+    assert_eq!(decode_url("gemini://example.com/caf%C3%A9%20menu"),
+               "gemini://example.com/café menu");
+    assert_eq!(decode_url("gemini://example.com/plain"),
+               "gemini://example.com/plain");
+
+    // Invalid UTF-8 and control characters leave the URL encoded
+    assert_eq!(decode_url("gemini://example.com/%FF"),
+               "gemini://example.com/%FF");
+    assert_eq!(decode_url("gemini://example.com/%1B[2J"),
+               "gemini://example.com/%1B[2J");
+}
+
+#[test]
+fn test_ascii_prefix() {
+    let doc = Document::new(vec![
+        Line::List("  item which wraps"),
+        Line::NamedLink { url: "a.gmi", name: "link which wraps" },
+        Line::BareLink("b.gmi"),
+    ]);
+    let w = word_wrap(&doc, 14, WrapOptions::default());
+    let lines = |f: fn(&WrappedLine) -> Cow<'static, str>| w.0.iter()
+        .map(|w| format!("{}{}", f(w), text(w)))
+        .collect::<Vec<_>>();
+    assert_eq!(lines(prefix), vec!["•   item which", "    wraps",
+                                   "→ link which", "  wraps", "→ b.gmi"]);
+    assert_eq!(lines(ascii_prefix), vec!["*   item which", "    wraps",
+                                         "-> link which", "   wraps",
+                                         "-> b.gmi"]);
+}
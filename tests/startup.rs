@@ -0,0 +1,81 @@
+// Runs the browser in a pseudo-terminal (using util-linux's `script`), to
+// check what it does with its command-line flags once it's on screen.
+#![cfg(target_os = "linux")]
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Returns the URLs requested so far, from a transcript file
+fn requested(transcript: &Path) -> Vec<String> {
+    std::fs::read_to_string(transcript).unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split('\t').nth(1))
+        .map(str::to_owned)
+        .collect()
+}
+
+#[test]
+fn test_startup_command() {
+    let dir = std::env::temp_dir()
+        .join(format!("titan-startup-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("config/titan")).unwrap();
+    let page = |name: &str, text: &str| {
+        let path = dir.join(name);
+        std::fs::write(&path, text).unwrap();
+        url::Url::from_file_path(&path).unwrap()
+    };
+    let home = page("home.gmi", "# Home\n");
+    let next = page("next.gmi", "# Next\n");
+    std::fs::write(dir.join("config/titan/titan.conf"),
+                   format!("home = {}\n", home)).unwrap();
+    let transcript = dir.join("transcript.log");
+
+    // The pseudo-terminal starts out with no size, so it's given one first
+    let cmd = format!("stty rows 24 cols 80; exec {} --command 'g {}'",
+                      env!("CARGO_BIN_EXE_titan"), next);
+    let child = Command::new("script")
+        .args(["-qec", &cmd, "/dev/null"])
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("XDG_DATA_HOME", dir.join("data"))
+        .env("TITAN_TRANSCRIPT", &transcript)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    let mut child = match child {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Skipping test, since script can't be run: {}", e);
+            return;
+        },
+    };
+
+    // Wait for the home page and then the command's page to load, then quit
+    // with Ctrl-C
+    let start = Instant::now();
+    while requested(&transcript).len() < 2 &&
+          start.elapsed() < Duration::from_secs(20)
+    {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let urls = requested(&transcript);
+    let _ = child.stdin.as_mut().unwrap().write_all(b"\x03");
+    let start = Instant::now();
+    let exited = loop {
+        match child.try_wait().unwrap() {
+            Some(status) => break status.success(),
+            None if start.elapsed() > Duration::from_secs(10) => {
+                let _ = child.kill();
+                break false;
+            },
+            None => std::thread::sleep(Duration::from_millis(50)),
+        }
+    };
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(urls, vec![home.to_string(), next.to_string()]);
+    assert!(exited);
+}